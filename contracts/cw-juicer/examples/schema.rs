@@ -1,26 +0,0 @@
-use std::env::current_dir;
-use std::fs::create_dir_all;
-
-use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
-
-use juno_juicer::msg::{
-    DepositMsg, ExecuteMsg, InstantiateMsg, IsKnownRootMsg, QueryMsg, WithdrawMsg,
-};
-
-fn main() {
-    let mut out_dir = current_dir().unwrap();
-    out_dir.push("schema");
-    create_dir_all(&out_dir).unwrap();
-    remove_schemas(&out_dir).unwrap();
-
-    export_schema(&schema_for!(InstantiateMsg), &out_dir);
-    export_schema(&schema_for!(ExecuteMsg), &out_dir);
-    export_schema(&schema_for!(QueryMsg), &out_dir);
-
-    // Execute messages
-    export_schema(&schema_for!(DepositMsg), &out_dir);
-    export_schema(&schema_for!(WithdrawMsg), &out_dir);
-
-    // Query messages
-    export_schema(&schema_for!(IsKnownRootMsg), &out_dir);
-}
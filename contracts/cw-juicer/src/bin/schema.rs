@@ -0,0 +1,59 @@
+use std::env::current_dir;
+
+use cosmwasm_schema::{export_schema, schema_for, write_api};
+
+use juno_juicer::msg::{
+    CanDepositResponse, ConfigResponse, CreatePoolMsg, DepositMsg, DepositResponse,
+    DepositTimeResponse, ExecuteMsg, ExportResponse, InstantiateMsg, IsKnownRootMsg,
+    IsSpentBatchMsg, IsSpentMsg, KnownRootsResponse, MerklePathResponse, MigrateMsg,
+    PausedResponse, PoolMsg, PoolResponse, ProofSizeResponse, QueryMsg, ReceiveMsg,
+    RegisterRelayerMsg, TreeInfoResponse, VerifyWithdrawResponse, WithdrawMsg, WithdrawPartialMsg,
+};
+
+fn main() {
+    // The whole API in juno-juicer.json, with the response of every query in
+    // raw/response_to_*.json. It clears schema/ first, so it has to come before
+    // the exports below.
+    write_api! {
+        instantiate: InstantiateMsg,
+        execute: ExecuteMsg,
+        query: QueryMsg,
+        migrate: MigrateMsg,
+    }
+
+    let mut out_dir = current_dir().unwrap();
+    out_dir.push("schema");
+
+    export_schema(&schema_for!(InstantiateMsg), &out_dir);
+    export_schema(&schema_for!(ExecuteMsg), &out_dir);
+    export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
+
+    // Execute messages
+    export_schema(&schema_for!(DepositMsg), &out_dir);
+    export_schema(&schema_for!(WithdrawMsg), &out_dir);
+    export_schema(&schema_for!(WithdrawPartialMsg), &out_dir);
+    export_schema(&schema_for!(CreatePoolMsg), &out_dir);
+    export_schema(&schema_for!(RegisterRelayerMsg), &out_dir);
+    export_schema(&schema_for!(ReceiveMsg), &out_dir);
+
+    // Query messages
+    export_schema(&schema_for!(IsKnownRootMsg), &out_dir);
+    export_schema(&schema_for!(IsSpentMsg), &out_dir);
+    export_schema(&schema_for!(IsSpentBatchMsg), &out_dir);
+    export_schema(&schema_for!(PoolMsg), &out_dir);
+
+    // Query responses
+    export_schema(&schema_for!(CanDepositResponse), &out_dir);
+    export_schema(&schema_for!(ConfigResponse), &out_dir);
+    export_schema(&schema_for!(DepositResponse), &out_dir);
+    export_schema(&schema_for!(ExportResponse), &out_dir);
+    export_schema(&schema_for!(KnownRootsResponse), &out_dir);
+    export_schema(&schema_for!(MerklePathResponse), &out_dir);
+    export_schema(&schema_for!(DepositTimeResponse), &out_dir);
+    export_schema(&schema_for!(PausedResponse), &out_dir);
+    export_schema(&schema_for!(PoolResponse), &out_dir);
+    export_schema(&schema_for!(ProofSizeResponse), &out_dir);
+    export_schema(&schema_for!(TreeInfoResponse), &out_dir);
+    export_schema(&schema_for!(VerifyWithdrawResponse), &out_dir);
+}
@@ -2,273 +2,5972 @@
 use cosmwasm_std::entry_point;
 
 use cosmwasm_std::{
-    to_binary, BankMsg, Binary, Coin, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
-    Uint128 as U128, Uint256 as U256,
+    from_binary, to_binary, to_vec, Addr, BankMsg, Binary, BlockInfo, Coin, CosmosMsg, Deps,
+    DepsMut, Env, MessageInfo, Order, Response, StdError, StdResult, Storage, Uint128 as U128,
+    Uint256 as U256, WasmMsg,
 };
+use cw20::{BalanceResponse, Cw20ExecuteMsg, Cw20QueryMsg, Cw20ReceiveMsg};
 
+use cw_storage_plus::Bound;
 use cw_utils::must_pay;
 
 use std::str::FromStr;
 
-use cw2::set_contract_version;
+use cw2::{get_contract_version, set_contract_version};
 
-use juicer::merkle_tree::MerkleTreeWithHistory;
-use juicer::msg::PublicSignals;
+use semver::Version;
+
+use juicer::bignum::{is_in_field, parse_u256};
+use juicer::commitment::change_commitment;
+use juicer::merkle_tree::{MerkleTreeWithHistory, ROOT_HISTORY_SIZE};
+use juicer::msg::{split_commitment_hash, withdraw_commitment_hash, CircomProof, PublicSignals};
 use juicer::verifier::Verifier;
 
 use crate::error::ContractError;
-use crate::msg::{DepositMsg, ExecuteMsg, InstantiateMsg, IsKnownRootMsg, QueryMsg, WithdrawMsg};
-use crate::state::{BASE_COIN, COMMITMENTS, NULLIFIER_HASHES, VERIFIER};
+use crate::events::{deposit_event, withdraw_event};
+use crate::msg::{
+    AssetInfo, CanDepositResponse, ClosePoolMsg, CommitWithdrawMsg, ConfigResponse, CreatePoolMsg,
+    DepositMsg, DepositResponse, DepositTimeResponse, ExecuteMsg, ExportResponse, FundFeesMsg,
+    ImportNullifiersMsg, ImportTreeMsg, InstantiateMsg, IsKnownRootMsg, IsSpentBatchMsg,
+    IsSpentMsg, KnownRootsResponse, MerklePathResponse, MigrateMsg, PausedResponse, Payout,
+    PoolMsg, PoolResponse, ProofSizeResponse, QueryMsg, RebuildTreeMsg, ReceiveMsg,
+    RegisterRelayerMsg, RevealWithdrawMsg, SetPausedMsg, TreeInfoResponse, VerifyWithdrawResponse,
+    WithdrawMsg, WithdrawPartialMsg,
+};
+use crate::state::{
+    PauseState, Pool, ADMIN, ASSET, BASE_COIN, BECH32_PREFIX, COMMITMENTS, DEPOSITED_COMMITMENTS,
+    DEPOSITS_CLOSED, DEPOSIT_FEE, EMIT_EVENTS, FEE_COLLECTOR, FEE_DENOM, FEE_RESERVE, KNOWN_ROOTS,
+    LEAF_HEIGHTS, LEAF_TIMES, LEAVES, MAX_BATCH_SIZE, MAX_DEPOSITS, MAX_FEE, MIN_CONFIRMATIONS,
+    MIN_DELAY_BLOCKS, NEXT_POOL_ID, NULLIFIER_HASHES, PARTIAL_VERIFIER, PAUSED, POOLS,
+    POOL_LEAF_HEIGHTS, POOL_LEAF_TIMES, POOL_LEAVES, POOL_NULLIFIER_HASHES, POOL_WITHDRAWAL_COUNTS,
+    REJECT_SELF_RELAY, RELAYERS, REQUIRE_COMMIT_REVEAL, VERIFIER, WITHDRAWALS_CLOSED,
+    WITHDRAWAL_COUNT, WITHDRAW_COMMITS, WITHDRAW_COMMIT_EXPIRY, WITHDRAW_COMMIT_HEIGHTS,
+};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:juno-juicer";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+const DEFAULT_MAX_BATCH_SIZE: u32 = 20;
+const DEFAULT_LEVELS: u32 = 20;
+const DEFAULT_BECH32_PREFIX: &str = "juno";
+// about a day of 6 second blocks
+const DEFAULT_WITHDRAW_COMMIT_EXPIRY: u64 = 14_400;
+
+// pagination
+const DEFAULT_LIMIT: u32 = 30;
+const MAX_LIMIT: u32 = 100;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     _info: MessageInfo,
     msg: InstantiateMsg,
-) -> StdResult<Response> {
+) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    BASE_COIN.save(deps.storage, &Coin::new(msg.amount.u128(), msg.denom))?;
 
-    let verifier = Verifier::new();
+    let asset = match msg.asset {
+        Some(AssetInfo::Cw20 { address }) => AssetInfo::Cw20 {
+            address: deps.api.addr_validate(&address)?.into_string(),
+        },
+        Some(native) => native,
+        None => AssetInfo::Native { denom: msg.denom },
+    };
+    validate_pool(&asset, msg.amount)?;
+    BASE_COIN.save(deps.storage, &Coin::new(msg.amount.u128(), asset.denom()))?;
+    ASSET.save(deps.storage, &asset)?;
+
+    let admin = msg
+        .admin
+        .map(|admin| deps.api.addr_validate(&admin))
+        .transpose()?;
+    let deposit_fee = msg.deposit_fee.unwrap_or_default();
+    if !deposit_fee.is_zero() {
+        let fee_collector = match msg.fee_collector {
+            Some(fee_collector) => deps.api.addr_validate(&fee_collector)?,
+            None => admin.clone().ok_or(ContractError::MissingFeeCollector {})?,
+        };
+        DEPOSIT_FEE.save(deps.storage, &deposit_fee)?;
+        FEE_COLLECTOR.save(deps.storage, &fee_collector)?;
+    }
+    if let Some(fee_denom) = msg.fee_denom {
+        validate_denom(&fee_denom)?;
+        if msg.max_fee.is_none() {
+            return Err(ContractError::MissingMaxFee {});
+        }
+        FEE_DENOM.save(deps.storage, &fee_denom)?;
+    }
+    if let Some(max_fee) = msg.max_fee {
+        MAX_FEE.save(deps.storage, &max_fee)?;
+    }
+
+    ADMIN.save(deps.storage, &admin)?;
+    EMIT_EVENTS.save(deps.storage, &msg.emit_events)?;
+    REJECT_SELF_RELAY.save(deps.storage, &msg.reject_self_relay)?;
+    REQUIRE_COMMIT_REVEAL.save(deps.storage, &msg.require_commit_reveal)?;
+    if let Some(expiry) = msg.withdraw_commit_expiry {
+        WITHDRAW_COMMIT_EXPIRY.save(deps.storage, &expiry)?;
+    }
+    BECH32_PREFIX.save(
+        deps.storage,
+        &msg.bech32_prefix
+            .unwrap_or_else(|| DEFAULT_BECH32_PREFIX.to_string()),
+    )?;
+    MAX_BATCH_SIZE.save(
+        deps.storage,
+        &msg.max_batch_size.unwrap_or(DEFAULT_MAX_BATCH_SIZE),
+    )?;
+    MIN_DELAY_BLOCKS.save(deps.storage, &msg.min_delay_blocks.unwrap_or(0))?;
+    if let Some(min_confirmations) = msg.min_confirmations {
+        MIN_CONFIRMATIONS.save(deps.storage, &min_confirmations)?;
+    }
+    if let Some(max_deposits) = msg.max_deposits {
+        MAX_DEPOSITS.save(deps.storage, &max_deposits)?;
+    }
+
+    let verifier = msg
+        .verification_key
+        .map(Verifier::from_vk)
+        .transpose()?
+        .unwrap_or_else(Verifier::new);
 
     VERIFIER.save(deps.storage, &verifier)?;
+    if let Some(vk) = msg.partial_verification_key {
+        PARTIAL_VERIFIER.save(deps.storage, &Verifier::from_vk(vk)?)?;
+    }
 
-    let tree = MerkleTreeWithHistory::new(20);
+    let mut tree = MerkleTreeWithHistory::try_new(
+        msg.levels.unwrap_or(DEFAULT_LEVELS),
+        msg.root_history_size.unwrap_or(ROOT_HISTORY_SIZE),
+        msg.zero_value,
+    )?;
+    let leaves = msg
+        .initial_commitments
+        .iter()
+        .enumerate()
+        .map(|(leaf_index, commitment)| {
+            record_commitment(deps.storage, None, commitment, leaf_index as u32)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // all or nothing, a full tree is rejected before any insert
+    tree.insert_many(&leaves)?;
+    for (leaf_index, commitment) in msg.initial_commitments.iter().enumerate() {
+        save_leaf(
+            deps.storage,
+            None,
+            leaf_index as u32,
+            commitment,
+            &env.block,
+        )?;
+    }
     COMMITMENTS.save(deps.storage, &tree)?;
+    index_known_roots(deps.storage, None, &tree)?;
 
     Ok(Response::default())
 }
 
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    let stored = get_contract_version(deps.storage)?;
+    let cannot_migrate = || ContractError::CannotMigrate {
+        previous_contract: stored.contract.clone(),
+        previous_version: stored.version.clone(),
+    };
+    if stored.contract != CONTRACT_NAME {
+        return Err(cannot_migrate());
+    }
+
+    // same version is allowed, to only swap the verification key
+    if parse_version(&stored.version)? > parse_version(CONTRACT_VERSION)? {
+        return Err(cannot_migrate());
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    // instances from before these settings were kept get the instantiate defaults,
+    // every deposit and withdrawal loads them
+    if ASSET.may_load(deps.storage)?.is_none() {
+        let denom = BASE_COIN.load(deps.storage)?.denom;
+        ASSET.save(deps.storage, &AssetInfo::Native { denom })?;
+    }
+    if ADMIN.may_load(deps.storage)?.is_none() {
+        ADMIN.save(deps.storage, &None)?;
+    }
+    if EMIT_EVENTS.may_load(deps.storage)?.is_none() {
+        EMIT_EVENTS.save(deps.storage, &false)?;
+    }
+    if BECH32_PREFIX.may_load(deps.storage)?.is_none() {
+        BECH32_PREFIX.save(deps.storage, &DEFAULT_BECH32_PREFIX.to_string())?;
+    }
+    if MAX_BATCH_SIZE.may_load(deps.storage)?.is_none() {
+        MAX_BATCH_SIZE.save(deps.storage, &DEFAULT_MAX_BATCH_SIZE)?;
+    }
+
+    // commitments and nullifier hashes are left untouched, only the root index
+    // is (re)built, in case the previous version didn't keep one
+    let tree = COMMITMENTS.load(deps.storage)?;
+    index_known_roots(deps.storage, None, &tree)?;
+    let pools: Vec<(u32, Pool)> = POOLS
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    for (pool_id, pool) in pools {
+        index_known_roots(deps.storage, Some(pool_id), &pool.commitments)?;
+    }
+
+    if let Some(vk) = msg.verification_key {
+        VERIFIER.save(deps.storage, &Verifier::from_vk(vk)?)?;
+    }
+    if let Some(vk) = msg.partial_verification_key {
+        PARTIAL_VERIFIER.save(deps.storage, &Verifier::from_vk(vk)?)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("from_version", stored.version)
+        .add_attribute("to_version", CONTRACT_VERSION))
+}
+
+fn parse_version(version: &str) -> Result<Version, ContractError> {
+    Version::parse(version).map_err(|err| ContractError::ParseError {
+        msg: err.to_string(),
+    })
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
-        ExecuteMsg::Deposit(msg) => execute_deposit(deps, info, msg),
-        ExecuteMsg::Withdraw(msg) => execute_withdraw(deps, info, msg),
+        ExecuteMsg::Deposit(msg) => execute_deposit(deps, env, info, msg),
+        ExecuteMsg::DepositBatch(msgs) => execute_deposit_batch(deps, env, info, msgs),
+        ExecuteMsg::Withdraw(msg) => execute_withdraw(deps, env, info, msg),
+        ExecuteMsg::WithdrawWithChange(msg) => execute_withdraw_with_change(deps, env, info, msg),
+        ExecuteMsg::CommitWithdraw(msg) => execute_commit_withdraw(deps, env, msg),
+        ExecuteMsg::RevealWithdraw(msg) => execute_reveal_withdraw(deps, env, info, msg),
+        ExecuteMsg::ImportNullifiers(msg) => execute_import_nullifiers(deps, info, msg),
+        ExecuteMsg::ImportTree(msg) => execute_import_tree(deps, env, info, msg),
+        ExecuteMsg::RebuildTree(msg) => execute_rebuild_tree(deps, info, msg),
+        ExecuteMsg::ClosePool(msg) => execute_close_pool(deps, env, info, msg),
+        ExecuteMsg::SetPaused(msg) => execute_set_paused(deps, info, msg),
+        ExecuteMsg::CreatePool(msg) => execute_create_pool(deps, info, msg),
+        ExecuteMsg::RegisterRelayer(msg) => execute_register_relayer(deps, info, msg),
+        ExecuteMsg::FundFees(msg) => execute_fund_fees(deps, info, msg),
+        ExecuteMsg::Receive(msg) => execute_receive(deps, env, info, msg),
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::IsKnownRoot(msg) => to_binary(&query_is_known_root(deps, msg)?),
+        QueryMsg::ProofSize(proof) => to_binary(&query_proof_size(proof)?),
+        QueryMsg::GetLastRootBytes { pool_id } => to_binary(&query_last_root_bytes(deps, pool_id)?),
+        QueryMsg::CanDeposit { pool_id } => to_binary(&query_can_deposit(deps, pool_id)?),
+        QueryMsg::ExportState {
+            start_after,
+            limit,
+            pool_id,
+        } => to_binary(&query_export_state(deps, start_after, limit, pool_id)?),
+        QueryMsg::TreeInfo { pool_id } => to_binary(&query_tree_info(deps, pool_id)?),
+        QueryMsg::IsSpent(msg) => to_binary(&query_is_spent(deps, msg)?),
+        QueryMsg::IsSpentBatch(msg) => to_binary(&query_is_spent_batch(deps, msg)?),
+        QueryMsg::WithdrawalCount { pool_id } => to_binary(&query_withdrawal_count(deps, pool_id)?),
+        QueryMsg::TotalWithdrawalCount {} => to_binary(&query_total_withdrawal_count(deps)?),
+        QueryMsg::AreKnownRoots { roots, pool_id } => {
+            to_binary(&query_are_known_roots(deps, roots, pool_id)?)
+        }
+        QueryMsg::KnownRoots { pool_id } => to_binary(&query_known_roots(deps, pool_id)?),
+        QueryMsg::Paused {} => to_binary(&query_paused(deps)?),
+        QueryMsg::Pool(msg) => to_binary(&query_pool(deps, msg)?),
+        QueryMsg::VerifyWithdraw(msg) => to_binary(&query_verify_withdraw(deps, env, msg)?),
+        QueryMsg::VerificationKey {} => to_binary(&query_verification_key(deps)?),
+        QueryMsg::Config { pool_id } => to_binary(&query_config(deps, pool_id)?),
+        QueryMsg::MerklePath {
+            leaf_index,
+            pool_id,
+        } => to_binary(&query_merkle_path(deps, leaf_index, pool_id)?),
+        QueryMsg::CommitmentIndex {
+            commitment,
+            pool_id,
+        } => to_binary(&query_commitment_index(deps, commitment, pool_id)?),
+        QueryMsg::DepositTime {
+            leaf_index,
+            pool_id,
+        } => to_binary(&query_deposit_time(deps, leaf_index, pool_id)?),
+        #[cfg(feature = "debug")]
+        QueryMsg::DebugState { pool_id } => to_binary(&query_debug_state(deps, pool_id)?),
     }
 }
 
 pub fn query_is_known_root(deps: Deps, msg: IsKnownRootMsg) -> StdResult<bool> {
-    let commitment_mt = COMMITMENTS.load(deps.storage)?;
+    let root = parse_root(&msg.root).map_err(|err| StdError::generic_err(err.to_string()))?;
+    let pool = load_pool(deps.storage, msg.pool_id)
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+    is_known_root(deps.storage, msg.pool_id, &pool.commitments, &root)
+}
+
+pub fn query_last_root_bytes(deps: Deps, pool_id: Option<u32>) -> StdResult<Binary> {
+    let pool =
+        load_pool(deps.storage, pool_id).map_err(|err| StdError::generic_err(err.to_string()))?;
+
+    Ok(Binary::from(pool.commitments.get_last_root_le_bytes()))
+}
+
+pub fn query_can_deposit(deps: Deps, pool_id: Option<u32>) -> StdResult<CanDepositResponse> {
+    let pool =
+        load_pool(deps.storage, pool_id).map_err(|err| StdError::generic_err(err.to_string()))?;
+    let reason = if DEPOSITS_CLOSED.may_load(deps.storage)?.unwrap_or(false) {
+        Some("closed")
+    } else if PAUSED.may_load(deps.storage)?.unwrap_or_default().deposits {
+        Some("paused")
+    } else {
+        let commitment_mt = pool.commitments;
+        if commitment_mt.is_full() {
+            Some("tree_full")
+        } else if check_max_deposits(deps.storage, &commitment_mt).is_err() {
+            Some("cap_reached")
+        } else {
+            None
+        }
+    };
+
+    Ok(CanDepositResponse {
+        allowed: reason.is_none(),
+        reason: reason.map(String::from),
+    })
+}
+
+pub fn query_export_state(
+    deps: Deps,
+    start_after: Option<u32>,
+    limit: Option<u32>,
+    pool_id: Option<u32>,
+) -> StdResult<ExportResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let leaves = match pool_id {
+        None => LEAVES
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .collect::<StdResult<Vec<_>>>()?,
+        Some(pool_id) => POOL_LEAVES
+            .prefix(pool_id)
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .collect::<StdResult<Vec<_>>>()?,
+    };
+
+    let commitment_mt = load_pool(deps.storage, pool_id)
+        .map_err(|err| StdError::generic_err(err.to_string()))?
+        .commitments;
+
+    Ok(ExportResponse {
+        leaves,
+        current_root: commitment_mt.get_last_root().to_string(),
+        next_index: commitment_mt.next_index,
+    })
+}
+
+pub fn query_tree_info(deps: Deps, pool_id: Option<u32>) -> StdResult<TreeInfoResponse> {
+    let commitment_mt = load_pool(deps.storage, pool_id)
+        .map_err(|err| StdError::generic_err(err.to_string()))?
+        .commitments;
+
+    let (used, capacity) = commitment_mt.fill_ratio();
+
+    Ok(TreeInfoResponse {
+        levels: commitment_mt.levels,
+        next_index: commitment_mt.next_index,
+        used,
+        capacity,
+        last_root: commitment_mt.get_last_root().to_string(),
+    })
+}
+
+#[cfg(feature = "debug")]
+pub fn query_debug_state(
+    deps: Deps,
+    pool_id: Option<u32>,
+) -> StdResult<crate::msg::DebugStateResponse> {
+    let pool =
+        load_pool(deps.storage, pool_id).map_err(|err| StdError::generic_err(err.to_string()))?;
+    let tree = pool.commitments;
 
-    Ok(commitment_mt.is_known_root(&U256::from_str(&msg.root)?))
+    Ok(crate::msg::DebugStateResponse {
+        filled_subtrees: tree.filled_subtrees.iter().map(U256::to_string).collect(),
+        zeros: tree.zeros.iter().map(U256::to_string).collect(),
+        next_index: tree.next_index,
+        current_root_index: tree.current_root_index,
+    })
+}
+
+pub fn query_is_spent(deps: Deps, msg: IsSpentMsg) -> StdResult<bool> {
+    is_spent(deps.storage, msg.pool_id, &msg.nullifier_hash)
+        .map_err(|err| StdError::generic_err(err.to_string()))
+}
+
+pub fn query_is_spent_batch(deps: Deps, msg: IsSpentBatchMsg) -> StdResult<Vec<bool>> {
+    check_batch_size(deps.storage, msg.nullifier_hashes.len())
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+
+    msg.nullifier_hashes
+        .iter()
+        .map(|nullifier_hash| is_spent(deps.storage, msg.pool_id, nullifier_hash))
+        .collect::<Result<_, _>>()
+        .map_err(|err| StdError::generic_err(err.to_string()))
+}
+
+pub fn query_withdrawal_count(deps: Deps, pool_id: Option<u32>) -> StdResult<u64> {
+    Ok(POOL_WITHDRAWAL_COUNTS
+        .may_load(deps.storage, pool_id.unwrap_or(0))?
+        .unwrap_or(0))
+}
+
+pub fn query_total_withdrawal_count(deps: Deps) -> StdResult<u64> {
+    Ok(WITHDRAWAL_COUNT.may_load(deps.storage)?.unwrap_or(0))
+}
+
+pub fn query_are_known_roots(
+    deps: Deps,
+    roots: Vec<String>,
+    pool_id: Option<u32>,
+) -> StdResult<Vec<bool>> {
+    check_batch_size(deps.storage, roots.len())
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+
+    let pool =
+        load_pool(deps.storage, pool_id).map_err(|err| StdError::generic_err(err.to_string()))?;
+    roots
+        .iter()
+        .map(|root| {
+            let root = parse_root(root).map_err(|err| StdError::generic_err(err.to_string()))?;
+            is_known_root(deps.storage, pool_id, &pool.commitments, &root)
+        })
+        .collect()
+}
+
+pub fn query_known_roots(deps: Deps, pool_id: Option<u32>) -> StdResult<KnownRootsResponse> {
+    let commitment_mt = load_pool(deps.storage, pool_id)
+        .map_err(|err| StdError::generic_err(err.to_string()))?
+        .commitments;
+
+    Ok(KnownRootsResponse {
+        roots: commitment_mt
+            .known_roots()
+            .iter()
+            .map(|root| root.to_string())
+            .collect(),
+    })
+}
+
+pub fn query_paused(deps: Deps) -> StdResult<PausedResponse> {
+    let paused = PAUSED.may_load(deps.storage)?.unwrap_or_default();
+
+    Ok(PausedResponse {
+        deposits: paused.deposits,
+        withdrawals: paused.withdrawals,
+    })
+}
+
+pub fn query_pool(deps: Deps, msg: PoolMsg) -> StdResult<PoolResponse> {
+    let pool = load_pool(deps.storage, msg.pool_id)
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+
+    Ok(PoolResponse {
+        asset: pool.asset,
+        amount: pool.amount,
+        next_index: pool.commitments.next_index,
+        last_root: pool.commitments.get_last_root().to_string(),
+    })
+}
+
+pub fn query_commitment_index(
+    deps: Deps,
+    commitment: String,
+    pool_id: Option<u32>,
+) -> StdResult<Option<u32>> {
+    let commitment =
+        parse_commitment(&commitment).map_err(|err| StdError::generic_err(err.to_string()))?;
+    DEPOSITED_COMMITMENTS.may_load(deps.storage, (pool_id.unwrap_or(0), commitment.to_string()))
+}
+
+pub fn query_deposit_time(
+    deps: Deps,
+    leaf_index: u32,
+    pool_id: Option<u32>,
+) -> StdResult<DepositTimeResponse> {
+    let (commitment, block_height, timestamp) = match pool_id {
+        None => (
+            LEAVES.may_load(deps.storage, leaf_index)?,
+            LEAF_HEIGHTS.may_load(deps.storage, leaf_index)?,
+            LEAF_TIMES.may_load(deps.storage, leaf_index)?,
+        ),
+        Some(pool_id) => (
+            POOL_LEAVES.may_load(deps.storage, (pool_id, leaf_index))?,
+            POOL_LEAF_HEIGHTS.may_load(deps.storage, (pool_id, leaf_index))?,
+            POOL_LEAF_TIMES.may_load(deps.storage, (pool_id, leaf_index))?,
+        ),
+    };
+
+    Ok(DepositTimeResponse {
+        commitment: commitment
+            .ok_or_else(|| StdError::not_found(format!("leaf {}", leaf_index)))?,
+        block_height,
+        timestamp,
+    })
+}
+
+pub fn query_merkle_path(
+    deps: Deps,
+    leaf_index: u32,
+    pool_id: Option<u32>,
+) -> StdResult<MerklePathResponse> {
+    let pool =
+        load_pool(deps.storage, pool_id).map_err(|err| StdError::generic_err(err.to_string()))?;
+
+    // the stored tree only keeps its right edge, rebuild the layers from the leaves
+    let leaves =
+        load_leaves(deps.storage, pool_id).map_err(|err| StdError::generic_err(err.to_string()))?;
+
+    let path = pool
+        .commitments
+        .merkle_proof(&leaves, leaf_index)
+        .ok_or_else(|| StdError::not_found(format!("leaf {}", leaf_index)))?;
+    let root = pool
+        .commitments
+        .path_root(&leaves[leaf_index as usize], &path)
+        .ok_or_else(|| StdError::generic_err("Invalid merkle path"))?;
+
+    Ok(MerklePathResponse {
+        path_elements: path
+            .path_elements
+            .iter()
+            .map(|element| element.to_string())
+            .collect(),
+        path_indices: path.path_indices,
+        root: root.to_string(),
+    })
+}
+
+pub fn query_verify_withdraw(
+    deps: Deps,
+    env: Env,
+    msg: WithdrawMsg,
+) -> StdResult<VerifyWithdrawResponse> {
+    // a direct withdrawal is what's being checked, refused when it must be committed first
+    let reason = check_direct_withdraw(deps.storage)
+        .and_then(|_| check_withdraw(deps, &env, &msg))
+        .err()
+        .map(|err| err.to_string());
+
+    Ok(VerifyWithdrawResponse {
+        valid: reason.is_none(),
+        reason,
+    })
+}
+
+pub fn query_config(deps: Deps, pool_id: Option<u32>) -> StdResult<ConfigResponse> {
+    let pool =
+        load_pool(deps.storage, pool_id).map_err(|err| StdError::generic_err(err.to_string()))?;
+
+    Ok(ConfigResponse {
+        denom: pool.asset.denom().to_string(),
+        amount: pool.amount,
+        levels: pool.commitments.levels,
+        contract_version: get_contract_version(deps.storage)?.version,
+        fee_denom: FEE_DENOM.may_load(deps.storage)?,
+        fee_reserve: FEE_RESERVE.may_load(deps.storage)?.unwrap_or_default(),
+        max_fee: MAX_FEE.may_load(deps.storage)?,
+    })
+}
+
+pub fn query_verification_key(deps: Deps) -> StdResult<Binary> {
+    let verifier = VERIFIER.load(deps.storage)?;
+
+    Ok(Binary::from(verifier.vk_json().as_bytes()))
+}
+
+pub fn query_proof_size(proof: CircomProof) -> StdResult<ProofSizeResponse> {
+    let size = to_vec(&proof)?.len() as u32;
+
+    proof
+        .clone()
+        .to_proof()
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+    // decoded, so every coordinate `to_flat` reads is there
+    let flat_size = to_vec(&proof.to_flat())?.len() as u32;
+    // a (G1), b (G2) and c (G1), each one decoded from its own entry
+    let points = [proof.pi_a.len(), proof.pi_b.len(), proof.pi_c.len()]
+        .iter()
+        .filter(|len| **len > 0)
+        .count() as u32;
+
+    Ok(ProofSizeResponse {
+        size,
+        flat_size,
+        points,
+    })
 }
 
 pub fn execute_deposit(
     deps: DepsMut,
+    env: Env,
     info: MessageInfo,
     msg: DepositMsg,
 ) -> Result<Response, ContractError> {
-    let coin = BASE_COIN.load(deps.storage)?;
+    check_deposits_open(deps.storage)?;
+
+    let pool = load_pool(deps.storage, msg.pool_id)?;
 
-    let payment = must_pay(&info, &coin.denom)?;
-    if payment != coin.amount {
+    // CW20 pools are funded through `Receive`
+    let denom = match &pool.asset {
+        AssetInfo::Native { denom } => denom,
+        AssetInfo::Cw20 { .. } => return Err(ContractError::WrongAsset {}),
+    };
+
+    let (fee, fee_msg) = deposit_fee(deps.storage, &pool.asset, 1)?;
+    let total = pool.amount.checked_add(fee).map_err(StdError::from)?;
+
+    let payment = must_pay(&info, denom)?;
+    if payment != total {
         return Err(ContractError::InvalidAmount {
-            denom: coin.denom,
-            amount: coin.amount,
+            denom: denom.clone(),
+            amount: total,
         });
     }
 
-    let mut commitment_mt = COMMITMENTS.load(deps.storage)?;
-    // TODO: confirm insert worked
-    commitment_mt.insert(&U256::from_str(&msg.commitment)?);
-    COMMITMENTS.save(deps.storage, &commitment_mt)?;
-
-    Ok(Response::new()
-        .add_attribute("action", "deposit")
-        .add_attribute("from", info.sender))
+    Ok(insert_deposit(deps, env, info.sender, pool, msg)?.add_messages(fee_msg))
 }
 
-pub fn execute_withdraw(
+pub fn execute_deposit_batch(
     deps: DepsMut,
-    _info: MessageInfo,
-    msg: WithdrawMsg,
+    env: Env,
+    info: MessageInfo,
+    msgs: Vec<DepositMsg>,
 ) -> Result<Response, ContractError> {
-    let coin = BASE_COIN.load(deps.storage)?;
+    check_deposits_open(deps.storage)?;
+    check_batch_size(deps.storage, msgs.len())?;
 
-    let recipient = deps.api.addr_validate(&msg.recipient)?;
-    if msg.relayer != "" && msg.relayer != "0" {
-        deps.api.addr_validate(&msg.relayer)?;
+    // a single payment can only fund one pool
+    let pool_id = msgs.first().and_then(|msg| msg.pool_id);
+    if msgs.iter().any(|msg| msg.pool_id != pool_id) {
+        return Err(ContractError::MixedPools {});
     }
+    let mut pool = load_pool(deps.storage, pool_id)?;
 
-    // TODO: check info.funds
+    let denom = match &pool.asset {
+        AssetInfo::Native { denom } => denom.clone(),
+        AssetInfo::Cw20 { .. } => return Err(ContractError::WrongAsset {}),
+    };
+    let (fee, fee_msg) = deposit_fee(deps.storage, &pool.asset, msgs.len() as u128)?;
+    let total = pool
+        .amount
+        .checked_mul(U128::from(msgs.len() as u128))
+        .and_then(|total| total.checked_add(fee))
+        .map_err(StdError::from)?;
 
-    let public_signals = PublicSignals::from_values(
-        msg.root.clone(),
-        msg.nullifier_hash.clone(),
-        msg.recipient.clone(),
-        msg.relayer.clone(),
-        msg.fee,
-    );
+    let payment = must_pay(&info, &denom)?;
+    if payment != total {
+        return Err(ContractError::InvalidAmount {
+            denom,
+            amount: total,
+        });
+    }
 
-    let commitment_mt = COMMITMENTS.load(deps.storage)?;
-    assert_ne!(
-        commitment_mt.current_root_index, 0,
-        "commitment merkle tree shouldn't be 0"
-    );
+    // the tree is only saved once every commitment fits
+    let emit_events = EMIT_EVENTS.load(deps.storage)?;
 
-    // 1. check nullifier_hash is not in nullifier hashes
-    match NULLIFIER_HASHES.may_load(deps.storage, msg.nullifier_hash.clone())? {
-        Some(_) => return Err(ContractError::DuplicatedCommitment {}),
-        None => (),
-    };
+    let mut res = Response::new()
+        .add_messages(fee_msg)
+        .add_attribute("action", "deposit_batch")
+        .add_attribute("from", info.sender);
+    for msg in msgs {
+        check_max_deposits(deps.storage, &pool.commitments)?;
+        let commitment = record_commitment(
+            deps.storage,
+            pool_id,
+            &msg.commitment,
+            pool.commitments.next_index,
+        )?;
+        let leaf_index = insert_leaf(deps.storage, pool_id, &mut pool.commitments, &commitment)?;
+        save_leaf(
+            deps.storage,
+            pool_id,
+            leaf_index,
+            &msg.commitment,
+            &env.block,
+        )?;
 
-    // 2. confirm root is ok
-    if !commitment_mt.is_known_root(&U256::from_str(&msg.root).unwrap()) {
-        return Err(ContractError::UnknownRoot {});
+        res = res
+            .add_attribute("commitment", &msg.commitment)
+            .add_attribute("leaf_index", leaf_index.to_string());
+        if emit_events {
+            res = res.add_event(deposit_event(&msg.commitment, leaf_index, env.block.time));
+        }
     }
+    save_pool(deps.storage, pool_id, &pool)?;
 
-    // 3. Confirm the circuit proof
-    let verifier = VERIFIER.load(deps.storage)?;
-    let proof = msg.proof.to_proof();
-    let inputs = public_signals.get();
-    if !verifier.verify_proof(proof, &inputs) {
-        return Err(ContractError::InvalidProof {});
-    };
+    Ok(res)
+}
 
-    // 4. Store nullifier hash to nullifier_hashes map
-    NULLIFIER_HASHES
-        .save(deps.storage, msg.nullifier_hash, &true)
-        .unwrap();
+pub fn execute_receive(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    wrapper: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    check_deposits_open(deps.storage)?;
 
-    // 5. Send the funds
-    let mut msgs: Vec<BankMsg> = Vec::new();
+    let ReceiveMsg::Deposit(msg) = from_binary(&wrapper.msg)?;
+    let pool = load_pool(deps.storage, msg.pool_id)?;
 
-    let amount_to_recipient = match coin.amount.checked_sub(U128::from(msg.fee)) {
-        Ok(v) => v,
-        Err(err) => {
-            return Err(ContractError::FeesTooHigh {
-                msg: err.to_string(),
-            })
-        }
-    };
+    // only the pooled token can call back
+    match &pool.asset {
+        AssetInfo::Cw20 { address } if *address == info.sender => {}
+        _ => return Err(ContractError::WrongAsset {}),
+    }
 
-    msgs.push(BankMsg::Send {
-        to_address: recipient.to_string(),
-        amount: vec![Coin {
-            denom: coin.denom.clone(),
-            amount: amount_to_recipient,
-        }],
-    });
-    if !msg.fee.is_zero() {
-        msgs.push(BankMsg::Send {
-            to_address: msg.relayer,
-            amount: vec![Coin {
-                denom: coin.denom.clone(),
-                amount: msg.fee,
-            }],
+    let (fee, fee_msg) = deposit_fee(deps.storage, &pool.asset, 1)?;
+    let total = pool.amount.checked_add(fee).map_err(StdError::from)?;
+    if wrapper.amount != total {
+        return Err(ContractError::InvalidAmount {
+            denom: pool.asset.denom().to_string(),
+            amount: total,
         });
     }
 
-    Ok(Response::new()
-        .add_messages(msgs)
-        .add_attribute("action", "withdraw"))
+    let sender = deps.api.addr_validate(&wrapper.sender)?;
+    Ok(insert_deposit(deps, env, sender, pool, msg)?.add_messages(fee_msg))
 }
 
-#[cfg(test)]
-mod tests {
-    use cosmwasm_std::coins;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+// Fee owed for `notes` deposits, and the message forwarding it to the fee collector.
+// It's never part of the pool, so notes and withdrawals are unaffected.
+fn deposit_fee(
+    storage: &dyn Storage,
+    asset: &AssetInfo,
+    notes: u128,
+) -> StdResult<(U128, Option<CosmosMsg>)> {
+    let fee = DEPOSIT_FEE.may_load(storage)?.unwrap_or_default();
+    if fee.is_zero() {
+        return Ok((fee, None));
+    }
+    let fee = fee.checked_mul(U128::from(notes))?;
 
-    use juicer::msg::Deposit;
+    let fee_collector = FEE_COLLECTOR.load(storage)?;
+    Ok((fee, Some(send_asset(asset, fee_collector.as_str(), fee)?)))
+}
 
-    use super::*;
+// Inserts an already paid commitment
+fn insert_deposit(
+    deps: DepsMut,
+    env: Env,
+    from: Addr,
+    mut pool: Pool,
+    msg: DepositMsg,
+) -> Result<Response, ContractError> {
+    check_max_deposits(deps.storage, &pool.commitments)?;
+    let commitment = record_commitment(
+        deps.storage,
+        msg.pool_id,
+        &msg.commitment,
+        pool.commitments.next_index,
+    )?;
+    let leaf_index = insert_leaf(
+        deps.storage,
+        msg.pool_id,
+        &mut pool.commitments,
+        &commitment,
+    )?;
+    save_pool(deps.storage, msg.pool_id, &pool)?;
+    save_leaf(
+        deps.storage,
+        msg.pool_id,
+        leaf_index,
+        &msg.commitment,
+        &env.block,
+    )?;
 
-    #[test]
-    fn test_deposit() {
-        let mut deps = mock_dependencies();
-        let info = mock_info(&"Alice".to_string(), &coins(10, "TKN"));
+    let mut res = Response::new()
+        .add_attribute("action", "deposit")
+        .add_attribute("from", from)
+        .add_attribute("commitment", &msg.commitment)
+        .add_attribute("leaf_index", leaf_index.to_string())
+        .set_data(to_binary(&DepositResponse {
+            leaf_index,
+            commitment: msg.commitment.clone(),
+            root: pool.commitments.get_last_root().to_string(),
+        })?);
+    if EMIT_EVENTS.load(deps.storage)? {
+        res = res.add_event(deposit_event(&msg.commitment, leaf_index, env.block.time));
+    }
 
-        // let deposit = generate_deposit();
-        let deposit = Deposit::new(
-            "276277773929387392791096474084808108569850403587654342680891529007770412737"
-                .to_string(),
-        );
+    Ok(res)
+}
 
-        // instantiate an empty contract
-        let instantiate_msg = InstantiateMsg {
-            amount: U128::from(10 as u128),
-            denom: "TKN".to_string(),
-        };
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, res.messages.len());
+pub fn execute_withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: WithdrawMsg,
+) -> Result<Response, ContractError> {
+    check_direct_withdraw(deps.storage)?;
 
-        let deposit_msg = DepositMsg {
-            commitment: deposit.get_commitment(),
-        };
+    withdraw(deps, env, info, msg)
+}
 
-        let msg = ExecuteMsg::Deposit(deposit_msg.clone());
+// Withdrawals spending a note in one step, refused when they must be committed first
+fn check_direct_withdraw(storage: &dyn Storage) -> Result<(), ContractError> {
+    if REQUIRE_COMMIT_REVEAL.may_load(storage)?.unwrap_or(false) {
+        return Err(ContractError::CommitRevealRequired {});
+    }
+    Ok(())
+}
 
-        let info = mock_info(&"Alice".to_string(), &coins(10, "TKN"));
-        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
-        assert_eq!(0, res.messages.len());
+fn withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: WithdrawMsg,
+) -> Result<Response, ContractError> {
+    // attached coins would be swallowed by the pool
+    if !info.funds.is_empty() {
+        return Err(ContractError::UnexpectedFunds {});
+    }
 
-        // TODO
-        // let commitment = COMMITMENTS
-        //     .load(&deps.storage, deposit.get_commitment())
-        //     .unwrap();
-        // assert_eq!(Uint128::new(10), commitment);
+    let CheckedWithdraw {
+        pool,
+        recipient,
+        relayer,
+        payouts,
+    } = check_withdraw(deps.as_ref(), &env, &msg)?;
+
+    // 4. Store nullifier hash to nullifier_hashes map
+    mark_spent(deps.storage, msg.pool_id, &msg.nullifier_hash)?;
+    count_withdrawal(deps.storage, msg.pool_id)?;
+
+    // 5. Send the funds
+    let self_relay = relayer.as_ref() == Some(&recipient);
+    let fee_denom = take_reserve_fee(deps.storage, msg.fee)?;
+    let msgs = withdraw_payouts(&pool.asset, &msg, &payouts, relayer, fee_denom)?;
+
+    let mut res = Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "withdraw")
+        .add_attribute("nullifier_hash", &msg.nullifier_hash)
+        .add_attribute("recipient", recipient.as_str())
+        .add_attribute("relayer", &msg.relayer)
+        .add_attribute("fee", msg.fee.to_string());
+    if self_relay {
+        res = res.add_attribute("warning", "relayer_is_recipient");
+    }
+    if EMIT_EVENTS.load(deps.storage)? {
+        res = res.add_event(withdraw_event(
+            &msg.nullifier_hash,
+            recipient.as_str(),
+            &msg.relayer,
+            msg.fee,
+        ));
     }
 
-    #[test]
-    fn test_withdraw_1() {
-        let mut deps = mock_dependencies();
+    Ok(res)
+}
 
-        // instantiate an empty contract
-        let instantiate_msg = InstantiateMsg {
-            amount: U128::from(10 as u128),
-            denom: "TKN".to_string(),
-        };
-        let info = mock_info(&"Alice".to_string(), &[]);
+pub fn execute_withdraw_with_change(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: WithdrawPartialMsg,
+) -> Result<Response, ContractError> {
+    // there's no reveal step for partial withdrawals, they'd skip the commitment
+    check_direct_withdraw(deps.storage)?;
+    if !info.funds.is_empty() {
+        return Err(ContractError::UnexpectedFunds {});
+    }
 
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, res.messages.len());
+    let CheckedWithdraw {
+        mut pool,
+        recipient,
+        relayer,
+        payouts,
+    } = check_withdraw_partial(deps.as_ref(), &env, &msg)?;
+    let withdraw = &msg.withdraw;
 
-        let mut tree = COMMITMENTS.load(&deps.storage).unwrap();
+    mark_spent(deps.storage, withdraw.pool_id, &withdraw.nullifier_hash)?;
+    count_withdrawal(deps.storage, withdraw.pool_id)?;
 
-        let deposit = Deposit {
-            nullifier: "54154714943715201094961901040590459639892306160131965986154511512546000403"
-                .to_string(),
-        };
+    // the change note is a new leaf of the same pool, like a deposit
+    let change = match change_leaf(&msg)? {
+        None => None,
+        Some(change) => {
+            let change = change.to_string();
+            let leaf = record_commitment(
+                deps.storage,
+                withdraw.pool_id,
+                &change,
+                pool.commitments.next_index,
+            )?;
+            let leaf_index =
+                insert_leaf(deps.storage, withdraw.pool_id, &mut pool.commitments, &leaf)?;
+            save_pool(deps.storage, withdraw.pool_id, &pool)?;
+            save_leaf(
+                deps.storage,
+                withdraw.pool_id,
+                leaf_index,
+                &change,
+                &env.block,
+            )?;
+            Some((change, leaf_index))
+        }
+    };
 
-        let leaf_index = tree
-            .insert(&U256::from_str(&deposit.clone().get_commitment()).unwrap())
-            .unwrap();
+    let self_relay = relayer.as_ref() == Some(&recipient);
+    let fee_denom = take_reserve_fee(deps.storage, withdraw.fee)?;
+    let msgs = withdraw_payouts(&pool.asset, withdraw, &payouts, relayer, fee_denom)?;
 
-        COMMITMENTS.save(&mut deps.storage, &tree).unwrap();
+    let mut res = Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "withdraw_with_change")
+        .add_attribute("nullifier_hash", &withdraw.nullifier_hash)
+        .add_attribute("recipient", recipient.as_str())
+        .add_attribute("relayer", &withdraw.relayer)
+        .add_attribute("fee", withdraw.fee.to_string())
+        .add_attribute("amount", msg.amount.to_string());
+    if let Some((change, leaf_index)) = &change {
+        res = res
+            .add_attribute("change_commitment", change)
+            .add_attribute("leaf_index", leaf_index.to_string());
+    }
+    if self_relay {
+        res = res.add_attribute("warning", "relayer_is_recipient");
+    }
+    if EMIT_EVENTS.load(deps.storage)? {
+        res = res.add_event(withdraw_event(
+            &withdraw.nullifier_hash,
+            recipient.as_str(),
+            &withdraw.relayer,
+            withdraw.fee,
+        ));
+        if let Some((change, leaf_index)) = &change {
+            res = res.add_event(deposit_event(change, *leaf_index, env.block.time));
+        }
+    }
 
-        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
-            proof: juicer::msg::CircomProof::from(
-                r#"
-                {"pi_a":["13899269723484849480002065473374493568327469679987898626585656783152635224196","4644776364206331144208370772102729462540382294894335687634266360911567618285","1"],"pi_b":[["11550199660326834097658136558533988234178757731057308044978347076813572730094","2682881763463105242359875271001109719339722524261167828167916342514182934974"],["95039516498389015079170513998234052571784823209713661742933740886373624805","3428917488231875962754312177544595651247105738928930070869265869601586471119"],["1","0"]],"pi_c":["18932896497737520548726210332000803585517357164811625711564892288268655803594","3898942506810745753991535926637360084087400921771473613166702262820083122159","1"],"protocol":"groth16","curve":"bn128"}
-                "#.to_string(),
-            ),
-            root: "7867364560627547019086598689541673085228895175200585554350937642876639323043".to_string(),
-            nullifier_hash: deposit.get_nullifier_hash((leaf_index) as u128),
-            recipient: "juno14spgzl9ps5tyev32ny74fa6m0s9q9828v0vrga".to_string(),
-            relayer: "juno1am5sw4geda8xfvmn4pkzruhv8ah0l3jx5hgchh".to_string(),
-            fee: U128::from(0 as u128),
-        });
-        let info = mock_info(&"Alice".to_string(), &[]);
+    Ok(res)
+}
 
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(1, res.messages.len());
+// Pays out a note, the recipient first, its fee going to the relayer
+fn withdraw_payouts(
+    asset: &AssetInfo,
+    msg: &WithdrawMsg,
+    payouts: &[(Addr, U128)],
+    relayer: Option<Addr>,
+    fee_denom: Option<String>,
+) -> StdResult<Vec<CosmosMsg>> {
+    let mut msgs: Vec<CosmosMsg> = Vec::new();
+    let fee_asset = fee_denom.map(|denom| AssetInfo::Native { denom });
+
+    // a recipient relaying its own withdrawal is paid its fee along the rest,
+    // unless the fee is in another denom
+    let (recipient, amount_to_recipient) = &payouts[0];
+    let relayer = relayer.filter(|relayer| fee_asset.is_some() || relayer != recipient);
+    let amount_to_recipient = match relayer {
+        Some(_) => *amount_to_recipient,
+        None => amount_to_recipient
+            .checked_add(msg.fee)
+            .map_err(StdError::from)?,
+    };
+
+    msgs.push(match &msg.callback {
+        Some(callback) => send_asset_with_callback(
+            asset,
+            recipient.as_str(),
+            amount_to_recipient,
+            callback.msg.clone(),
+        )?,
+        None => send_asset(asset, recipient.as_str(), amount_to_recipient)?,
+    });
+    for (address, amount) in &payouts[1..] {
+        msgs.push(send_asset(asset, address.as_str(), *amount)?);
+    }
+    if let Some(relayer) = relayer.filter(|_| !msg.fee.is_zero()) {
+        let fee_asset = fee_asset.as_ref().unwrap_or(asset);
+        msgs.push(send_asset(fee_asset, relayer.as_str(), msg.fee)?);
+    }
+
+    Ok(msgs)
+}
+
+// Debits `fee` from the fee reserve when fees are paid in FEE_DENOM, returning
+// the denom. `check_spend` made sure the reserve covers it.
+fn take_reserve_fee(storage: &mut dyn Storage, fee: U128) -> Result<Option<String>, ContractError> {
+    let fee_denom = FEE_DENOM.may_load(storage)?;
+    if fee_denom.is_some() && !fee.is_zero() {
+        let available = FEE_RESERVE.may_load(storage)?.unwrap_or_default();
+        let reserve = available
+            .checked_sub(fee)
+            .map_err(|_| ContractError::FeeReserveTooLow { fee, available })?;
+        FEE_RESERVE.save(storage, &reserve)?;
+    }
+    Ok(fee_denom)
+}
+
+// Anyone may top up the reserve, it is only ever spent on relayer fees
+pub fn execute_fund_fees(
+    deps: DepsMut,
+    info: MessageInfo,
+    _msg: FundFeesMsg,
+) -> Result<Response, ContractError> {
+    let fee_denom = FEE_DENOM
+        .may_load(deps.storage)?
+        .ok_or(ContractError::NoFeeDenom {})?;
+    let payment = must_pay(&info, &fee_denom)?;
+
+    let reserve = FEE_RESERVE.may_load(deps.storage)?.unwrap_or_default();
+    let reserve = reserve.checked_add(payment).map_err(StdError::from)?;
+    FEE_RESERVE.save(deps.storage, &reserve)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "fund_fees")
+        .add_attribute("from", info.sender)
+        .add_attribute("amount", payment.to_string())
+        .add_attribute("reserve", reserve.to_string()))
+}
+
+pub struct CheckedWithdraw {
+    pub pool: Pool,
+    pub recipient: Addr,
+    pub relayer: Option<Addr>,
+    // what each address is paid before the fee, the recipient first
+    pub payouts: Vec<(Addr, U128)>,
+}
+
+// Every check of a withdrawal short of spending it, shared with the
+// `VerifyWithdraw` dry-run so both always agree
+pub fn check_withdraw(
+    deps: Deps,
+    env: &Env,
+    msg: &WithdrawMsg,
+) -> Result<CheckedWithdraw, ContractError> {
+    let (checked, public_signals) = check_spend(deps, env, msg, None)?;
+
+    // 3. Confirm the circuit proof, which only opens deposits: a change note's
+    // leaf holds its value where the withdraw circuit expects 0, see `change_leaf`
+    let verifier = VERIFIER.load(deps.storage)?;
+    let proof = msg.proof.clone().to_proof()?;
+    verifier.verify_proof(proof, &public_signals.get())?;
+
+    Ok(checked)
+}
+
+pub fn check_withdraw_partial(
+    deps: Deps,
+    env: &Env,
+    msg: &WithdrawPartialMsg,
+) -> Result<CheckedWithdraw, ContractError> {
+    let verifier = PARTIAL_VERIFIER
+        .may_load(deps.storage)?
+        .ok_or(ContractError::PartialWithdrawUnsupported {})?;
+
+    let (checked, public_signals) = check_spend(deps, env, &msg.withdraw, Some(msg.amount))?;
+    if msg.note_value > checked.pool.amount {
+        return Err(ContractError::AmountTooHigh {
+            amount: msg.note_value,
+            max: checked.pool.amount,
+        });
+    }
+
+    // rejected before the note is spent, record_commitment only runs after
+    if let Some(change) = change_leaf(msg)? {
+        let key = (msg.withdraw.pool_id.unwrap_or(0), change.to_string());
+        if DEPOSITED_COMMITMENTS.has(deps.storage, key) {
+            return Err(ContractError::DuplicatedCommitment {});
+        }
+    }
+
+    let public_signals = public_signals.with_change(
+        parse_commitment(&msg.change_commitment)?.to_string(),
+        checked.pool.amount,
+        msg.note_value,
+        msg.amount,
+    );
+    let proof = msg.withdraw.proof.clone().to_proof()?;
+    verifier.verify_proof(proof, &public_signals.get())?;
+
+    Ok(checked)
+}
+
+// The leaf of what's left of the note, none when it's spent in full. Its value is
+// bound here rather than by the prover, and in a form the withdraw circuit can't
+// open, so a change note is only ever spent through a partial withdrawal.
+fn change_leaf(msg: &WithdrawPartialMsg) -> Result<Option<U256>, ContractError> {
+    let commitment = parse_commitment(&msg.change_commitment)?;
+    let value =
+        msg.note_value
+            .checked_sub(msg.amount)
+            .map_err(|_| ContractError::AmountTooHigh {
+                amount: msg.amount,
+                max: msg.note_value,
+            })?;
+
+    Ok(change_commitment(&commitment, value.u128()))
+}
+
+// Every check of spending a note short of its proof, returning the public signals
+// to verify it against. `amount` is paid out of the note, all of it when None.
+fn check_spend(
+    deps: Deps,
+    env: &Env,
+    msg: &WithdrawMsg,
+    amount: Option<U128>,
+) -> Result<(CheckedWithdraw, PublicSignals), ContractError> {
+    check_withdrawals_open(deps.storage)?;
+
+    let pool = load_pool(deps.storage, msg.pool_id)?;
+    let amount = amount.unwrap_or(pool.amount);
+    if amount > pool.amount {
+        return Err(ContractError::AmountTooHigh {
+            amount,
+            max: pool.amount,
+        });
+    }
+
+    let recipient = deps.api.addr_validate(&msg.recipient)?;
+    // "" and "0" both mean no relayer, which can't be paid a fee
+    let relayer = match msg.relayer.as_str() {
+        "" | "0" => None,
+        relayer => Some(deps.api.addr_validate(relayer)?),
+    };
+    if relayer.is_none() && !msg.fee.is_zero() {
+        return Err(ContractError::FeeWithoutRelayer {});
+    }
+    if relayer.as_ref() == Some(&recipient)
+        && REJECT_SELF_RELAY.may_load(deps.storage)?.unwrap_or(false)
+    {
+        return Err(ContractError::SelfRelay {});
+    }
+    // the proof binds the recipient, so only it may receive the callback, and
+    // with its full note since the callee may expect the whole amount
+    if let Some(callback) = &msg.callback {
+        if deps.api.addr_validate(&callback.contract)? != recipient {
+            return Err(ContractError::CallbackNotRecipient {});
+        }
+        if !msg.fee.is_zero() {
+            return Err(ContractError::CallbackWithFee {});
+        }
+        if msg.recipients.is_some() {
+            return Err(ContractError::CallbackWithSplit {});
+        }
+    }
+    if let Some(max_fee) = MAX_FEE.may_load(deps.storage)? {
+        if msg.fee > max_fee {
+            return Err(ContractError::FeeAboveMaximum {
+                fee: msg.fee,
+                max_fee,
+            });
+        }
+    }
+    if let Some(relayer) = &relayer {
+        if let Some(min_fee) = RELAYERS.may_load(deps.storage, relayer.to_string())? {
+            if msg.fee < min_fee {
+                return Err(ContractError::FeeBelowMinimum {
+                    fee: msg.fee,
+                    min_fee,
+                });
+            }
+        }
+    }
+
+    // a fee in FEE_DENOM comes out of the fee reserve, not out of the note
+    let note_fee = match FEE_DENOM.may_load(deps.storage)? {
+        None => msg.fee,
+        Some(_) => {
+            let available = FEE_RESERVE.may_load(deps.storage)?.unwrap_or_default();
+            if msg.fee > available {
+                return Err(ContractError::FeeReserveTooLow {
+                    fee: msg.fee,
+                    available,
+                });
+            }
+            U128::zero()
+        }
+    };
+    // the recipient must always receive part of the note
+    if note_fee >= amount {
+        return Err(ContractError::FeesTooHigh {
+            fee: msg.fee,
+            amount,
+        });
+    }
+    let payouts = match &msg.recipients {
+        None => vec![(recipient.clone(), amount - note_fee)],
+        Some(split) => check_split(deps, split, &recipient, amount - note_fee)?,
+    };
+
+    // the proof only binds the decoded addresses, pin them to the strings paid out
+    let prefix = BECH32_PREFIX.load(deps.storage)?;
+    let payees = payouts.iter().map(|(address, _)| address);
+    for address in payees.chain(&relayer) {
+        if PublicSignals::encode_address(address.as_str(), &prefix).is_none() {
+            return Err(ContractError::InvalidSignalAddress {
+                address: address.to_string(),
+                prefix,
+            });
+        }
+    }
+
+    let root = parse_root(&msg.root)?;
+    let public_signals = PublicSignals::from_values(
+        root.to_string(),
+        parse_nullifier_hash(&msg.nullifier_hash)?,
+        recipient.to_string(),
+        msg.relayer.clone(),
+        msg.fee,
+    )?;
+    let public_signals = match &msg.recipients {
+        None => public_signals,
+        Some(_) => {
+            let payouts: Vec<(String, U128)> = payouts
+                .iter()
+                .map(|(address, amount)| (address.to_string(), *amount))
+                .collect();
+            public_signals.with_split(split_commitment_hash(&payouts)?)
+        }
+    };
+
+    // the genesis root is known but commits to no note
+    let commitment_mt = &pool.commitments;
+    if commitment_mt.is_empty() {
+        return Err(ContractError::UnknownRoot {});
+    }
+
+    // 1. check nullifier_hash is not in nullifier hashes
+    if is_spent(deps.storage, msg.pool_id, &msg.nullifier_hash)? {
+        return Err(ContractError::DuplicatedCommitment {});
+    }
+
+    // 2. confirm root is ok
+    if !is_known_root(deps.storage, msg.pool_id, commitment_mt, &root)? {
+        let key = (msg.pool_id.unwrap_or(0), root.to_string());
+        if KNOWN_ROOTS.has(deps.storage, key) {
+            return Err(ContractError::RootExpired {});
+        }
+        return Err(ContractError::UnknownRoot {});
+    }
+
+    // A root is recorded in the block of its last leaf, and both checks below
+    // age it from there. Leaves without a height (from before heights were kept)
+    // pass.
+    let min_confirmations = MIN_CONFIRMATIONS.may_load(deps.storage)?.unwrap_or(0);
+    let min_delay_blocks = MIN_DELAY_BLOCKS.may_load(deps.storage)?.unwrap_or(0);
+    let root_height = match min_confirmations.max(min_delay_blocks) {
+        0 => None,
+        _ => {
+            let last_leaf = commitment_mt
+                .known_root_size(&root)
+                .and_then(|size| size.checked_sub(1))
+                .ok_or(ContractError::UnknownRoot {})?;
+            match msg.pool_id {
+                None => LEAF_HEIGHTS.may_load(deps.storage, last_leaf)?,
+                Some(pool_id) => POOL_LEAF_HEIGHTS.may_load(deps.storage, (pool_id, last_leaf))?,
+            }
+        }
+    };
+
+    if let Some(height) = root_height {
+        // a root recorded in a block that gets reorged away may never have existed,
+        // one too close to the u64 limit is never confirmed
+        if env.block.height < height.saturating_add(min_confirmations) {
+            return Err(ContractError::RootUnconfirmed {});
+        }
+
+        // The leaf index stays private, but a root only includes deposits up to
+        // its last leaf, so requiring an aged root delays every deposit it proves
+        if env.block.height < height.saturating_add(min_delay_blocks) {
+            return Err(ContractError::TooEarly {});
+        }
+    }
+
+    Ok((
+        CheckedWithdraw {
+            pool,
+            recipient,
+            relayer,
+            payouts,
+        },
+        public_signals,
+    ))
+}
+
+// Payouts of a split withdrawal, which must add up to `expected`
+fn check_split(
+    deps: Deps,
+    split: &[Payout],
+    recipient: &Addr,
+    expected: U128,
+) -> Result<Vec<(Addr, U128)>, ContractError> {
+    check_batch_size(deps.storage, split.len())?;
+    match split.first() {
+        None => return Err(ContractError::EmptySplit {}),
+        Some(first) if first.address != recipient.as_str() => {
+            return Err(ContractError::SplitRecipient {})
+        }
+        Some(_) => {}
+    }
+
+    let mut total = U128::zero();
+    let mut payouts = Vec::with_capacity(split.len());
+    for payout in split {
+        if payout.amount.is_zero() {
+            return Err(ContractError::ZeroPayout {});
+        }
+        total = total.checked_add(payout.amount).map_err(StdError::from)?;
+        payouts.push((deps.api.addr_validate(&payout.address)?, payout.amount));
+    }
+    if total != expected {
+        return Err(ContractError::SplitMismatch { total, expected });
+    }
+
+    Ok(payouts)
+}
+
+pub fn execute_close_pool(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ClosePoolMsg,
+) -> Result<Response, ContractError> {
+    if ADMIN.load(deps.storage)? != Some(info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // blocking withdrawals while notes are still backed by funds would strand them
+    if msg.withdrawals {
+        let mut assets = vec![ASSET.load(deps.storage)?];
+        for pool in POOLS.range(deps.storage, None, None, Order::Ascending) {
+            assets.push(pool?.1.asset);
+        }
+        for asset in assets {
+            let balance = match &asset {
+                AssetInfo::Native { denom } => {
+                    deps.querier
+                        .query_balance(&env.contract.address, denom)?
+                        .amount
+                }
+                AssetInfo::Cw20 { address } => {
+                    let res: BalanceResponse = deps.querier.query_wasm_smart(
+                        address,
+                        &Cw20QueryMsg::Balance {
+                            address: env.contract.address.to_string(),
+                        },
+                    )?;
+                    res.balance
+                }
+            };
+            if !balance.is_zero() {
+                return Err(ContractError::PoolNotEmpty {
+                    amount: balance,
+                    denom: asset.denom().to_string(),
+                });
+            }
+        }
+        WITHDRAWALS_CLOSED.save(deps.storage, &true)?;
+    }
+    DEPOSITS_CLOSED.save(deps.storage, &true)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "close_pool")
+        .add_attribute("withdrawals", msg.withdrawals.to_string()))
+}
+
+pub fn execute_create_pool(
+    deps: DepsMut,
+    info: MessageInfo,
+    msg: CreatePoolMsg,
+) -> Result<Response, ContractError> {
+    if ADMIN.load(deps.storage)? != Some(info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let asset = match msg.asset {
+        AssetInfo::Cw20 { address } => AssetInfo::Cw20 {
+            address: deps.api.addr_validate(&address)?.into_string(),
+        },
+        native => native,
+    };
+    validate_pool(&asset, msg.amount)?;
+    // same circuit as the instantiate pool
+    let zero_value = COMMITMENTS.load(deps.storage)?.ZERO_VALUE;
+    let commitments = MerkleTreeWithHistory::try_new(
+        msg.levels.unwrap_or(DEFAULT_LEVELS),
+        msg.root_history_size.unwrap_or(ROOT_HISTORY_SIZE),
+        Some(zero_value),
+    )?;
+
+    // id 0 is never handed out, the instantiate pool is addressed with `None`
+    let pool_id = NEXT_POOL_ID.may_load(deps.storage)?.unwrap_or(1);
+    NEXT_POOL_ID.save(deps.storage, &(pool_id + 1))?;
+    index_known_roots(deps.storage, Some(pool_id), &commitments)?;
+    POOLS.save(
+        deps.storage,
+        pool_id,
+        &Pool {
+            asset,
+            amount: msg.amount,
+            commitments,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "create_pool")
+        .add_attribute("pool_id", pool_id.to_string()))
+}
+
+pub fn execute_register_relayer(
+    deps: DepsMut,
+    info: MessageInfo,
+    msg: RegisterRelayerMsg,
+) -> Result<Response, ContractError> {
+    if ADMIN.load(deps.storage)? != Some(info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let address = deps.api.addr_validate(&msg.address)?;
+    RELAYERS.save(deps.storage, address.to_string(), &msg.min_fee)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "register_relayer")
+        .add_attribute("relayer", address)
+        .add_attribute("min_fee", msg.min_fee.to_string()))
+}
+
+// A pool must be payable with `must_pay`, so a non-zero amount of a valid
+// cosmos denom: [a-zA-Z][a-zA-Z0-9/:._-]{2,127}
+fn validate_pool(asset: &AssetInfo, amount: U128) -> Result<(), ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmount {});
+    }
+    if let AssetInfo::Native { denom } = asset {
+        validate_denom(denom)?;
+    }
+    Ok(())
+}
+
+fn validate_denom(denom: &str) -> Result<(), ContractError> {
+    let valid = (3..=128).contains(&denom.len())
+        && denom.starts_with(|c: char| c.is_ascii_alphabetic())
+        && denom
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/:._-".contains(c));
+    if !valid {
+        return Err(ContractError::InvalidDenom {
+            denom: denom.to_string(),
+        });
+    }
+    Ok(())
+}
+
+// The instantiate pool (`None`) is kept in the original items, so existing instances
+// keep their tree. `migrate` fills in the items added since.
+fn load_pool(storage: &dyn Storage, pool_id: Option<u32>) -> Result<Pool, ContractError> {
+    match pool_id {
+        None => Ok(Pool {
+            asset: ASSET.load(storage)?,
+            amount: BASE_COIN.load(storage)?.amount,
+            commitments: COMMITMENTS.load(storage)?,
+        }),
+        Some(pool_id) => POOLS
+            .may_load(storage, pool_id)?
+            .ok_or(ContractError::UnknownPool { pool_id }),
+    }
+}
+
+// Inserts into a pool's tree, recording its new root in KNOWN_ROOTS. The evicted
+// root keeps its entry, its size tells it has expired.
+fn insert_leaf(
+    storage: &mut dyn Storage,
+    pool_id: Option<u32>,
+    tree: &mut MerkleTreeWithHistory,
+    leaf: &U256,
+) -> Result<u32, ContractError> {
+    let leaf_index = tree.insert(leaf)?;
+
+    let key = (pool_id.unwrap_or(0), tree.get_last_root().to_string());
+    KNOWN_ROOTS.save(storage, key, &tree.next_index)?;
+
+    Ok(leaf_index)
+}
+
+// Records the root history of `tree` in KNOWN_ROOTS. Oldest first, so a root
+// held in two slots keeps the size of the latest.
+fn index_known_roots(
+    storage: &mut dyn Storage,
+    pool_id: Option<u32>,
+    tree: &MerkleTreeWithHistory,
+) -> StdResult<()> {
+    let pool_id = pool_id.unwrap_or(0);
+    for (root, size) in tree.root_history() {
+        KNOWN_ROOTS.save(storage, (pool_id, root.to_string()), &size)?;
+    }
+    Ok(())
+}
+
+// Same answer as `MerkleTreeWithHistory::is_known_root`, without scanning the history
+fn is_known_root(
+    storage: &dyn Storage,
+    pool_id: Option<u32>,
+    tree: &MerkleTreeWithHistory,
+    root: &U256,
+) -> StdResult<bool> {
+    if root.is_zero() {
+        return Ok(false);
+    }
+    let size = KNOWN_ROOTS.may_load(storage, (pool_id.unwrap_or(0), root.to_string()))?;
+    Ok(matches!(size, Some(size) if size >= tree.oldest_root_size()))
+}
+
+// Every leaf of a pool, in insertion order, erroring on the first gap
+fn load_leaves(storage: &dyn Storage, pool_id: Option<u32>) -> Result<Vec<U256>, ContractError> {
+    let leaves: Vec<(u32, String)> = match pool_id {
+        None => LEAVES
+            .range(storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?,
+        Some(pool_id) => POOL_LEAVES
+            .prefix(pool_id)
+            .range(storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?,
+    };
+
+    let mut loaded = Vec::with_capacity(leaves.len());
+    for (leaf_index, leaf) in leaves {
+        if leaf_index as usize != loaded.len() {
+            return Err(ContractError::MissingLeaf {
+                leaf_index: loaded.len() as u32,
+            });
+        }
+        loaded.push(U256::from_str(&leaf)?);
+    }
+    Ok(loaded)
+}
+
+fn save_pool(storage: &mut dyn Storage, pool_id: Option<u32>, pool: &Pool) -> StdResult<()> {
+    match pool_id {
+        None => COMMITMENTS.save(storage, &pool.commitments),
+        Some(pool_id) => POOLS.save(storage, pool_id, pool),
+    }
+}
+
+fn save_leaf(
+    storage: &mut dyn Storage,
+    pool_id: Option<u32>,
+    leaf_index: u32,
+    commitment: &String,
+    block: &BlockInfo,
+) -> StdResult<()> {
+    let time = block.time.seconds();
+    match pool_id {
+        None => {
+            LEAVES.save(storage, leaf_index, commitment)?;
+            LEAF_HEIGHTS.save(storage, leaf_index, &block.height)?;
+            LEAF_TIMES.save(storage, leaf_index, &time)
+        }
+        Some(pool_id) => {
+            POOL_LEAVES.save(storage, (pool_id, leaf_index), commitment)?;
+            POOL_LEAF_HEIGHTS.save(storage, (pool_id, leaf_index), &block.height)?;
+            POOL_LEAF_TIMES.save(storage, (pool_id, leaf_index), &time)
+        }
+    }
+}
+
+// The circuit would reduce an out-of-field commitment, while the tree stores it as is
+fn parse_commitment(commitment: &str) -> Result<U256, ContractError> {
+    let value = U256::from_str(commitment)?;
+    if !is_in_field(&value) {
+        return Err(ContractError::ParseError {
+            msg: format!(
+                "Commitment {} is outside the BN254 scalar field",
+                commitment
+            ),
+        });
+    }
+    Ok(value)
+}
+
+// Each commitment is accepted once per pool, compared in canonical decimal form
+// `leaf_index` is the index the tree inserted the commitment at
+fn record_commitment(
+    storage: &mut dyn Storage,
+    pool_id: Option<u32>,
+    commitment: &str,
+    leaf_index: u32,
+) -> Result<U256, ContractError> {
+    let commitment = parse_commitment(commitment)?;
+    let key = (pool_id.unwrap_or(0), commitment.to_string());
+    if DEPOSITED_COMMITMENTS.has(storage, key.clone()) {
+        return Err(ContractError::DuplicatedCommitment {});
+    }
+    DEPOSITED_COMMITMENTS.save(storage, key, &leaf_index)?;
+
+    Ok(commitment)
+}
+
+// Nullifier hashes are keyed in canonical decimal form, so no other encoding of
+// a spent hash (leading zeros, hex, adding the modulus) can spend its note again
+fn parse_nullifier_hash(nullifier_hash: &str) -> Result<String, ContractError> {
+    match parse_u256(nullifier_hash) {
+        Ok(value) if is_in_field(&value) => Ok(value.to_string()),
+        _ => Err(ContractError::ParseError {
+            msg: format!(
+                "Nullifier hash {} isn't a BN254 scalar field element",
+                nullifier_hash
+            ),
+        }),
+    }
+}
+
+fn is_spent(
+    storage: &dyn Storage,
+    pool_id: Option<u32>,
+    nullifier_hash: &str,
+) -> Result<bool, ContractError> {
+    let nullifier_hash = parse_nullifier_hash(nullifier_hash)?;
+    Ok(match pool_id {
+        None => NULLIFIER_HASHES.has(storage, nullifier_hash),
+        Some(pool_id) => POOL_NULLIFIER_HASHES.has(storage, (pool_id, nullifier_hash)),
+    })
+}
+
+fn mark_spent(
+    storage: &mut dyn Storage,
+    pool_id: Option<u32>,
+    nullifier_hash: &str,
+) -> Result<(), ContractError> {
+    let nullifier_hash = parse_nullifier_hash(nullifier_hash)?;
+    match pool_id {
+        None => NULLIFIER_HASHES.save(storage, nullifier_hash, &true)?,
+        Some(pool_id) => POOL_NULLIFIER_HASHES.save(storage, (pool_id, nullifier_hash), &true)?,
+    }
+    Ok(())
+}
+
+fn count_withdrawal(storage: &mut dyn Storage, pool_id: Option<u32>) -> StdResult<()> {
+    let count = WITHDRAWAL_COUNT.may_load(storage)?.unwrap_or(0);
+    WITHDRAWAL_COUNT.save(storage, &(count + 1))?;
+    let pool_id = pool_id.unwrap_or(0);
+    let pool_count = POOL_WITHDRAWAL_COUNTS
+        .may_load(storage, pool_id)?
+        .unwrap_or(0);
+    POOL_WITHDRAWAL_COUNTS.save(storage, pool_id, &(pool_count + 1))
+}
+
+// Roots are accepted as decimal or 0x prefixed big-endian hex
+fn parse_root(root: &str) -> Result<U256, ContractError> {
+    parse_u256(root).map_err(|err| ContractError::ParseError {
+        msg: format!("Root {}: {}", root, err),
+    })
+}
+
+fn send_asset(asset: &AssetInfo, to: &str, amount: U128) -> StdResult<CosmosMsg> {
+    let msg = match asset {
+        AssetInfo::Native { denom } => BankMsg::Send {
+            to_address: to.to_string(),
+            amount: vec![Coin {
+                denom: denom.clone(),
+                amount,
+            }],
+        }
+        .into(),
+        AssetInfo::Cw20 { address } => WasmMsg::Execute {
+            contract_addr: address.clone(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: to.to_string(),
+                amount,
+            })?,
+            funds: vec![],
+        }
+        .into(),
+    };
+    Ok(msg)
+}
+
+// Like `send_asset`, but executes `msg` on the receiving contract, via cw20 `Send`
+// for tokens
+fn send_asset_with_callback(
+    asset: &AssetInfo,
+    contract: &str,
+    amount: U128,
+    msg: Binary,
+) -> StdResult<CosmosMsg> {
+    let msg = match asset {
+        AssetInfo::Native { denom } => WasmMsg::Execute {
+            contract_addr: contract.to_string(),
+            msg,
+            funds: vec![Coin {
+                denom: denom.clone(),
+                amount,
+            }],
+        },
+        AssetInfo::Cw20 { address } => WasmMsg::Execute {
+            contract_addr: address.clone(),
+            msg: to_binary(&Cw20ExecuteMsg::Send {
+                contract: contract.to_string(),
+                amount,
+                msg,
+            })?,
+            funds: vec![],
+        },
+    };
+    Ok(msg.into())
+}
+
+fn check_deposits_open(storage: &dyn Storage) -> Result<(), ContractError> {
+    if DEPOSITS_CLOSED.may_load(storage)?.unwrap_or(false) {
+        return Err(ContractError::PoolClosed {});
+    }
+    if PAUSED.may_load(storage)?.unwrap_or_default().deposits {
+        return Err(ContractError::Paused {});
+    }
+    Ok(())
+}
+
+// Change notes of partial withdrawals count as deposits but are never refused
+fn check_max_deposits(
+    storage: &dyn Storage,
+    tree: &MerkleTreeWithHistory,
+) -> Result<(), ContractError> {
+    if let Some(max_deposits) = MAX_DEPOSITS.may_load(storage)? {
+        if tree.next_index >= max_deposits {
+            return Err(ContractError::PoolFull {});
+        }
+    }
+    Ok(())
+}
+
+fn check_withdrawals_open(storage: &dyn Storage) -> Result<(), ContractError> {
+    if WITHDRAWALS_CLOSED.may_load(storage)?.unwrap_or(false) {
+        return Err(ContractError::PoolClosed {});
+    }
+    if PAUSED.may_load(storage)?.unwrap_or_default().withdrawals {
+        return Err(ContractError::Paused {});
+    }
+    Ok(())
+}
+
+pub fn execute_set_paused(
+    deps: DepsMut,
+    info: MessageInfo,
+    msg: SetPausedMsg,
+) -> Result<Response, ContractError> {
+    if ADMIN.load(deps.storage)? != Some(info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    PAUSED.save(
+        deps.storage,
+        &PauseState {
+            deposits: msg.deposits,
+            withdrawals: msg.withdrawals,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_paused")
+        .add_attribute("deposits", msg.deposits.to_string())
+        .add_attribute("withdrawals", msg.withdrawals.to_string()))
+}
+
+// Bounds the gas used by a single batch message
+fn check_batch_size(storage: &dyn Storage, len: usize) -> Result<(), ContractError> {
+    let max = MAX_BATCH_SIZE.load(storage)?;
+    if len > max as usize {
+        return Err(ContractError::BatchTooLarge { max });
+    }
+    Ok(())
+}
+
+pub fn execute_commit_withdraw(
+    deps: DepsMut,
+    env: Env,
+    msg: CommitWithdrawMsg,
+) -> Result<Response, ContractError> {
+    let commitment_hash = U256::from_str(&msg.commitment_hash)?.to_string();
+
+    // every commit drops a few expired ones, so abandoned commits don't pile up
+    let pruned = prune_withdraw_commits(deps.storage, env.block.height)?;
+
+    if WITHDRAW_COMMITS.has(deps.storage, commitment_hash.clone()) {
+        return Err(ContractError::DuplicatedWithdrawCommitment {});
+    }
+    WITHDRAW_COMMITS.save(deps.storage, commitment_hash.clone(), &env.block.height)?;
+    WITHDRAW_COMMIT_HEIGHTS.save(deps.storage, (env.block.height, commitment_hash), &true)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "commit_withdraw")
+        .add_attribute("pruned", pruned.to_string()))
+}
+
+fn withdraw_commit_expiry(storage: &dyn Storage) -> StdResult<u64> {
+    Ok(WITHDRAW_COMMIT_EXPIRY
+        .may_load(storage)?
+        .unwrap_or(DEFAULT_WITHDRAW_COMMIT_EXPIRY))
+}
+
+// Removes up to MAX_BATCH_SIZE commits past their expiry, oldest first
+fn prune_withdraw_commits(storage: &mut dyn Storage, height: u64) -> StdResult<u32> {
+    let expiry = withdraw_commit_expiry(storage)?;
+    let cutoff = match height.checked_sub(expiry) {
+        Some(cutoff) => cutoff,
+        None => return Ok(0),
+    };
+    let max = MAX_BATCH_SIZE.load(storage)?;
+    let expired: Vec<(u64, String)> = WITHDRAW_COMMIT_HEIGHTS
+        .keys(
+            storage,
+            None,
+            Some(Bound::exclusive((cutoff, String::new()))),
+            Order::Ascending,
+        )
+        .take(max as usize)
+        .collect::<StdResult<_>>()?;
+
+    for (committed_at, commitment_hash) in &expired {
+        WITHDRAW_COMMIT_HEIGHTS.remove(storage, (*committed_at, commitment_hash.clone()));
+        WITHDRAW_COMMITS.remove(storage, commitment_hash.clone());
+    }
+
+    Ok(expired.len() as u32)
+}
+
+pub fn execute_reveal_withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: RevealWithdrawMsg,
+) -> Result<Response, ContractError> {
+    deps.api.addr_validate(&msg.withdraw.recipient)?;
+
+    let commitment_hash = withdraw_commitment_hash(
+        &msg.withdraw.nullifier_hash,
+        &msg.withdraw.recipient,
+        &msg.salt,
+    )?;
+
+    // the commit must come from a prior block, otherwise it could have been
+    // submitted alongside a front-running reveal
+    let committed_at = WITHDRAW_COMMITS
+        .may_load(deps.storage, commitment_hash.clone())?
+        .ok_or(ContractError::UnknownWithdrawCommitment {})?;
+    if committed_at >= env.block.height {
+        return Err(ContractError::WithdrawCommitmentTooRecent {});
+    }
+    let expiry = withdraw_commit_expiry(deps.storage)?;
+    if committed_at.saturating_add(expiry) < env.block.height {
+        return Err(ContractError::WithdrawCommitmentExpired {});
+    }
+    WITHDRAW_COMMITS.remove(deps.storage, commitment_hash.clone());
+    WITHDRAW_COMMIT_HEIGHTS.remove(deps.storage, (committed_at, commitment_hash));
+
+    withdraw(deps, env, info, msg.withdraw)
+}
+
+pub fn execute_import_nullifiers(
+    deps: DepsMut,
+    info: MessageInfo,
+    msg: ImportNullifiersMsg,
+) -> Result<Response, ContractError> {
+    if ADMIN.load(deps.storage)? != Some(info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    check_batch_size(deps.storage, msg.hashes.len())?;
+
+    // once deposits are accepted, imported nullifiers could collide with live notes
+    let pool = load_pool(deps.storage, msg.pool_id)?;
+    if pool.commitments.next_index != 0 {
+        return Err(ContractError::PoolAlreadyActive {});
+    }
+
+    for nullifier_hash in &msg.hashes {
+        mark_spent(deps.storage, msg.pool_id, nullifier_hash)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "import_nullifiers")
+        .add_attribute("count", msg.hashes.len().to_string()))
+}
+
+pub fn execute_import_tree(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ImportTreeMsg,
+) -> Result<Response, ContractError> {
+    if ADMIN.load(deps.storage)? != Some(info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut pool = load_pool(deps.storage, msg.pool_id)?;
+    if !pool.commitments.is_empty() {
+        return Err(ContractError::PoolAlreadyActive {});
+    }
+
+    // proofs are only valid for the circuit's depth, empty leaf and hash
+    let mut tree = MerkleTreeWithHistory::import(msg.snapshot)?;
+    if tree.levels != pool.commitments.levels
+        || tree.ZERO_VALUE != pool.commitments.ZERO_VALUE
+        || tree.poseidon_params != pool.commitments.poseidon_params
+    {
+        return Err(ContractError::SnapshotMismatch {});
+    }
+    // a full tree has no edge left, `import` checked its root against its leaves
+    if matches!(tree.edge_root(), Some(root) if root != tree.get_last_root()) {
+        return Err(ContractError::InvalidSnapshot {});
+    }
+
+    // leaves are kept in LEAVES like deposits, not in the stored tree
+    for (leaf_index, leaf) in tree.leaves.take().unwrap_or_default().iter().enumerate() {
+        let commitment = leaf.to_string();
+        record_commitment(deps.storage, msg.pool_id, &commitment, leaf_index as u32)?;
+        save_leaf(
+            deps.storage,
+            msg.pool_id,
+            leaf_index as u32,
+            &commitment,
+            &env.block,
+        )?;
+    }
+
+    let next_index = tree.next_index;
+    let root = tree.get_last_root();
+    index_known_roots(deps.storage, msg.pool_id, &tree)?;
+    pool.commitments = tree;
+    save_pool(deps.storage, msg.pool_id, &pool)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "import_tree")
+        .add_attribute("next_index", next_index.to_string())
+        .add_attribute("root", root.to_string()))
+}
+
+// Recovers a tree whose state drifted from its leaves, LEAVES being authoritative
+pub fn execute_rebuild_tree(
+    deps: DepsMut,
+    info: MessageInfo,
+    msg: RebuildTreeMsg,
+) -> Result<Response, ContractError> {
+    if ADMIN.load(deps.storage)? != Some(info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut pool = load_pool(deps.storage, msg.pool_id)?;
+    let previous_root = pool.commitments.get_last_root();
+    // a missing leaf would silently drop its deposit from the rebuilt tree
+    let leaves = load_leaves(deps.storage, msg.pool_id)?;
+    if leaves.len() != pool.commitments.next_index as usize {
+        return Err(ContractError::MissingLeaf {
+            leaf_index: leaves.len() as u32,
+        });
+    }
+    pool.commitments.leaves = Some(leaves);
+    pool.commitments.rebuild_from_leaves()?;
+    pool.commitments.leaves = None;
+
+    // only the tree's edge is repaired, the leaves must still add up to a root
+    // the pool has recorded
+    let root = pool.commitments.get_last_root();
+    if !is_known_root(deps.storage, msg.pool_id, &pool.commitments, &root)? {
+        return Err(ContractError::UnknownRoot {});
+    }
+    // the drifted root was never a root of the pool's leaves
+    if previous_root != root {
+        let key = (msg.pool_id.unwrap_or(0), previous_root.to_string());
+        KNOWN_ROOTS.remove(deps.storage, key);
+    }
+    index_known_roots(deps.storage, msg.pool_id, &pool.commitments)?;
+    save_pool(deps.storage, msg.pool_id, &pool)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "rebuild_tree")
+        .add_attribute("next_index", pool.commitments.next_index.to_string())
+        .add_attribute("previous_root", previous_root.to_string())
+        .add_attribute("root", root.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_dependencies_with_balance, mock_env, mock_info,
+    };
+    use cosmwasm_std::{attr, coin, coins, from_binary, from_slice, SubMsg};
+    use cw20::Cw20Coin;
+    use cw_multi_test::{App, ContractWrapper, Executor};
+    use cw_utils::PaymentError;
+
+    use crate::events::SCHEMA_VERSION;
+    use crate::msg::WithdrawCallback;
+    use juicer::bignum::{u256_to_hex, FIELD_MODULUS};
+    use juicer::cli;
+    use juicer::merkle_tree::{default_zero_value, field_modulus, MerklePath};
+    use juicer::msg::{Deposit, ProofError, ProofInput};
+    use juicer::poseidon::PoseidonParams;
+    use juicer::testing::{g1_multiple, CannedProver, ProofBuilder, WithdrawProof};
+    use juicer::verifier::VerifyingKeyJson;
+
+    use super::*;
+
+    #[test]
+    fn test_deposit() {
+        let mut deps = mock_dependencies();
+        let info = mock_info("Alice", &coins(10, "TKN"));
+
+        // let deposit = generate_deposit();
+        let deposit = Deposit::new(
+            "276277773929387392791096474084808108569850403587654342680891529007770412737"
+                .to_string(),
+        );
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {
+            amount: U128::from(10_u128),
+            denom: "TKN".to_string(),
+            ..Default::default()
+        };
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        let deposit_msg = DepositMsg {
+            commitment: deposit.get_commitment(),
+            pool_id: None,
+        };
+
+        let msg = ExecuteMsg::Deposit(deposit_msg.clone());
+
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // TODO
+        // let commitment = COMMITMENTS
+        //     .load(&deps.storage, deposit.get_commitment())
+        //     .unwrap();
+        // assert_eq!(Uint128::new(10), commitment);
+    }
+
+    #[test]
+    fn test_withdraw_1() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {
+            amount: U128::from(10_u128),
+            denom: "TKN".to_string(),
+            ..Default::default()
+        };
+        let info = mock_info("Alice", &[]);
+
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        let mut tree = COMMITMENTS.load(&deps.storage).unwrap();
+
+        let deposit = Deposit {
+            nullifier: "54154714943715201094961901040590459639892306160131965986154511512546000403"
+                .to_string(),
+        };
+
+        let leaf = U256::from_str(&deposit.clone().get_commitment()).unwrap();
+        let leaf_index = insert_leaf(&mut deps.storage, None, &mut tree, &leaf).unwrap();
+
+        COMMITMENTS.save(&mut deps.storage, &tree).unwrap();
+
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            proof: juicer::msg::CircomProof::from(
+                r#"
+                {"pi_a":["13899269723484849480002065473374493568327469679987898626585656783152635224196","4644776364206331144208370772102729462540382294894335687634266360911567618285","1"],"pi_b":[["11550199660326834097658136558533988234178757731057308044978347076813572730094","2682881763463105242359875271001109719339722524261167828167916342514182934974"],["95039516498389015079170513998234052571784823209713661742933740886373624805","3428917488231875962754312177544595651247105738928930070869265869601586471119"],["1","0"]],"pi_c":["18932896497737520548726210332000803585517357164811625711564892288268655803594","3898942506810745753991535926637360084087400921771473613166702262820083122159","1"],"protocol":"groth16","curve":"bn128"}
+                "#.to_string(),
+            ).into(),
+            root: "7867364560627547019086598689541673085228895175200585554350937642876639323043".to_string(),
+            nullifier_hash: deposit.get_nullifier_hash((leaf_index) as u128),
+            recipient: "juno14spgzl9ps5tyev32ny74fa6m0s9q9828v0vrga".to_string(),
+            relayer: "juno1am5sw4geda8xfvmn4pkzruhv8ah0l3jx5hgchh".to_string(),
+            fee: U128::zero(),
+            pool_id: None,
+            callback: None,
+            recipients: None,
+        });
+        let info = mock_info("Alice", &[]);
+
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(1, res.messages.len());
+    }
+
+    fn default_instantiate_msg() -> InstantiateMsg {
+        InstantiateMsg {
+            amount: U128::from(10_u128),
+            denom: "TKN".to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Instantiates a 10 TKN pool holding the single deposit used by
+    // `test_withdraw_1`, and returns the matching withdraw message.
+    fn setup_withdraw(mut deps: DepsMut, instantiate_msg: InstantiateMsg) -> WithdrawMsg {
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.branch(), mock_env(), info, instantiate_msg).unwrap();
+
+        insert_withdraw_fixture(deps)
+    }
+
+    // Inserts the deposit used by `test_withdraw_1` into an instantiated pool,
+    // and returns the matching withdraw message.
+    fn insert_withdraw_fixture(deps: DepsMut) -> WithdrawMsg {
+        let (deposit, withdraw_msg) = withdraw_fixture();
+
+        let mut tree = COMMITMENTS.load(deps.storage).unwrap();
+        let leaf = U256::from_str(&deposit.clone().get_commitment()).unwrap();
+        let leaf_index = insert_leaf(deps.storage, None, &mut tree, &leaf).unwrap();
+        COMMITMENTS.save(deps.storage, &tree).unwrap();
+
+        WithdrawMsg {
+            nullifier_hash: deposit.get_nullifier_hash(leaf_index as u128),
+            ..withdraw_msg
+        }
+    }
+
+    // The deposit used by `test_withdraw_1`, and its withdraw message once
+    // inserted as the first leaf of a fresh pool.
+    fn withdraw_fixture() -> (Deposit, WithdrawMsg) {
+        let deposit = Deposit {
+            nullifier: "54154714943715201094961901040590459639892306160131965986154511512546000403"
+                .to_string(),
+        };
+
+        let withdraw_msg = WithdrawMsg {
+            proof: juicer::msg::CircomProof::from(
+                r#"
+                {"pi_a":["13899269723484849480002065473374493568327469679987898626585656783152635224196","4644776364206331144208370772102729462540382294894335687634266360911567618285","1"],"pi_b":[["11550199660326834097658136558533988234178757731057308044978347076813572730094","2682881763463105242359875271001109719339722524261167828167916342514182934974"],["95039516498389015079170513998234052571784823209713661742933740886373624805","3428917488231875962754312177544595651247105738928930070869265869601586471119"],["1","0"]],"pi_c":["18932896497737520548726210332000803585517357164811625711564892288268655803594","3898942506810745753991535926637360084087400921771473613166702262820083122159","1"],"protocol":"groth16","curve":"bn128"}
+                "#.to_string(),
+            ).into(),
+            root: "7867364560627547019086598689541673085228895175200585554350937642876639323043".to_string(),
+            nullifier_hash: deposit.clone().get_nullifier_hash(0),
+            recipient: "juno14spgzl9ps5tyev32ny74fa6m0s9q9828v0vrga".to_string(),
+            relayer: "juno1am5sw4geda8xfvmn4pkzruhv8ah0l3jx5hgchh".to_string(),
+            fee: U128::zero(),
+            pool_id: None,
+            callback: None,
+            recipients: None,
+        };
+
+        (deposit, withdraw_msg)
+    }
+
+    fn cw20_instantiate_msg(token: &str) -> InstantiateMsg {
+        InstantiateMsg {
+            amount: U128::from(10_u128),
+            asset: Some(AssetInfo::Cw20 {
+                address: token.to_string(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cw20_deposit_withdraw() {
+        let mut app = App::default();
+
+        let cw20_id = app.store_code(Box::new(ContractWrapper::new(
+            cw20_base::contract::execute,
+            cw20_base::contract::instantiate,
+            cw20_base::contract::query,
+        )));
+        let juicer_id = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+
+        let owner = Addr::unchecked("owner");
+        let alice = Addr::unchecked("alice");
+        let token = app
+            .instantiate_contract(
+                cw20_id,
+                owner.clone(),
+                &cw20_base::msg::InstantiateMsg {
+                    name: "Juicer Token".to_string(),
+                    symbol: "JUICE".to_string(),
+                    decimals: 6,
+                    initial_balances: vec![Cw20Coin {
+                        address: alice.to_string(),
+                        amount: U128::from(10_u128),
+                    }],
+                    mint: None,
+                    marketing: None,
+                },
+                &[],
+                "token",
+                None,
+            )
+            .unwrap();
+        let juicer = app
+            .instantiate_contract(
+                juicer_id,
+                owner,
+                &cw20_instantiate_msg(token.as_str()),
+                &[],
+                "juicer",
+                None,
+            )
+            .unwrap();
+
+        let balance = |app: &App, address: &str| -> U128 {
+            let res: BalanceResponse = app
+                .wrap()
+                .query_wasm_smart(
+                    &token,
+                    &Cw20QueryMsg::Balance {
+                        address: address.to_string(),
+                    },
+                )
+                .unwrap();
+            res.balance
+        };
+
+        let (deposit, withdraw_msg) = withdraw_fixture();
+        let msg = Cw20ExecuteMsg::Send {
+            contract: juicer.to_string(),
+            amount: U128::from(10_u128),
+            msg: to_binary(&ReceiveMsg::Deposit(DepositMsg {
+                commitment: deposit.get_commitment(),
+                pool_id: None,
+            }))
+            .unwrap(),
+        };
+        app.execute_contract(alice.clone(), token.clone(), &msg, &[])
+            .unwrap();
+        assert_eq!(balance(&app, alice.as_str()), U128::zero());
+        assert_eq!(balance(&app, juicer.as_str()), U128::from(10_u128));
+
+        let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+        app.execute_contract(Addr::unchecked("relayer"), juicer.clone(), &msg, &[])
+            .unwrap();
+        assert_eq!(balance(&app, juicer.as_str()), U128::zero());
+        assert_eq!(balance(&app, &withdraw_msg.recipient), U128::from(10_u128));
+    }
+
+    #[test]
+    fn test_cw20_pool_rejects_other_assets() {
+        let mut deps = mock_dependencies();
+
+        let info = mock_info("Alice", &[]);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            cw20_instantiate_msg("token"),
+        )
+        .unwrap();
+
+        let deposit_msg = DepositMsg {
+            commitment: Deposit::new("1".to_string()).get_commitment(),
+            pool_id: None,
+        };
+
+        // native coins
+        let msg = ExecuteMsg::Deposit(deposit_msg.clone());
+        let info = mock_info("Alice", &coins(10, "token"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::WrongAsset {});
+
+        // another CW20 token
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "Alice".to_string(),
+            amount: U128::from(10_u128),
+            msg: to_binary(&ReceiveMsg::Deposit(deposit_msg)).unwrap(),
+        });
+        let info = mock_info("other_token", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::WrongAsset {});
+    }
+
+    #[test]
+    fn test_commit_reveal_withdraw() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let salt = "1337".to_string();
+        let commitment_hash =
+            withdraw_commitment_hash(&withdraw_msg.nullifier_hash, &withdraw_msg.recipient, &salt)
+                .unwrap();
+
+        let env = mock_env();
+        let msg = ExecuteMsg::CommitWithdraw(CommitWithdrawMsg { commitment_hash });
+        let info = mock_info("Alice", &[]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+        let msg = ExecuteMsg::RevealWithdraw(RevealWithdrawMsg {
+            withdraw: withdraw_msg.clone(),
+            salt,
+        });
+
+        // revealing in the same block is rejected
+        let info = mock_info("Bob", &[]);
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::WithdrawCommitmentTooRecent {});
+
+        // a malformed reveal is an error, not a panic
+        let bad_salt = ExecuteMsg::RevealWithdraw(RevealWithdrawMsg {
+            withdraw: withdraw_msg,
+            salt: "salt".to_string(),
+        });
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), bad_salt).unwrap_err();
+        assert!(matches!(err, ContractError::ParseError { .. }));
+
+        let mut env = env;
+        env.block.height += 1;
+        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        let commits = WITHDRAW_COMMIT_HEIGHTS.keys(&deps.storage, None, None, Order::Ascending);
+        assert_eq!(commits.count(), 0);
+    }
+
+    #[test]
+    fn test_commit_reveal_required() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(
+            deps.as_mut(),
+            InstantiateMsg {
+                require_commit_reveal: true,
+                ..default_instantiate_msg()
+            },
+        );
+
+        let info = mock_info("Alice", &[]);
+        let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::CommitRevealRequired {});
+
+        // relayers checking first are told the same
+        let msg = QueryMsg::VerifyWithdraw(withdraw_msg.clone());
+        let res: VerifyWithdrawResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(
+            res,
+            VerifyWithdrawResponse {
+                valid: false,
+                reason: Some(ContractError::CommitRevealRequired {}.to_string()),
+            }
+        );
+
+        let salt = "1337".to_string();
+        let commitment_hash =
+            withdraw_commitment_hash(&withdraw_msg.nullifier_hash, &withdraw_msg.recipient, &salt)
+                .unwrap();
+        let msg = ExecuteMsg::CommitWithdraw(CommitWithdrawMsg { commitment_hash });
+        let info = mock_info("Alice", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let mut env = mock_env();
+        env.block.height += 1;
+        let msg = ExecuteMsg::RevealWithdraw(RevealWithdrawMsg {
+            withdraw: withdraw_msg,
+            salt,
+        });
+        let info = mock_info("Bob", &[]);
+        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+        assert_eq!(1, res.messages.len());
+
+        // a partial withdrawal can't spend a note in one step either
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(
+            deps.as_mut(),
+            InstantiateMsg {
+                require_commit_reveal: true,
+                partial_verification_key: Some(mock_partial_vk()),
+                ..default_instantiate_msg()
+            },
+        );
+        let msg = ExecuteMsg::WithdrawWithChange(WithdrawPartialMsg {
+            withdraw: WithdrawMsg {
+                proof: mock_proof(10),
+                ..withdraw_msg.clone()
+            },
+            change_commitment: Deposit::new("1".to_string()).get_commitment(),
+            note_value: U128::from(10_u128),
+            amount: U128::from(4_u128),
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::CommitRevealRequired {});
+        assert!(!is_spent(&deps.storage, None, &withdraw_msg.nullifier_hash).unwrap());
+    }
+
+    #[test]
+    fn test_withdraw_commit_expiry() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(
+            deps.as_mut(),
+            InstantiateMsg {
+                withdraw_commit_expiry: Some(10),
+                max_batch_size: Some(2),
+                ..default_instantiate_msg()
+            },
+        );
+        let commit = |deps: DepsMut, height: u64, commitment_hash: &str| {
+            let mut env = mock_env();
+            env.block.height = height;
+            let msg = ExecuteMsg::CommitWithdraw(CommitWithdrawMsg {
+                commitment_hash: commitment_hash.to_string(),
+            });
+            execute(deps, env, mock_info("Alice", &[]), msg)
+        };
+
+        let salt = "1337".to_string();
+        let commitment_hash =
+            withdraw_commitment_hash(&withdraw_msg.nullifier_hash, &withdraw_msg.recipient, &salt)
+                .unwrap();
+        commit(deps.as_mut(), 100, &commitment_hash).unwrap();
+        for hash in ["1", "2", "3"] {
+            commit(deps.as_mut(), 101, hash).unwrap();
+        }
+
+        // revealable up to 10 blocks later
+        let msg = ExecuteMsg::RevealWithdraw(RevealWithdrawMsg {
+            withdraw: withdraw_msg,
+            salt,
+        });
+        let mut env = mock_env();
+        env.block.height = 111;
+        let info = mock_info("Bob", &[]);
+        let err = execute(deps.as_mut(), env, info, msg).unwrap_err();
+        assert_eq!(err, ContractError::WithdrawCommitmentExpired {});
+
+        // later commits drop expired ones, at most a batch at a time
+        let res = commit(deps.as_mut(), 112, "4").unwrap();
+        assert!(res.attributes.contains(&attr("pruned", "2")));
+        assert!(!WITHDRAW_COMMITS.has(&deps.storage, commitment_hash));
+        let res = commit(deps.as_mut(), 112, "5").unwrap();
+        assert!(res.attributes.contains(&attr("pruned", "2")));
+        let res = commit(deps.as_mut(), 112, "6").unwrap();
+        assert!(res.attributes.contains(&attr("pruned", "0")));
+
+        let left: Vec<String> = WITHDRAW_COMMITS
+            .keys(&deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()
+            .unwrap();
+        assert_eq!(left, vec!["4", "5", "6"]);
+        assert_eq!(
+            WITHDRAW_COMMIT_HEIGHTS
+                .keys(&deps.storage, None, None, Order::Ascending)
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_import_nullifiers() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("admin", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let spent = Deposit {
+            nullifier: "54154714943715201094961901040590459639892306160131965986154511512546000403"
+                .to_string(),
+        }
+        .get_nullifier_hash(0);
+        let msg = ExecuteMsg::ImportNullifiers(ImportNullifiersMsg {
+            hashes: vec![
+                spent,
+                "10174783302134252183272028399003089320089964203118066360883858790559353379370"
+                    .to_string(),
+            ],
+            pool_id: None,
+        });
+
+        let info = mock_info("Bob", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let info = mock_info("admin", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+
+        let withdraw_msg = insert_withdraw_fixture(deps.as_mut());
+
+        // the pool is now active, imports are closed
+        let info = mock_info("admin", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::PoolAlreadyActive {});
+
+        let info = mock_info("Alice", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Withdraw(withdraw_msg),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::DuplicatedCommitment {});
+    }
+
+    #[test]
+    fn test_rebuild_tree() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("admin", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        for nullifier in ["1", "2", "3"] {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: Deposit::new(nullifier.to_string()).get_commitment(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        }
+        let expected = COMMITMENTS.load(&deps.storage).unwrap();
+
+        let mut tree = expected.clone();
+        tree.filled_subtrees[1] = U256::from(42_u8);
+        tree.roots[tree.current_root_index as usize] = U256::from(42_u8);
+        COMMITMENTS.save(&mut deps.storage, &tree).unwrap();
+
+        let msg = ExecuteMsg::RebuildTree(RebuildTreeMsg { pool_id: None });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let info = mock_info("admin", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.attributes[2].value, "42");
+        assert_eq!(
+            res.attributes[3].value,
+            expected.get_last_root().to_string()
+        );
+        assert_eq!(COMMITMENTS.load(&deps.storage).unwrap(), expected);
+        assert!(is_known_root(&deps.storage, None, &expected, &expected.get_last_root()).unwrap());
+        assert!(!is_known_root(&deps.storage, None, &expected, &U256::from(42_u8)).unwrap());
+
+        // leaves that don't add up to a known root are rejected
+        LEAVES
+            .save(&mut deps.storage, 2, &U256::from(7_u8).to_string())
+            .unwrap();
+        let msg = ExecuteMsg::RebuildTree(RebuildTreeMsg { pool_id: None });
+        let info = mock_info("admin", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::UnknownRoot {});
+    }
+
+    #[test]
+    fn test_rebuild_tree_missing_leaves() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("admin", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        for nullifier in ["1", "2", "3"] {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: Deposit::new(nullifier.to_string()).get_commitment(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        }
+        let expected = COMMITMENTS.load(&deps.storage).unwrap();
+        let msg = ExecuteMsg::RebuildTree(RebuildTreeMsg { pool_id: None });
+
+        // the last leaf missing, then one in the middle
+        let last = LEAVES.load(&deps.storage, 2).unwrap();
+        LEAVES.remove(&mut deps.storage, 2);
+        let info = mock_info("admin", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::MissingLeaf { leaf_index: 2 });
+
+        LEAVES.save(&mut deps.storage, 2, &last).unwrap();
+        LEAVES.remove(&mut deps.storage, 1);
+        let info = mock_info("admin", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::MissingLeaf { leaf_index: 1 });
+        assert_eq!(COMMITMENTS.load(&deps.storage).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_import_tree() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("admin", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        // the old pool's tree, holding the withdraw fixture's note
+        let (deposit, withdraw_msg) = withdraw_fixture();
+        let commitment = deposit.get_commitment();
+        let mut tree = MerkleTreeWithHistory::new(DEFAULT_LEVELS).with_leaves();
+        tree.insert(&U256::from_str(&commitment).unwrap()).unwrap();
+        let snapshot = tree.export();
+
+        let msg = ExecuteMsg::ImportTree(ImportTreeMsg {
+            snapshot: snapshot.clone(),
+            pool_id: None,
+        });
+        let info = mock_info("Bob", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let mut tampered = snapshot.clone();
+        tampered.leaves = Some(vec![U256::from(1_u8)]);
+        let info = mock_info("admin", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::ImportTree(ImportTreeMsg {
+                snapshot: tampered,
+                pool_id: None,
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidSnapshot {});
+
+        // a tree the circuit can't prove against
+        let info = mock_info("admin", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::ImportTree(ImportTreeMsg {
+                snapshot: MerkleTreeWithHistory::new(4).export(),
+                pool_id: None,
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::SnapshotMismatch {});
+
+        // a tree hashed with other Poseidon rounds
+        let params = PoseidonParams {
+            partial_rounds: 56,
+            ..PoseidonParams::default()
+        };
+        let other_hash = MerkleTreeWithHistory::try_new_with_params(
+            DEFAULT_LEVELS,
+            ROOT_HISTORY_SIZE,
+            None,
+            params,
+        )
+        .unwrap();
+        let info = mock_info("admin", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::ImportTree(ImportTreeMsg {
+                snapshot: other_hash.export(),
+                pool_id: None,
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::SnapshotMismatch {});
+
+        // a root that isn't the one of its edge, with or without the leaves
+        for leaves in [snapshot.leaves.clone(), None] {
+            let mut tampered = snapshot.clone();
+            tampered.leaves = leaves;
+            tampered.roots[tampered.current_root_index as usize] = U256::from(1_u8);
+            let info = mock_info("admin", &[]);
+            let err = execute(
+                deps.as_mut(),
+                mock_env(),
+                info,
+                ExecuteMsg::ImportTree(ImportTreeMsg {
+                    snapshot: tampered,
+                    pool_id: None,
+                }),
+            )
+            .unwrap_err();
+            assert_eq!(err, ContractError::InvalidSnapshot {});
+        }
+
+        let info = mock_info("admin", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+        assert_eq!(LEAVES.load(&deps.storage, 0).unwrap(), commitment);
+        let res = query_tree_info(deps.as_ref(), None).unwrap();
+        assert_eq!(res.next_index, 1);
+        assert_eq!(res.last_root, tree.get_last_root().to_string());
+
+        let info = mock_info("admin", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::PoolAlreadyActive {});
+
+        // the note carried over is spendable, and can't be deposited again
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Deposit(DepositMsg {
+                commitment,
+                pool_id: None,
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::DuplicatedCommitment {});
+        let info = mock_info("Alice", &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Withdraw(withdraw_msg),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_import_nullifiers_batch_too_large() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("admin", &[]);
+        instantiate(deps.as_mut(), mock_env(), info.clone(), instantiate_msg).unwrap();
+
+        let msg = ExecuteMsg::ImportNullifiers(ImportNullifiersMsg {
+            hashes: (0..=DEFAULT_MAX_BATCH_SIZE)
+                .map(|i| i.to_string())
+                .collect(),
+            pool_id: None,
+        });
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::BatchTooLarge {
+                max: DEFAULT_MAX_BATCH_SIZE
+            }
+        );
+
+        let msg = ExecuteMsg::ImportNullifiers(ImportNullifiersMsg {
+            hashes: (0..DEFAULT_MAX_BATCH_SIZE).map(|i| i.to_string()).collect(),
+            pool_id: None,
+        });
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn test_custom_max_batch_size() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            max_batch_size: Some(2),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("admin", &[]);
+        instantiate(deps.as_mut(), mock_env(), info.clone(), instantiate_msg).unwrap();
+
+        let msg = ExecuteMsg::ImportNullifiers(ImportNullifiersMsg {
+            hashes: vec!["1".to_string(), "2".to_string(), "3".to_string()],
+            pool_id: None,
+        });
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::BatchTooLarge { max: 2 });
+    }
+
+    #[test]
+    fn test_deposit_attributes() {
+        let mut deps = mock_dependencies();
+
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate_msg()).unwrap();
+
+        for (i, nullifier) in ["1", "2", "3"].iter().enumerate() {
+            let commitment = Deposit::new(nullifier.to_string()).get_commitment();
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: commitment.clone(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+            let attr = |key: &str| {
+                res.attributes
+                    .iter()
+                    .find(|a| a.key == key)
+                    .map(|a| a.value.clone())
+                    .unwrap()
+            };
+            assert_eq!(commitment, attr("commitment"));
+            let leaf_index: u32 = attr("leaf_index").parse().unwrap();
+            assert_eq!(i as u32, leaf_index);
+        }
+    }
+
+    #[test]
+    fn test_cli_messages() {
+        let msg: ExecuteMsg = from_slice(cli::deposit_msg("42", Some(1)).as_bytes()).unwrap();
+        assert_eq!(
+            msg,
+            ExecuteMsg::Deposit(DepositMsg {
+                commitment: "42".to_string(),
+                pool_id: Some(1),
+            })
+        );
+
+        let msg: QueryMsg = from_slice(cli::is_known_root_query("42").as_bytes()).unwrap();
+        assert_eq!(
+            msg,
+            QueryMsg::IsKnownRoot(IsKnownRootMsg {
+                root: "42".to_string(),
+                pool_id: None,
+            })
+        );
+
+        // the withdraw fixture round trips, and is accepted by the contract
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+        let proof = WithdrawProof {
+            proof: withdraw_msg.proof.clone().to_circom().unwrap(),
+            root: withdraw_msg.root.clone(),
+            nullifier_hash: withdraw_msg.nullifier_hash.clone(),
+            recipient: withdraw_msg.recipient.clone(),
+            relayer: withdraw_msg.relayer.clone(),
+            fee: withdraw_msg.fee,
+        };
+        let msg: ExecuteMsg = from_slice(cli::withdraw_msg(&proof, None).as_bytes()).unwrap();
+        assert_eq!(msg, ExecuteMsg::Withdraw(withdraw_msg));
+
+        let info = mock_info("Alice", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn test_messages_deny_unknown_fields() {
+        let msg: InstantiateMsg = from_slice(br#"{"denom":"TKN","amount":"10"}"#).unwrap();
+        assert_eq!(msg, default_instantiate_msg());
+        assert!(
+            from_slice::<InstantiateMsg>(br#"{"denom":"TKN","amount":"10","admn":"Alice"}"#)
+                .is_err()
+        );
+
+        let (_, withdraw_msg) = withdraw_fixture();
+        let json = String::from_utf8(to_vec(&ExecuteMsg::Withdraw(withdraw_msg.clone())).unwrap())
+            .unwrap();
+        let msg: ExecuteMsg = from_slice(json.as_bytes()).unwrap();
+        assert_eq!(msg, ExecuteMsg::Withdraw(withdraw_msg));
+
+        // a typo, and a field the contract doesn't know
+        let typo = json.replace("\"nullifier_hash\"", "\"nullifier_hsh\"");
+        assert!(from_slice::<ExecuteMsg>(typo.as_bytes()).is_err());
+        let extra = json.replace("{\"withdraw\":{", "{\"withdraw\":{\"memo\":\"hi\",");
+        assert_ne!(extra, json);
+        assert!(from_slice::<ExecuteMsg>(extra.as_bytes()).is_err());
+
+        let msg = br#"{"deposit":{"commitment":"1","pool_id":null}}"#;
+        assert!(from_slice::<ExecuteMsg>(msg).is_ok());
+        let msg = br#"{"deposit":{"commitment":"1","pool":1}}"#;
+        assert!(from_slice::<ExecuteMsg>(msg).is_err());
+    }
+
+    #[test]
+    fn test_deposit_response_data() {
+        let mut deps = mock_dependencies();
+
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate_msg()).unwrap();
+
+        for (i, nullifier) in ["1", "2"].iter().enumerate() {
+            let commitment = Deposit::new(nullifier.to_string()).get_commitment();
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: commitment.clone(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+            let data: DepositResponse = from_binary(&res.data.unwrap()).unwrap();
+            let root = COMMITMENTS.load(&deps.storage).unwrap().get_last_root();
+            assert_eq!(
+                data,
+                DepositResponse {
+                    leaf_index: i as u32,
+                    commitment,
+                    root: root.to_string(),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_deposit_payment_errors() {
+        let mut deps = mock_dependencies();
+
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate_msg()).unwrap();
+
+        let msg = ExecuteMsg::Deposit(DepositMsg {
+            commitment: Deposit::new("1".to_string()).get_commitment(),
+            pool_id: None,
+        });
+        let cases = [
+            (vec![], PaymentError::NoFunds {}),
+            (
+                vec![coin(10, "TKN"), coin(10, "ATOM")],
+                PaymentError::MultipleDenoms {},
+            ),
+            (
+                coins(10, "ATOM"),
+                PaymentError::MissingDenom("TKN".to_string()),
+            ),
+        ];
+        for (funds, payment_err) in cases {
+            let info = mock_info("Alice", &funds);
+            let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+            assert_eq!(err.to_string(), payment_err.to_string());
+            assert_eq!(err, ContractError::Payment(payment_err));
+        }
+        assert!(COMMITMENTS.load(&deps.storage).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_deposit_batch() {
+        let mut deps = mock_dependencies();
+
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate_msg()).unwrap();
+
+        let msgs: Vec<DepositMsg> = ["1", "2", "3"]
+            .iter()
+            .map(|nullifier| DepositMsg {
+                commitment: Deposit::new(nullifier.to_string()).get_commitment(),
+                pool_id: None,
+            })
+            .collect();
+
+        // underpaid
+        let info = mock_info("Alice", &coins(20, "TKN"));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::DepositBatch(msgs.clone()),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidAmount {
+                denom: "TKN".to_string(),
+                amount: U128::from(30_u128),
+            }
+        );
+
+        let info = mock_info("Alice", &coins(30, "TKN"));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::DepositBatch(msgs.clone()),
+        )
+        .unwrap();
+        let leaf_indices: Vec<&str> = res
+            .attributes
+            .iter()
+            .filter(|a| a.key == "leaf_index")
+            .map(|a| a.value.as_str())
+            .collect();
+        assert_eq!(leaf_indices, vec!["0", "1", "2"]);
+
+        // same root as depositing one by one
+        let mut tree = MerkleTreeWithHistory::new(DEFAULT_LEVELS);
+        for msg in &msgs {
+            tree.insert(&U256::from_str(&msg.commitment).unwrap())
+                .unwrap();
+        }
+        let stored = COMMITMENTS.load(&deps.storage).unwrap();
+        assert_eq!(stored.get_last_root(), tree.get_last_root());
+        assert_eq!(LEAVES.load(&deps.storage, 2).unwrap(), msgs[2].commitment);
+    }
+
+    #[test]
+    fn test_deposit_fee() {
+        let mut deps = mock_dependencies();
+
+        // no one to collect it
+        let msg = InstantiateMsg {
+            deposit_fee: Some(U128::from(1_u128)),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        let err = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::MissingFeeCollector {});
+
+        let msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            deposit_fee: Some(U128::from(1_u128)),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let deposit_msg = DepositMsg {
+            commitment: Deposit::new("1".to_string()).get_commitment(),
+            pool_id: None,
+        };
+
+        // the fee comes on top of the note
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Deposit(deposit_msg.clone()),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidAmount {
+                denom: "TKN".to_string(),
+                amount: U128::from(11_u128),
+            }
+        );
+
+        // and goes to the admin by default
+        let info = mock_info("Alice", &coins(11, "TKN"));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Deposit(deposit_msg.clone()),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "admin".to_string(),
+                amount: coins(1, "TKN"),
+            })]
+        );
+
+        // the note itself is unchanged
+        let mut tree = MerkleTreeWithHistory::new(DEFAULT_LEVELS);
+        tree.insert(&U256::from_str(&deposit_msg.commitment).unwrap())
+            .unwrap();
+        let res = query_tree_info(deps.as_ref(), None).unwrap();
+        assert_eq!(res.last_root, tree.get_last_root().to_string());
+        let res = query_pool(deps.as_ref(), PoolMsg { pool_id: None }).unwrap();
+        assert_eq!(res.amount, U128::from(10_u128));
+    }
+
+    #[test]
+    fn test_deposit_batch_fee() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            deposit_fee: Some(U128::from(1_u128)),
+            fee_collector: Some("collector".to_string()),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let msgs: Vec<DepositMsg> = ["1", "2"]
+            .iter()
+            .map(|nullifier| DepositMsg {
+                commitment: Deposit::new(nullifier.to_string()).get_commitment(),
+                pool_id: None,
+            })
+            .collect();
+
+        let info = mock_info("Alice", &coins(20, "TKN"));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::DepositBatch(msgs.clone()),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidAmount {
+                denom: "TKN".to_string(),
+                amount: U128::from(22_u128),
+            }
+        );
+
+        let info = mock_info("Alice", &coins(22, "TKN"));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::DepositBatch(msgs),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "collector".to_string(),
+                amount: coins(2, "TKN"),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_deposit_batch_tree_full() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            levels: Some(2),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let msg = ExecuteMsg::Deposit(DepositMsg {
+            commitment: "1".to_string(),
+            pool_id: None,
+        });
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // 3 commitments left, 4 sent
+        let msgs: Vec<DepositMsg> = (2..=5_u32)
+            .map(|i| DepositMsg {
+                commitment: i.to_string(),
+                pool_id: None,
+            })
+            .collect();
+        let info = mock_info("Alice", &coins(40, "TKN"));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::DepositBatch(msgs),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::TreeFull {});
+        assert_eq!(COMMITMENTS.load(&deps.storage).unwrap().next_index, 1);
+    }
+
+    #[test]
+    fn test_deposit_event() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            emit_events: true,
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        for (i, nullifier) in ["1", "2"].iter().enumerate() {
+            let commitment = Deposit::new(nullifier.to_string()).get_commitment();
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: commitment.clone(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+            assert_eq!(1, res.events.len());
+            let event = &res.events[0];
+            assert_eq!("deposit", event.ty);
+            let keys: Vec<&str> = event.attributes.iter().map(|a| a.key.as_str()).collect();
+            assert_eq!(
+                vec!["schema_version", "commitment", "leaf_index", "timestamp"],
+                keys
+            );
+            assert_eq!(SCHEMA_VERSION, event.attributes[0].value);
+            assert_eq!(commitment, event.attributes[1].value);
+            assert_eq!(i.to_string(), event.attributes[2].value);
+            assert_eq!(
+                mock_env().block.time.seconds().to_string(),
+                event.attributes[3].value
+            );
+        }
+    }
+
+    #[test]
+    fn test_insert_leaf_hash_failed() {
+        let mut deps = mock_dependencies();
+        let mut tree = MerkleTreeWithHistory::new(4);
+        insert_leaf(&mut deps.storage, None, &mut tree, &U256::from(1_u8)).unwrap();
+
+        // a stored tree whose Poseidon rounds were corrupted can't hash anymore
+        let mut corrupted = tree.clone();
+        corrupted.poseidon_params = Some(PoseidonParams {
+            full_rounds: 0,
+            ..PoseidonParams::default()
+        });
+        assert_eq!(
+            insert_leaf(&mut deps.storage, None, &mut corrupted, &U256::from(2_u8)),
+            Err(ContractError::InsertFailed {})
+        );
+        let roots: Vec<String> = KNOWN_ROOTS
+            .prefix(0)
+            .keys(&deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()
+            .unwrap();
+        assert_eq!(roots, vec![tree.get_last_root().to_string()]);
+    }
+
+    #[test]
+    fn test_query_commitment_index() {
+        let mut deps = mock_dependencies();
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate_msg()).unwrap();
+
+        let commitments: Vec<String> = ["1", "2"]
+            .iter()
+            .map(|nullifier| Deposit::new(nullifier.to_string()).get_commitment())
+            .collect();
+        for commitment in &commitments {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: commitment.clone(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        }
+
+        let commitment_index = |commitment: &str, pool_id| {
+            let msg = QueryMsg::CommitmentIndex {
+                commitment: commitment.to_string(),
+                pool_id,
+            };
+            query(deps.as_ref(), mock_env(), msg)
+                .map(|res| from_binary::<Option<u32>>(&res).unwrap())
+        };
+        assert_eq!(commitment_index(&commitments[0], None), Ok(Some(0)));
+        assert_eq!(commitment_index(&commitments[1], None), Ok(Some(1)));
+        assert_eq!(
+            commitment_index(&format!("00{}", commitments[1]), None),
+            Ok(Some(1))
+        );
+        assert_eq!(commitment_index("42", None), Ok(None));
+        assert_eq!(commitment_index(&commitments[0], Some(1)), Ok(None));
+        assert!(commitment_index("not a commitment", None).is_err());
+    }
+
+    #[test]
+    fn test_deposit_time() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            emit_events: true,
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let mut env = mock_env();
+        env.block.height += 7;
+        env.block.time = env.block.time.plus_seconds(3600);
+        let commitment = Deposit::new("1".to_string()).get_commitment();
+        let msg = ExecuteMsg::Deposit(DepositMsg {
+            commitment: commitment.clone(),
+            pool_id: None,
+        });
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        let res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+        let timestamp = &res.events[0].attributes[3];
+        assert_eq!(timestamp.key, "timestamp");
+        assert_eq!(timestamp.value, env.block.time.seconds().to_string());
+
+        let msg = QueryMsg::DepositTime {
+            leaf_index: 0,
+            pool_id: None,
+        };
+        let res: DepositTimeResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(
+            res,
+            DepositTimeResponse {
+                commitment,
+                block_height: Some(env.block.height),
+                timestamp: Some(env.block.time.seconds()),
+            }
+        );
+
+        let msg = QueryMsg::DepositTime {
+            leaf_index: 1,
+            pool_id: None,
+        };
+        query(deps.as_ref(), mock_env(), msg).unwrap_err();
+    }
+
+    #[test]
+    fn test_withdrawal_event() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg {
+            emit_events: true,
+            ..default_instantiate_msg()
+        };
+        let withdraw_msg = setup_withdraw(deps.as_mut(), instantiate_msg);
+
+        let info = mock_info("Alice", &[]);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Withdraw(withdraw_msg.clone()),
+        )
+        .unwrap();
+
+        assert_eq!(1, res.events.len());
+        let event = &res.events[0];
+        assert_eq!("withdrawal", event.ty);
+        let keys: Vec<&str> = event.attributes.iter().map(|a| a.key.as_str()).collect();
+        assert_eq!(
+            vec!["schema_version", "nullifier_hash", "to", "relayer", "fee"],
+            keys
+        );
+        assert_eq!(SCHEMA_VERSION, event.attributes[0].value);
+        assert_eq!(withdraw_msg.nullifier_hash, event.attributes[1].value);
+        assert_eq!(withdraw_msg.recipient, event.attributes[2].value);
+    }
+
+    #[test]
+    fn test_withdraw_root_index_wrapped() {
+        let mut deps = mock_dependencies();
+
+        // a single slot history wraps back to index 0 on the first insert
+        let instantiate_msg = InstantiateMsg {
+            root_history_size: Some(1),
+            ..default_instantiate_msg()
+        };
+        let withdraw_msg = setup_withdraw(deps.as_mut(), instantiate_msg);
+        assert_eq!(
+            COMMITMENTS.load(&deps.storage).unwrap().current_root_index,
+            0
+        );
+
+        let info = mock_info("Alice", &[]);
+        let msg = ExecuteMsg::Withdraw(withdraw_msg);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn test_withdraw_empty_pool() {
+        let mut deps = mock_dependencies();
+
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate_msg()).unwrap();
+
+        let genesis_root = COMMITMENTS
+            .load(&deps.storage)
+            .unwrap()
+            .genesis_root()
+            .unwrap();
+        let (_, withdraw_msg) = withdraw_fixture();
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            root: genesis_root.to_string(),
+            ..withdraw_msg
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::UnknownRoot {});
+    }
+
+    #[test]
+    fn test_withdraw_nullifier_hash_encodings() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let info = mock_info("Alice", &[]);
+        let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let nullifier_hash = U256::from_str(&withdraw_msg.nullifier_hash).unwrap();
+        for encoding in [
+            format!("0{}", withdraw_msg.nullifier_hash),
+            u256_to_hex(&nullifier_hash),
+        ] {
+            let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+                nullifier_hash: encoding,
+                ..withdraw_msg.clone()
+            });
+            let info = mock_info("Alice", &[]);
+            let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+            assert_eq!(err, ContractError::DuplicatedCommitment {});
+        }
+
+        // the same field element plus the modulus
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            nullifier_hash: (nullifier_hash + FIELD_MODULUS).to_string(),
+            ..withdraw_msg
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::ParseError { .. }));
+    }
+
+    #[test]
+    fn test_withdraw_non_numeric_root() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        for root in ["not a root", "", "0x12"] {
+            let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+                root: root.to_string(),
+                ..withdraw_msg.clone()
+            });
+            let info = mock_info("Alice", &[]);
+            let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+            assert!(matches!(err, ContractError::ParseError { .. }), "{}", err);
+
+            let msg = QueryMsg::IsKnownRoot(IsKnownRootMsg {
+                root: root.to_string(),
+                pool_id: None,
+            });
+            assert!(query(deps.as_ref(), mock_env(), msg).is_err());
+        }
+    }
+
+    #[test]
+    fn test_withdraw_expired_root() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            root_history_size: Some(2),
+            ..default_instantiate_msg()
+        };
+        let withdraw_msg = setup_withdraw(deps.as_mut(), instantiate_msg);
+
+        // two more deposits push the proven root out of the history
+        for nullifier in ["1", "2"] {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: Deposit::new(nullifier.to_string()).get_commitment(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        }
+
+        let info = mock_info("Alice", &[]);
+        let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::RootExpired {});
+
+        // a root the pool never had
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            root: "42".to_string(),
+            ..withdraw_msg
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::UnknownRoot {});
+    }
+
+    #[test]
+    fn test_withdraw_min_delay_blocks() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            min_delay_blocks: Some(10),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let (deposit, withdraw_msg) = withdraw_fixture();
+        let msg = ExecuteMsg::Deposit(DepositMsg {
+            commitment: deposit.get_commitment(),
+            pool_id: None,
+        });
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let mut env = mock_env();
+        env.block.height += 9;
+        let msg = ExecuteMsg::Withdraw(withdraw_msg);
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::TooEarly {});
+
+        // a delay past the u64 limit is never over, rather than overflowing
+        env.block.height += 1;
+        MIN_DELAY_BLOCKS.save(&mut deps.storage, &u64::MAX).unwrap();
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::TooEarly {});
+
+        MIN_DELAY_BLOCKS.save(&mut deps.storage, &10).unwrap();
+        execute(deps.as_mut(), env, info, msg).unwrap();
+    }
+
+    #[test]
+    fn test_withdraw_payouts_overflow() {
+        let (_, mut withdraw_msg) = withdraw_fixture();
+        withdraw_msg.fee = U128::from(1_u8);
+        let asset = AssetInfo::Native {
+            denom: "TKN".to_string(),
+        };
+        let payouts = [(Addr::unchecked("recipient"), U128::MAX)];
+
+        // a recipient relaying its own withdrawal is paid the fee on top
+        let err = withdraw_payouts(&asset, &withdraw_msg, &payouts, None, None).unwrap_err();
+        assert!(matches!(err, StdError::Overflow { .. }));
+    }
+
+    #[test]
+    fn test_withdraw_min_confirmations() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            min_confirmations: Some(3),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let (deposit, withdraw_msg) = withdraw_fixture();
+        let msg = ExecuteMsg::Deposit(DepositMsg {
+            commitment: deposit.get_commitment(),
+            pool_id: None,
+        });
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            LEAF_HEIGHTS.load(&deps.storage, 0).unwrap(),
+            mock_env().block.height
+        );
+
+        // too fresh, in the deposit's block and the next two
+        let mut env = mock_env();
+        let msg = ExecuteMsg::Withdraw(withdraw_msg);
+        let info = mock_info("Alice", &[]);
+        for _ in 0..3 {
+            let err = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
+            assert_eq!(err, ContractError::RootUnconfirmed {});
+            env.block.height += 1;
+        }
+
+        // past the u64 limit the root is never confirmed, rather than overflowing
+        MIN_CONFIRMATIONS
+            .save(&mut deps.storage, &u64::MAX)
+            .unwrap();
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::RootUnconfirmed {});
+
+        MIN_CONFIRMATIONS.save(&mut deps.storage, &3).unwrap();
+        execute(deps.as_mut(), env, info, msg).unwrap();
+    }
+
+    #[test]
+    fn test_query_verify_withdraw() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let verify = |deps: Deps| -> VerifyWithdrawResponse {
+            let msg = QueryMsg::VerifyWithdraw(withdraw_msg.clone());
+            from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap()
+        };
+        assert_eq!(
+            verify(deps.as_ref()),
+            VerifyWithdrawResponse {
+                valid: true,
+                reason: None
+            }
+        );
+        // a dry-run doesn't spend the note
+        assert!(verify(deps.as_ref()).valid);
+
+        let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+        let info = mock_info("Alice", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        assert_eq!(
+            verify(deps.as_ref()),
+            VerifyWithdrawResponse {
+                valid: false,
+                reason: Some(ContractError::DuplicatedCommitment {}.to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn test_withdraw_leaf_index_out_of_range() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+        let (deposit, _) = withdraw_fixture();
+
+        // the note is leaf 0 of a one leaf tree, claim it sits at leaf 5
+        let next_index = COMMITMENTS.load(&deps.storage).unwrap().next_index;
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            nullifier_hash: deposit.get_nullifier_hash(next_index as u128 + 4),
+            ..withdraw_msg
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidProof {});
+    }
+
+    #[test]
+    fn test_withdraw_proof_builder() {
+        let mut deps = mock_dependencies();
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate_msg()).unwrap();
+
+        let (deposit, fixture) = withdraw_fixture();
+        let msg = ExecuteMsg::Deposit(DepositMsg {
+            commitment: deposit.clone().get_commitment(),
+            pool_id: None,
+        });
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // the client side tree, and the proof the fixture was generated with
+        let mut tree = MerkleTreeWithHistory::new(DEFAULT_LEVELS).with_leaves();
+        tree.insert(&U256::from_str(&deposit.clone().get_commitment()).unwrap())
+            .unwrap();
+        let prover = CannedProver(fixture.proof.to_circom().unwrap());
+        let built = ProofBuilder::new(prover, deposit, 0, &fixture.recipient)
+            .relayer(&fixture.relayer, U128::zero())
+            .build(&tree)
+            .unwrap();
+
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            proof: built.proof.into(),
+            root: built.root,
+            nullifier_hash: built.nullifier_hash,
+            recipient: built.recipient,
+            relayer: built.relayer,
+            fee: built.fee,
+            pool_id: None,
+            callback: None,
+            recipients: None,
+        });
+        let info = mock_info("Alice", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn test_withdraw_flat_proof() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+        let flat = withdraw_msg.proof.clone().to_circom().unwrap().to_flat();
+
+        let verify = |deps: Deps, proof: ProofInput| -> VerifyWithdrawResponse {
+            let msg = QueryMsg::VerifyWithdraw(WithdrawMsg {
+                proof,
+                ..withdraw_msg.clone()
+            });
+            from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap()
+        };
+        assert_eq!(
+            verify(deps.as_ref(), withdraw_msg.proof.clone()),
+            verify(deps.as_ref(), ProofInput::Flat(flat.clone()))
+        );
+
+        let err = verify(deps.as_ref(), ProofInput::Flat(flat[..7].to_vec()));
+        assert_eq!(
+            err.reason,
+            Some(ProofError::InvalidFlatProof { len: 7 }.to_string())
+        );
+
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            proof: ProofInput::Flat(flat),
+            ..withdraw_msg
+        });
+        let info = mock_info("Alice", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    // A degenerate key where a proof only binds the note value: alpha and every
+    // IC point but the note value's are the identity, and that one is G1, so the
+    // check is e(A, B) = e(note_value * G1 + C, delta). The withdraw signals
+    // alone are free.
+    fn mock_partial_vk() -> VerifyingKeyJson {
+        let identity = || vec!["0".to_string(), "1".to_string(), "0".to_string()];
+        let g2 = || {
+            vec![
+                vec![
+                    "10857046999023057135944570762232829481370756359578518086990519993285655852781"
+                        .to_string(),
+                    "11559732032986387107991004021392285783925812861821192530917403151452391805634"
+                        .to_string(),
+                ],
+                vec![
+                    "8495653923123431417604973247489272438418190587263600148770280649306958101930"
+                        .to_string(),
+                    "4082367875863433681332203403145435568316851327593401208105741076214120093531"
+                        .to_string(),
+                ],
+                vec!["1".to_string(), "0".to_string()],
+            ]
+        };
+        // [..withdraw signals, change_commitment, denomination, note_value, amount]
+        let mut ic = vec![identity(); 10];
+        ic[8] = g1_multiple(1);
+        VerifyingKeyJson {
+            ic,
+            vk_alpha_1: identity(),
+            vk_beta_2: g2(),
+            vk_gamma_2: g2(),
+            vk_delta_2: g2(),
+            vk_alphabeta_12: vec![],
+        }
+    }
+
+    // A proof of a note holding `note_value` under `mock_partial_vk`, the only
+    // value a prover could open it for. Full withdrawals use 0.
+    fn mock_proof(note_value: u128) -> ProofInput {
+        let a = g1_multiple(note_value + 1);
+        let c = g1_multiple(1);
+        let g2 = mock_partial_vk().vk_delta_2;
+        ProofInput::Flat([&a[..2], &g2[0], &g2[1], &c[..2]].concat().to_vec())
+    }
+
+    #[test]
+    fn test_withdraw_fee_denom() {
+        // accepts `mock_proof` for the five withdraw signals
+        let mut vk = mock_partial_vk();
+        vk.ic.truncate(6);
+        let relayer = "juno1am5sw4geda8xfvmn4pkzruhv8ah0l3jx5hgchh";
+
+        // by default the fee comes out of the note
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg {
+            verification_key: Some(vk.clone()),
+            ..default_instantiate_msg()
+        };
+        let withdraw_msg = WithdrawMsg {
+            proof: mock_proof(0),
+            fee: U128::new(2),
+            ..setup_withdraw(deps.as_mut(), instantiate_msg)
+        };
+        let info = mock_info("Alice", &[]);
+        let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send {
+                    to_address: withdraw_msg.recipient.clone(),
+                    amount: coins(8, "TKN"),
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: relayer.to_string(),
+                    amount: coins(2, "TKN"),
+                }),
+            ]
+        );
+
+        let msg = ExecuteMsg::FundFees(FundFeesMsg {});
+        let info = mock_info("Alice", &coins(5, "TKN"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::NoFeeDenom {});
+
+        // with a fee denom, out of the reserve and below a maximum
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg {
+            verification_key: Some(vk.clone()),
+            fee_denom: Some("uusdc".to_string()),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        let err =
+            instantiate(deps.as_mut(), mock_env(), info, instantiate_msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::MissingMaxFee {});
+
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg {
+            max_fee: Some(U128::new(3)),
+            ..instantiate_msg
+        };
+        let withdraw_msg = WithdrawMsg {
+            proof: mock_proof(0),
+            fee: U128::new(2),
+            ..setup_withdraw(deps.as_mut(), instantiate_msg)
+        };
+        let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::FeeReserveTooLow {
+                fee: U128::new(2),
+                available: U128::zero()
+            }
+        );
+
+        // pool coins don't fund the reserve
+        let fund = ExecuteMsg::FundFees(FundFeesMsg {});
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("Bob", &coins(5, "TKN")),
+            fund.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Payment(_)));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("Bob", &coins(5, "uusdc")),
+            fund,
+        )
+        .unwrap();
+
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send {
+                    to_address: withdraw_msg.recipient.clone(),
+                    amount: coins(10, "TKN"),
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: relayer.to_string(),
+                    amount: coins(2, "uusdc"),
+                }),
+            ]
+        );
+
+        let res: ConfigResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Config { pool_id: None },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.fee_denom, Some("uusdc".to_string()));
+        assert_eq!(res.fee_reserve, U128::new(3));
+        assert_eq!(res.max_fee, Some(U128::new(3)));
+
+        // the reserve covers it, but no single withdrawal may take more
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("Bob", &coins(10, "uusdc")),
+            ExecuteMsg::FundFees(FundFeesMsg {}),
+        )
+        .unwrap();
+        let withdraw_msg = WithdrawMsg {
+            fee: U128::new(4),
+            ..withdraw_msg
+        };
+        let msg = ExecuteMsg::Withdraw(withdraw_msg);
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::FeeAboveMaximum {
+                fee: U128::new(4),
+                max_fee: U128::new(3),
+            }
+        );
+    }
+
+    #[test]
+    fn test_withdraw_self_relay() {
+        // accepts `mock_proof` for the five withdraw signals
+        let mut vk = mock_partial_vk();
+        vk.ic.truncate(6);
+
+        for fee in [0_u128, 2] {
+            let mut deps = mock_dependencies();
+            let instantiate_msg = InstantiateMsg {
+                verification_key: Some(vk.clone()),
+                ..default_instantiate_msg()
+            };
+            let withdraw_msg = setup_withdraw(deps.as_mut(), instantiate_msg);
+            let withdraw_msg = WithdrawMsg {
+                proof: mock_proof(0),
+                relayer: withdraw_msg.recipient.clone(),
+                fee: U128::from(fee),
+                ..withdraw_msg
+            };
+
+            let info = mock_info("Alice", &[]);
+            let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+            let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+            assert_eq!(
+                res.messages,
+                vec![SubMsg::new(BankMsg::Send {
+                    to_address: withdraw_msg.recipient.clone(),
+                    amount: coins(10, "TKN"),
+                })]
+            );
+            assert!(res
+                .attributes
+                .iter()
+                .any(|a| a.key == "warning" && a.value == "relayer_is_recipient"));
+        }
+
+        // or rejected outright
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg {
+            verification_key: Some(vk),
+            reject_self_relay: true,
+            ..default_instantiate_msg()
+        };
+        let withdraw_msg = setup_withdraw(deps.as_mut(), instantiate_msg);
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            proof: mock_proof(0),
+            relayer: withdraw_msg.recipient.clone(),
+            ..withdraw_msg
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::SelfRelay {});
+    }
+
+    #[test]
+    fn test_withdraw_split() {
+        // accepts `mock_proof` for the five withdraw signals
+        let mut vk = mock_partial_vk();
+        vk.ic.truncate(6);
+
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg {
+            verification_key: Some(vk),
+            ..default_instantiate_msg()
+        };
+        let withdraw_msg = setup_withdraw(deps.as_mut(), instantiate_msg);
+        let change = "juno1am5sw4geda8xfvmn4pkzruhv8ah0l3jx5hgchh".to_string();
+        let split = |amounts: &[u128]| {
+            let addresses = [withdraw_msg.recipient.clone(), change.clone()];
+            let recipients = addresses
+                .iter()
+                .zip(amounts)
+                .map(|(address, amount)| Payout {
+                    address: address.clone(),
+                    amount: U128::from(*amount),
+                })
+                .collect();
+            ExecuteMsg::Withdraw(WithdrawMsg {
+                proof: mock_proof(0),
+                relayer: change.clone(),
+                fee: U128::from(1_u128),
+                recipients: Some(recipients),
+                ..withdraw_msg.clone()
+            })
+        };
+
+        for (amounts, err) in [
+            (vec![], ContractError::EmptySplit {}),
+            (
+                vec![3, 5],
+                ContractError::SplitMismatch {
+                    total: U128::from(8_u128),
+                    expected: U128::from(9_u128),
+                },
+            ),
+        ] {
+            let info = mock_info("Alice", &[]);
+            let res = execute(deps.as_mut(), mock_env(), info, split(&amounts));
+            assert_eq!(res.unwrap_err(), err);
+        }
+
+        let info = mock_info("Alice", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, split(&[3, 6])).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send {
+                    to_address: withdraw_msg.recipient.clone(),
+                    amount: coins(3, "TKN"),
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: change.clone(),
+                    amount: coins(6, "TKN"),
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: change.clone(),
+                    amount: coins(1, "TKN"),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_withdraw_partial() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        // the default circuit can't prove a change note
+        let msg = ExecuteMsg::WithdrawWithChange(WithdrawPartialMsg {
+            withdraw: withdraw_msg.clone(),
+            change_commitment: Deposit::new("1".to_string()).get_commitment(),
+            note_value: U128::from(10_u128),
+            amount: U128::from(4_u128),
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::PartialWithdrawUnsupported {});
+
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(
+            deps.as_mut(),
+            InstantiateMsg {
+                partial_verification_key: Some(mock_partial_vk()),
+                ..default_instantiate_msg()
+            },
+        );
+        let withdraw_msg = WithdrawMsg {
+            proof: mock_proof(10),
+            ..withdraw_msg
+        };
+        let change = Deposit::new("1".to_string()).get_commitment();
+        let partial_msg = WithdrawPartialMsg {
+            withdraw: withdraw_msg.clone(),
+            change_commitment: change.clone(),
+            note_value: U128::from(10_u128),
+            amount: U128::from(4_u128),
+        };
+
+        // more than the pool amount, whether withdrawn or held
+        for (note_value, amount) in [(10_u128, 11_u128), (11, 4)] {
+            let msg = ExecuteMsg::WithdrawWithChange(WithdrawPartialMsg {
+                note_value: U128::from(note_value),
+                amount: U128::from(amount),
+                ..partial_msg.clone()
+            });
+            let info = mock_info("Alice", &[]);
+            let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+            assert_eq!(
+                err,
+                ContractError::AmountTooHigh {
+                    amount: U128::from(11_u128),
+                    max: U128::from(10_u128),
+                }
+            );
+        }
+
+        let msg = ExecuteMsg::WithdrawWithChange(WithdrawPartialMsg {
+            change_commitment: field_modulus().to_string(),
+            ..partial_msg.clone()
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::ParseError { .. }));
+
+        // the proof opens the deposit for the pool amount only
+        let msg = ExecuteMsg::WithdrawWithChange(WithdrawPartialMsg {
+            note_value: U128::from(8_u128),
+            ..partial_msg.clone()
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidProof {});
+
+        let msg = ExecuteMsg::WithdrawWithChange(partial_msg);
+        let info = mock_info("Alice", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: withdraw_msg.recipient.clone(),
+                amount: coins(4, "TKN"),
+            })]
+        );
+
+        // clients built against the former message name are still understood
+        let json = String::from_utf8(to_binary(&msg).unwrap().to_vec()).unwrap();
+        let json = json.replacen("withdraw_with_change", "withdraw_partial", 1);
+        assert_eq!(from_slice::<ExecuteMsg>(json.as_bytes()).unwrap(), msg);
+
+        // the note is spent, its change is the next leaf and holds the rest
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::DuplicatedCommitment {});
+        let change = change_commitment(&U256::from_str(&change).unwrap(), 6).unwrap();
+        assert_eq!(LEAVES.load(&deps.storage, 1).unwrap(), change.to_string());
+        let tree = COMMITMENTS.load(&deps.storage).unwrap();
+        assert_eq!(tree.next_index, 2);
+    }
+
+    #[test]
+    fn test_withdraw_partial_duplicate_change() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(
+            deps.as_mut(),
+            InstantiateMsg {
+                partial_verification_key: Some(mock_partial_vk()),
+                ..default_instantiate_msg()
+            },
+        );
+        let withdraw_msg = WithdrawMsg {
+            proof: mock_proof(10),
+            ..withdraw_msg
+        };
+
+        let change = Deposit::new("1".to_string()).get_commitment();
+        let deposited = change_commitment(&U256::from_str(&change).unwrap(), 6).unwrap();
+        let msg = ExecuteMsg::Deposit(DepositMsg {
+            commitment: deposited.to_string(),
+            pool_id: None,
+        });
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // a change note can't reuse a leaf, and nothing of the withdrawal is kept
+        let msg = ExecuteMsg::WithdrawWithChange(WithdrawPartialMsg {
+            withdraw: withdraw_msg.clone(),
+            change_commitment: change,
+            note_value: U128::from(10_u128),
+            amount: U128::from(4_u128),
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::DuplicatedCommitment {});
+        assert!(!is_spent(&deps.storage, None, &withdraw_msg.nullifier_hash).unwrap());
+        assert_eq!(query_tree_info(deps.as_ref(), None).unwrap().next_index, 2);
+        assert_eq!(query_withdrawal_count(deps.as_ref(), None).unwrap(), 0);
+
+        // the same note with a fresh change goes through in one message
+        let change = Deposit::new("2".to_string()).get_commitment();
+        let msg = ExecuteMsg::WithdrawWithChange(WithdrawPartialMsg {
+            withdraw: withdraw_msg.clone(),
+            change_commitment: change.clone(),
+            note_value: U128::from(10_u128),
+            amount: U128::from(4_u128),
+        });
+        let info = mock_info("Alice", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: withdraw_msg.recipient.clone(),
+                amount: coins(4, "TKN"),
+            })]
+        );
+        assert!(is_spent(&deps.storage, None, &withdraw_msg.nullifier_hash).unwrap());
+        let change = change_commitment(&U256::from_str(&change).unwrap(), 6).unwrap();
+        assert_eq!(LEAVES.load(&deps.storage, 2).unwrap(), change.to_string());
+        assert_eq!(query_withdrawal_count(deps.as_ref(), None).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_withdraw_partial_spends_change() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(
+            deps.as_mut(),
+            InstantiateMsg {
+                partial_verification_key: Some(mock_partial_vk()),
+                ..default_instantiate_msg()
+            },
+        );
+        let withdraw_msg = WithdrawMsg {
+            proof: mock_proof(10),
+            ..withdraw_msg
+        };
+
+        let change = Deposit::new("1".to_string());
+        let msg = ExecuteMsg::WithdrawWithChange(WithdrawPartialMsg {
+            withdraw: withdraw_msg.clone(),
+            change_commitment: change.clone().get_commitment(),
+            note_value: U128::from(10_u128),
+            amount: U128::from(4_u128),
+        });
+        let info = mock_info("Alice", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // the change note is proven against the root including it, and only
+        // opens for the 6 it was inserted with
+        let tree = COMMITMENTS.load(&deps.storage).unwrap();
+        let spend_change = WithdrawPartialMsg {
+            withdraw: WithdrawMsg {
+                root: tree.get_last_root().to_string(),
+                nullifier_hash: change.clone().get_nullifier_hash(1),
+                proof: mock_proof(6),
+                ..withdraw_msg.clone()
+            },
+            change_commitment: Deposit::new("2".to_string()).get_commitment(),
+            note_value: U128::from(6_u128),
+            amount: U128::from(6_u128),
+        };
+
+        // withdrawing the whole denomination out of it
+        let msg = ExecuteMsg::WithdrawWithChange(WithdrawPartialMsg {
+            note_value: U128::from(10_u128),
+            amount: U128::from(10_u128),
+            ..spend_change.clone()
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidProof {});
+
+        let msg = ExecuteMsg::WithdrawWithChange(WithdrawPartialMsg {
+            amount: U128::from(10_u128),
+            ..spend_change.clone()
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::AmountTooHigh {
+                amount: U128::from(10_u128),
+                max: U128::from(6_u128),
+            }
+        );
+        assert!(!is_spent(&deps.storage, None, &change.get_nullifier_hash(1)).unwrap());
+
+        // spent in full, there's no change left to insert
+        let msg = ExecuteMsg::WithdrawWithChange(spend_change);
+        let info = mock_info("Alice", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: withdraw_msg.recipient.clone(),
+                amount: coins(6, "TKN"),
+            })]
+        );
+        assert!(!res.attributes.iter().any(|attr| attr.key == "leaf_index"));
+
+        let res = query_tree_info(deps.as_ref(), None).unwrap();
+        assert_eq!(res.next_index, 2);
+    }
+
+    #[test]
+    fn test_withdraw_callback() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+        let stake_msg = Binary::from(br#"{"stake":{}}"#);
+
+        // the callback can't redirect the proven recipient's funds
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            callback: Some(WithdrawCallback {
+                contract: "staking".to_string(),
+                msg: stake_msg.clone(),
+            }),
+            ..withdraw_msg.clone()
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::CallbackNotRecipient {});
+
+        let callback = Some(WithdrawCallback {
+            contract: withdraw_msg.recipient.clone(),
+            msg: stake_msg.clone(),
+        });
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            fee: U128::from(1_u128),
+            callback: callback.clone(),
+            ..withdraw_msg.clone()
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::CallbackWithFee {});
+
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            callback,
+            ..withdraw_msg.clone()
+        });
+        let info = mock_info("Alice", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: withdraw_msg.recipient,
+                msg: stake_msg,
+                funds: coins(10, "TKN"),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_withdraw_unexpected_funds() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Withdraw(withdraw_msg),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::UnexpectedFunds {});
+    }
+
+    #[test]
+    fn test_withdraw_fees_too_high() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        for fee in [10_u128, 11] {
+            let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+                fee: U128::from(fee),
+                ..withdraw_msg.clone()
+            });
+            let info = mock_info("Alice", &[]);
+            let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+            assert_eq!(
+                err,
+                ContractError::FeesTooHigh {
+                    fee: U128::from(fee),
+                    amount: U128::from(10_u128),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_withdraw_recipient_other_prefix() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        // encodes to the same public signal as the proven juno recipient
+        let recipient = "osmo14spgzl9ps5tyev32ny74fa6m0s9q9828jxugen".to_string();
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            recipient: recipient.clone(),
+            ..withdraw_msg
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidSignalAddress {
+                address: recipient,
+                prefix: "juno".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_withdraw_without_relayer() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            relayer: "".to_string(),
+            fee: U128::from(1_u128),
+            ..withdraw_msg.clone()
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::FeeWithoutRelayer {});
+
+        // accepted as "no relayer", then rejected by the proof made for a relayer
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            relayer: "0".to_string(),
+            fee: U128::zero(),
+            ..withdraw_msg
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidProof {});
+    }
+
+    #[test]
+    fn test_register_relayer() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            ..default_instantiate_msg()
+        };
+        let withdraw_msg = setup_withdraw(deps.as_mut(), instantiate_msg);
+
+        let register = |min_fee: u128| {
+            ExecuteMsg::RegisterRelayer(RegisterRelayerMsg {
+                address: withdraw_msg.relayer.clone(),
+                min_fee: U128::from(min_fee),
+            })
+        };
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, register(1)).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let info = mock_info("admin", &[]);
+        execute(deps.as_mut(), mock_env(), info, register(1)).unwrap();
+
+        let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::FeeBelowMinimum {
+                fee: U128::zero(),
+                min_fee: U128::from(1_u128)
+            }
+        );
+
+        // the fixture proof was made for a zero fee
+        let info = mock_info("admin", &[]);
+        execute(deps.as_mut(), mock_env(), info, register(0)).unwrap();
+
+        let msg = ExecuteMsg::Withdraw(withdraw_msg);
+        let info = mock_info("Alice", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn test_withdraw_attributes() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let info = mock_info("Alice", &[]);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Withdraw(withdraw_msg.clone()),
+        )
+        .unwrap();
+
+        let attrs: Vec<(&str, &str)> = res
+            .attributes
+            .iter()
+            .map(|a| (a.key.as_str(), a.value.as_str()))
+            .collect();
+        let fee = withdraw_msg.fee.to_string();
+        assert_eq!(
+            vec![
+                ("action", "withdraw"),
+                ("nullifier_hash", withdraw_msg.nullifier_hash.as_str()),
+                ("recipient", withdraw_msg.recipient.as_str()),
+                ("relayer", withdraw_msg.relayer.as_str()),
+                ("fee", fee.as_str()),
+            ],
+            attrs
+        );
+    }
+
+    #[test]
+    fn test_events_disabled_by_default() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let info = mock_info("Alice", &[]);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Withdraw(withdraw_msg),
+        )
+        .unwrap();
+        assert!(res.events.is_empty());
+    }
+
+    #[test]
+    fn test_withdraw_unsupported_proof() {
+        let mut deps = mock_dependencies();
+        let mut withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+        let mut proof = withdraw_msg.proof.to_circom().unwrap();
+        proof.protocol = "plonk".to_string();
+        withdraw_msg.proof = proof.into();
+
+        let info = mock_info("Alice", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Withdraw(withdraw_msg),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::Proof(ProofError::UnsupportedProtocol { .. })
+        ));
+    }
+
+    #[test]
+    fn test_query_proof_size() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+        let proof = withdraw_msg.proof.to_circom().unwrap();
+
+        let res: ProofSizeResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::ProofSize(proof)).unwrap())
+                .unwrap();
+
+        // the fixture proof's coordinates, with and without the snarkjs JSON around them
+        assert_eq!(
+            res,
+            ProofSizeResponse {
+                size: 720,
+                flat_size: 634,
+                points: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_close_pool() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("admin", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let msg = ExecuteMsg::ClosePool(ClosePoolMsg { withdrawals: true });
+        let info = mock_info("Bob", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let info = mock_info("admin", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let msg = ExecuteMsg::Deposit(DepositMsg {
+            commitment: Deposit::new("1".to_string()).get_commitment(),
+            pool_id: None,
+        });
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::PoolClosed {});
+
+        // queries keep working
+        let msg = QueryMsg::IsKnownRoot(IsKnownRootMsg {
+            root: "1".to_string(),
+            pool_id: None,
+        });
+        query(deps.as_ref(), mock_env(), msg).unwrap();
+    }
+
+    #[test]
+    fn test_close_pool_not_empty() {
+        let mut deps = mock_dependencies_with_balance(&coins(10, "TKN"));
+        let instantiate_msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            ..default_instantiate_msg()
+        };
+        let withdraw_msg = setup_withdraw(deps.as_mut(), instantiate_msg);
+
+        let msg = ExecuteMsg::ClosePool(ClosePoolMsg { withdrawals: true });
+        let info = mock_info("admin", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::PoolNotEmpty {
+                amount: U128::new(10),
+                denom: "TKN".to_string()
+            }
+        );
+
+        // closing for deposits only keeps withdrawals open
+        let msg = ExecuteMsg::ClosePool(ClosePoolMsg { withdrawals: false });
+        let info = mock_info("admin", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let info = mock_info("Alice", &[]);
+        let msg = ExecuteMsg::Withdraw(withdraw_msg);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn test_query_is_spent() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+        let spent = withdraw_msg.nullifier_hash.clone();
+
+        let info = mock_info("Alice", &[]);
+        let msg = ExecuteMsg::Withdraw(withdraw_msg);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let is_spent = |deps: Deps, nullifier_hash: &str| -> bool {
+            let msg = QueryMsg::IsSpent(IsSpentMsg {
+                nullifier_hash: nullifier_hash.to_string(),
+                pool_id: None,
+            });
+            from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap()
+        };
+        assert!(is_spent(deps.as_ref(), &spent));
+        assert!(is_spent(deps.as_ref(), &format!("00{}", spent)));
+        assert!(!is_spent(deps.as_ref(), "42"));
+
+        let msg = QueryMsg::IsSpentBatch(IsSpentBatchMsg {
+            nullifier_hashes: vec!["42".to_string(), spent, "43".to_string()],
+            pool_id: None,
+        });
+        let res: Vec<bool> = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res, vec![false, true, false]);
+
+        let msg = QueryMsg::IsSpentBatch(IsSpentBatchMsg {
+            nullifier_hashes: vec!["42".to_string(); 21],
+            pool_id: None,
+        });
+        query(deps.as_ref(), mock_env(), msg).unwrap_err();
+    }
+
+    #[test]
+    fn test_root_hex_encoding() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let hex_root = u256_to_hex(&U256::from_str(&withdraw_msg.root).unwrap());
+        let is_known_root = |deps: Deps, root: &str| {
+            let msg = QueryMsg::IsKnownRoot(IsKnownRootMsg {
+                root: root.to_string(),
+                pool_id: None,
+            });
+            query(deps, mock_env(), msg).map(|res| from_binary::<bool>(&res).unwrap())
+        };
+        assert_eq!(is_known_root(deps.as_ref(), &withdraw_msg.root), Ok(true));
+        assert_eq!(is_known_root(deps.as_ref(), &hex_root), Ok(true));
+        assert_eq!(
+            is_known_root(deps.as_ref(), &format!("0x{:0>64}", "1")),
+            Ok(false)
+        );
+        assert!(is_known_root(deps.as_ref(), &hex_root[..hex_root.len() - 1]).is_err());
+        assert!(is_known_root(deps.as_ref(), &format!("{}00", hex_root)).is_err());
+
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            root: hex_root,
+            ..withdraw_msg
+        });
+        let info = mock_info("Alice", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn test_query_known_roots() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            root_history_size: Some(3),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let mut roots = vec![COMMITMENTS.load(&deps.storage).unwrap().get_last_root()];
+        for i in 1..=4_u32 {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: i.to_string(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+            roots.push(COMMITMENTS.load(&deps.storage).unwrap().get_last_root());
+        }
+
+        let res: KnownRootsResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::KnownRoots { pool_id: None },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let expected: Vec<String> = roots[2..].iter().map(|root| root.to_string()).collect();
+        assert_eq!(res.roots, expected);
+    }
+
+    #[test]
+    fn test_query_withdrawal_count() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let withdrawal_count = |deps: Deps| -> u64 {
+            from_binary(
+                &query(
+                    deps,
+                    mock_env(),
+                    QueryMsg::WithdrawalCount { pool_id: None },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+        assert_eq!(withdrawal_count(deps.as_ref()), 0);
+
+        // failed withdrawals aren't counted
+        let info = mock_info("Alice", &coins(1, "TKN"));
+        let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(withdrawal_count(deps.as_ref()), 0);
+
+        let info = mock_info("Alice", &[]);
+        let msg = ExecuteMsg::Withdraw(withdraw_msg);
+        execute(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap();
+        assert_eq!(withdrawal_count(deps.as_ref()), 1);
+
+        // nor are replays
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(withdrawal_count(deps.as_ref()), 1);
+    }
+
+    #[test]
+    fn test_query_are_known_roots() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            root_history_size: Some(3),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let mut roots = vec![COMMITMENTS.load(&deps.storage).unwrap().get_last_root()];
+        for i in 1..=4_u32 {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: i.to_string(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+            roots.push(COMMITMENTS.load(&deps.storage).unwrap().get_last_root());
+        }
+
+        // the first two roots fell out of the history
+        let msg = QueryMsg::AreKnownRoots {
+            roots: vec![
+                roots[4].to_string(),
+                "42".to_string(),
+                roots[0].to_string(),
+                roots[2].to_string(),
+                roots[1].to_string(),
+            ],
+            pool_id: None,
+        };
+        let res: Vec<bool> = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res, vec![true, false, false, true, false]);
+
+        let msg = QueryMsg::AreKnownRoots {
+            roots: vec![],
+            pool_id: None,
+        };
+        let res: Vec<bool> = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert!(res.is_empty());
+
+        let msg = QueryMsg::AreKnownRoots {
+            roots: vec!["42".to_string(); 21],
+            pool_id: None,
+        };
+        query(deps.as_ref(), mock_env(), msg).unwrap_err();
+    }
+
+    #[test]
+    fn test_query_last_root_bytes() {
+        let mut deps = mock_dependencies();
+        setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let res: Binary = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::GetLastRootBytes { pool_id: None },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let bytes: [u8; 32] = res.to_array().unwrap();
+
+        let tree = COMMITMENTS.load(&deps.storage).unwrap();
+        assert_eq!(U256::from_le_bytes(bytes), tree.get_last_root());
+        assert_eq!(
+            U256::from_le_bytes(bytes).to_string(),
+            "7867364560627547019086598689541673085228895175200585554350937642876639323043"
+        );
+    }
+
+    #[test]
+    fn test_query_can_deposit() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("admin", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let can_deposit = |deps: Deps| -> CanDepositResponse {
+            from_binary(&query(deps, mock_env(), QueryMsg::CanDeposit { pool_id: None }).unwrap())
+                .unwrap()
+        };
+
+        assert_eq!(
+            can_deposit(deps.as_ref()),
+            CanDepositResponse {
+                allowed: true,
+                reason: None
+            }
+        );
+
+        // a full (single level) tree
+        let mut tree = MerkleTreeWithHistory::new(1);
+        tree.insert(&U256::from(1_u32)).unwrap();
+        tree.insert(&U256::from(2_u32)).unwrap();
+        COMMITMENTS.save(&mut deps.storage, &tree).unwrap();
+        assert_eq!(
+            can_deposit(deps.as_ref()),
+            CanDepositResponse {
+                allowed: false,
+                reason: Some("tree_full".to_string())
+            }
+        );
+
+        let msg = ExecuteMsg::ClosePool(ClosePoolMsg { withdrawals: false });
+        let info = mock_info("admin", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            can_deposit(deps.as_ref()),
+            CanDepositResponse {
+                allowed: false,
+                reason: Some("closed".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn test_set_paused() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            ..default_instantiate_msg()
+        };
+        let withdraw_msg = setup_withdraw(deps.as_mut(), instantiate_msg);
+
+        let paused = |deps: Deps| -> PausedResponse {
+            from_binary(&query(deps, mock_env(), QueryMsg::Paused {}).unwrap()).unwrap()
+        };
+        assert_eq!(
+            paused(deps.as_ref()),
+            PausedResponse {
+                deposits: false,
+                withdrawals: false
+            }
+        );
+
+        let msg = ExecuteMsg::SetPaused(SetPausedMsg {
+            deposits: true,
+            withdrawals: true,
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let info = mock_info("admin", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            paused(deps.as_ref()),
+            PausedResponse {
+                deposits: true,
+                withdrawals: true
+            }
+        );
+
+        let msg = ExecuteMsg::Deposit(DepositMsg {
+            commitment: "1".to_string(),
+            pool_id: None,
+        });
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::Paused {});
+
+        let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::Paused {});
+
+        // resume withdrawals only
+        let msg = ExecuteMsg::SetPaused(SetPausedMsg {
+            deposits: true,
+            withdrawals: false,
+        });
+        let info = mock_info("admin", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let msg = ExecuteMsg::Withdraw(withdraw_msg);
+        let info = mock_info("Alice", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let res: CanDepositResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::CanDeposit { pool_id: None },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.reason, Some("paused".to_string()));
+    }
+
+    #[test]
+    fn test_create_pool() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let msg = ExecuteMsg::CreatePool(CreatePoolMsg {
+            asset: AssetInfo::Native {
+                denom: "ATOM".to_string(),
+            },
+            amount: U128::from(5_u128),
+            levels: None,
+            root_history_size: None,
+        });
+        let info = mock_info("Alice", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let info = mock_info("admin", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.attributes[1].value, "1");
+
+        let (deposit, withdraw_msg) = withdraw_fixture();
+        let deposit_msg = |pool_id| {
+            ExecuteMsg::Deposit(DepositMsg {
+                commitment: deposit.clone().get_commitment(),
+                pool_id,
+            })
+        };
+
+        // each pool only takes its own denomination
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        let err = execute(deps.as_mut(), mock_env(), info, deposit_msg(Some(1))).unwrap_err();
+        assert!(matches!(err, ContractError::Payment(_)));
+
+        let info = mock_info("Alice", &coins(5, "ATOM"));
+        execute(deps.as_mut(), mock_env(), info, deposit_msg(Some(1))).unwrap();
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        execute(deps.as_mut(), mock_env(), info, deposit_msg(None)).unwrap();
+
+        let info = mock_info("Alice", &coins(5, "ATOM"));
+        let err = execute(deps.as_mut(), mock_env(), info, deposit_msg(Some(2))).unwrap_err();
+        assert_eq!(err, ContractError::UnknownPool { pool_id: 2 });
+
+        let pool = |deps: Deps, pool_id| -> PoolResponse {
+            from_binary(&query(deps, mock_env(), QueryMsg::Pool(PoolMsg { pool_id })).unwrap())
+                .unwrap()
+        };
+        let res = pool(deps.as_ref(), Some(1));
+        assert_eq!(res.amount, U128::from(5_u128));
+        assert_eq!(res.next_index, 1);
+        assert_eq!(res.last_root, withdraw_msg.root);
+        assert_eq!(pool(deps.as_ref(), None).last_root, withdraw_msg.root);
+
+        // same note in both trees, nullifiers are tracked per pool
+        let msg = ExecuteMsg::Withdraw(WithdrawMsg {
+            pool_id: Some(1),
+            ..withdraw_msg.clone()
+        });
+        let info = mock_info("Alice", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: withdraw_msg.recipient.clone(),
+                amount: coins(5, "ATOM"),
+            })
+        );
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::DuplicatedCommitment {});
+
+        let is_spent = |deps: Deps, pool_id| -> bool {
+            let msg = QueryMsg::IsSpent(IsSpentMsg {
+                nullifier_hash: withdraw_msg.nullifier_hash.clone(),
+                pool_id,
+            });
+            from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap()
+        };
+        assert!(is_spent(deps.as_ref(), Some(1)));
+        assert!(!is_spent(deps.as_ref(), None));
+
+        let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: withdraw_msg.recipient.clone(),
+                amount: coins(10, "TKN"),
+            })
+        );
+        assert!(is_spent(deps.as_ref(), None));
+
+        // the pool queries answer for the pool asked
+        let query_pool = |msg: QueryMsg| query(deps.as_ref(), mock_env(), msg);
+        let count = |pool_id| -> u64 {
+            from_binary(&query_pool(QueryMsg::WithdrawalCount { pool_id }).unwrap()).unwrap()
+        };
+        assert_eq!(count(None), 1);
+        assert_eq!(count(Some(1)), 1);
+        let total: u64 =
+            from_binary(&query_pool(QueryMsg::TotalWithdrawalCount {}).unwrap()).unwrap();
+        assert_eq!(total, 2);
+
+        let res: TreeInfoResponse =
+            from_binary(&query_pool(QueryMsg::TreeInfo { pool_id: Some(1) }).unwrap()).unwrap();
+        assert_eq!(res.next_index, 1);
+        assert_eq!(res.last_root, withdraw_msg.root);
+        let res: KnownRootsResponse =
+            from_binary(&query_pool(QueryMsg::KnownRoots { pool_id: Some(1) }).unwrap()).unwrap();
+        assert_eq!(res.roots.last(), Some(&withdraw_msg.root));
+        let res: Vec<bool> = from_binary(
+            &query_pool(QueryMsg::AreKnownRoots {
+                roots: vec![withdraw_msg.root.clone(), "42".to_string()],
+                pool_id: Some(1),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res, vec![true, false]);
+        let res: Vec<bool> = from_binary(
+            &query_pool(QueryMsg::IsSpentBatch(IsSpentBatchMsg {
+                nullifier_hashes: vec![withdraw_msg.nullifier_hash.clone(), "42".to_string()],
+                pool_id: Some(1),
+            }))
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res, vec![true, false]);
+        let res: ExportResponse = from_binary(
+            &query_pool(QueryMsg::ExportState {
+                start_after: None,
+                limit: None,
+                pool_id: Some(1),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.leaves, vec![(0, deposit.clone().get_commitment())]);
+        assert_eq!(res.current_root, withdraw_msg.root);
+        let res: CanDepositResponse =
+            from_binary(&query_pool(QueryMsg::CanDeposit { pool_id: Some(1) }).unwrap()).unwrap();
+        assert!(res.allowed);
+        let res: ConfigResponse =
+            from_binary(&query_pool(QueryMsg::Config { pool_id: Some(1) }).unwrap()).unwrap();
+        assert_eq!(res.denom, "ATOM");
+        assert_eq!(res.amount, U128::from(5_u128));
+        let res: Binary =
+            from_binary(&query_pool(QueryMsg::GetLastRootBytes { pool_id: Some(1) }).unwrap())
+                .unwrap();
+        assert_eq!(
+            U256::from_le_bytes(res.to_array().unwrap()).to_string(),
+            withdraw_msg.root
+        );
+        query_pool(QueryMsg::CanDeposit { pool_id: Some(2) }).unwrap_err();
+        query_pool(QueryMsg::Config { pool_id: Some(2) }).unwrap_err();
+        query_pool(QueryMsg::TreeInfo { pool_id: Some(2) }).unwrap_err();
+
+        // pool 1 took a deposit, its nullifiers can't be imported anymore
+        let msg = ExecuteMsg::ImportNullifiers(ImportNullifiersMsg {
+            hashes: vec!["42".to_string()],
+            pool_id: Some(1),
+        });
+        let info = mock_info("admin", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::PoolAlreadyActive {});
+    }
+
+    #[test]
+    fn test_deposit_duplicated_commitment() {
+        let mut deps = mock_dependencies();
+
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate_msg()).unwrap();
+
+        let deposit = |deps: DepsMut, commitment: &str| {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: commitment.to_string(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps, mock_env(), info, msg)
+        };
+        deposit(deps.as_mut(), "42").unwrap();
+        let err = deposit(deps.as_mut(), "42").unwrap_err();
+        assert_eq!(err, ContractError::DuplicatedCommitment {});
+        let err = deposit(deps.as_mut(), "042").unwrap_err();
+        assert_eq!(err, ContractError::DuplicatedCommitment {});
+
+        let msg = ExecuteMsg::DepositBatch(vec![
+            DepositMsg {
+                commitment: "43".to_string(),
+                pool_id: None,
+            },
+            DepositMsg {
+                commitment: "43".to_string(),
+                pool_id: None,
+            },
+        ]);
+        let info = mock_info("Alice", &coins(20, "TKN"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::DuplicatedCommitment {});
+    }
+
+    #[test]
+    fn test_deposit_out_of_field_commitment() {
+        let mut deps = mock_dependencies();
+
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate_msg()).unwrap();
+
+        let deposit = |deps: DepsMut, commitment: U256| {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: commitment.to_string(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps, mock_env(), info, msg)
+        };
+        // would alias 0 and 1 in the circuit
+        for commitment in [FIELD_MODULUS, FIELD_MODULUS + U256::from(1_u8)] {
+            let err = deposit(deps.as_mut(), commitment).unwrap_err();
+            assert_eq!(
+                err,
+                ContractError::ParseError {
+                    msg: format!(
+                        "Commitment {} is outside the BN254 scalar field",
+                        commitment
+                    ),
+                }
+            );
+        }
+        deposit(deps.as_mut(), FIELD_MODULUS - U256::from(1_u8)).unwrap();
+    }
+
+    #[test]
+    fn test_deposit_batch_mixed_pools() {
+        let mut deps = mock_dependencies();
+
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate_msg()).unwrap();
+
+        let msg = ExecuteMsg::DepositBatch(vec![
+            DepositMsg {
+                commitment: "1".to_string(),
+                pool_id: None,
+            },
+            DepositMsg {
+                commitment: "2".to_string(),
+                pool_id: Some(1),
+            },
+        ]);
+        let info = mock_info("Alice", &coins(20, "TKN"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::MixedPools {});
+    }
+
+    #[test]
+    fn test_instantiate_denom() {
+        let mut deps = mock_dependencies();
+
+        for denom in ["", "ab", "1tkn", "tkn token", &"t".repeat(129)] {
+            let instantiate_msg = InstantiateMsg {
+                denom: denom.to_string(),
+                ..default_instantiate_msg()
+            };
+            let info = mock_info("Alice", &[]);
+            let err = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap_err();
+            assert_eq!(
+                err,
+                ContractError::InvalidDenom {
+                    denom: denom.to_string()
+                }
+            );
+        }
+
+        let instantiate_msg = InstantiateMsg {
+            amount: U128::zero(),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        let err = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap_err();
+        assert_eq!(err, ContractError::ZeroAmount {});
+
+        let ibc_denom = "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2";
+        for denom in ["TKN", "ujuno", ibc_denom, "factory/juno1abc/u-tkn.1"] {
+            let instantiate_msg = InstantiateMsg {
+                denom: denom.to_string(),
+                ..default_instantiate_msg()
+            };
+            let info = mock_info("Alice", &[]);
+            instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_instantiate_levels() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            levels: Some(0),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        let err = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidLevels { levels: 0 });
+
+        let instantiate_msg = InstantiateMsg {
+            root_history_size: Some(0),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        let err = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidRootHistorySize {});
+
+        let instantiate_msg = InstantiateMsg {
+            zero_value: Some(field_modulus()),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        let err = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidZeroValue {});
+
+        let instantiate_msg = InstantiateMsg {
+            levels: Some(3),
+            root_history_size: Some(5),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        let tree = COMMITMENTS.load(&deps.storage).unwrap();
+        assert_eq!(tree.levels, 3);
+        assert_eq!(tree.root_history_size, 5);
+    }
+
+    #[test]
+    fn test_deposit_max_deposits() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg {
+            max_deposits: Some(2),
+            ..default_instantiate_msg()
+        };
+        let withdraw_msg = setup_withdraw(deps.as_mut(), instantiate_msg);
+
+        let deposit = |nullifier: &str| {
+            ExecuteMsg::Deposit(DepositMsg {
+                commitment: Deposit::new(nullifier.to_string()).get_commitment(),
+                pool_id: None,
+            })
+        };
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        execute(deps.as_mut(), mock_env(), info.clone(), deposit("1")).unwrap();
+
+        // the tree holds 2^20 leaves, the pool only 2
+        let err = execute(deps.as_mut(), mock_env(), info, deposit("2")).unwrap_err();
+        assert_eq!(err, ContractError::PoolFull {});
+        let msg = ExecuteMsg::DepositBatch(vec![DepositMsg {
+            commitment: Deposit::new("2".to_string()).get_commitment(),
+            pool_id: None,
+        }]);
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::PoolFull {});
+
+        let res: CanDepositResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::CanDeposit { pool_id: None },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.reason, Some("cap_reached".to_string()));
+
+        let info = mock_info("Alice", &[]);
+        let msg = ExecuteMsg::Withdraw(withdraw_msg);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn test_deposit_tree_full() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            levels: Some(3),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        for i in 0..8_u32 {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: (i + 1).to_string(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        }
+
+        let msg = ExecuteMsg::Deposit(DepositMsg {
+            commitment: "9".to_string(),
+            pool_id: None,
+        });
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::TreeFull {});
+    }
+
+    #[test]
+    fn test_query_export_state() {
+        let mut deps = mock_dependencies();
+
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate_msg()).unwrap();
+
+        let commitments: Vec<String> = (1..=5)
+            .map(|i| Deposit::new(i.to_string()).get_commitment())
+            .collect();
+        for commitment in &commitments {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: commitment.clone(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        }
+
+        let export = |deps: Deps, start_after, limit| -> ExportResponse {
+            let msg = QueryMsg::ExportState {
+                start_after,
+                limit,
+                pool_id: None,
+            };
+            from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap()
+        };
+
+        let res = export(deps.as_ref(), None, None);
+        assert_eq!(res.next_index, 5);
+        let expected: Vec<(u32, String)> = commitments
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i as u32, c.clone()))
+            .collect();
+        assert_eq!(res.leaves, expected);
+
+        // rebuild the tree locally from the exported leaves
+        let mut tree = MerkleTreeWithHistory::new(20);
+        for (_, commitment) in &res.leaves {
+            tree.insert(&U256::from_str(commitment).unwrap()).unwrap();
+        }
+        assert_eq!(tree.get_last_root().to_string(), res.current_root);
+
+        let page = export(deps.as_ref(), Some(1), Some(2));
+        assert_eq!(page.leaves, expected[2..4].to_vec());
+    }
+
+    #[test]
+    fn test_query_merkle_path() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            levels: Some(4),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let merkle_path = |deps: Deps, leaf_index| {
+            let msg = QueryMsg::MerklePath {
+                leaf_index,
+                pool_id: None,
+            };
+            query(deps, mock_env(), msg).map(|res| from_binary::<MerklePathResponse>(&res).unwrap())
+        };
+        assert!(merkle_path(deps.as_ref(), 0).is_err());
+
+        let commitments: Vec<String> = (1..=5_u32).map(|i| i.to_string()).collect();
+        for commitment in &commitments {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: commitment.clone(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        }
+
+        let tree = COMMITMENTS.load(&deps.storage).unwrap();
+        for (leaf_index, commitment) in commitments.iter().enumerate() {
+            let res = merkle_path(deps.as_ref(), leaf_index as u32).unwrap();
+            assert_eq!(res.root, tree.get_last_root().to_string());
+
+            let path = MerklePath {
+                path_elements: res
+                    .path_elements
+                    .iter()
+                    .map(|element| U256::from_str(element).unwrap())
+                    .collect(),
+                path_indices: res.path_indices,
+            };
+            assert!(tree.verify_merkle_proof(&U256::from_str(commitment).unwrap(), &path));
+        }
+        assert!(merkle_path(deps.as_ref(), 5).is_err());
+    }
+
+    #[test]
+    fn test_instantiate_invalid_verification_key() {
+        let mut vk = mock_partial_vk();
+        vk.vk_alpha_1.pop();
+
+        for instantiate_msg in [
+            InstantiateMsg {
+                verification_key: Some(vk.clone()),
+                ..default_instantiate_msg()
+            },
+            InstantiateMsg {
+                partial_verification_key: Some(vk.clone()),
+                ..default_instantiate_msg()
+            },
+        ] {
+            let mut deps = mock_dependencies();
+            let info = mock_info("Alice", &[]);
+            let err = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap_err();
+            assert_eq!(err, ContractError::InvalidVerificationKey {});
+        }
+    }
+
+    #[test]
+    fn test_instantiate_initial_commitments() {
+        let mut deps = mock_dependencies();
+
+        let commitments: Vec<String> = (1..=5_u32).map(|i| i.to_string()).collect();
+        let instantiate_msg = InstantiateMsg {
+            levels: Some(3),
+            initial_commitments: commitments.clone(),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let mut expected = MerkleTreeWithHistory::new(3);
+        for commitment in &commitments {
+            expected
+                .insert(&U256::from_str(commitment).unwrap())
+                .unwrap();
+        }
+        let res: TreeInfoResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::TreeInfo { pool_id: None },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.next_index, 5);
+        assert_eq!(res.last_root, expected.get_last_root().to_string());
+        assert!(is_known_root(&deps.storage, None, &expected, &expected.get_last_root()).unwrap());
+        assert_eq!(LEAVES.load(&deps.storage, 4).unwrap(), "5");
+
+        // preloaded commitments can't be deposited again
+        let msg = ExecuteMsg::Deposit(DepositMsg {
+            commitment: "3".to_string(),
+            pool_id: None,
+        });
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::DuplicatedCommitment {});
+
+        // more than the tree holds
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg {
+            levels: Some(2),
+            initial_commitments: commitments,
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        let err = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap_err();
+        assert_eq!(err, ContractError::TreeFull {});
+
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg {
+            initial_commitments: vec!["1".to_string(), field_modulus().to_string()],
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        let err = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap_err();
+        assert!(matches!(err, ContractError::ParseError { .. }));
+    }
+
+    // Every message kind serializes under a variant its schema declares, and
+    // every query has a response schema
+    #[test]
+    fn test_msgs_match_schema() {
+        use cosmwasm_schema::{schema_for, QueryResponses};
+        use schemars::schema::{RootSchema, Schema};
+
+        let top_level_key = |json: &Binary| -> String {
+            let json = String::from_utf8(json.to_vec()).unwrap();
+            json[2..].split('"').next().unwrap().to_string()
+        };
+        // keys of the `{"<variant>": ...}` objects the enum schema is one of
+        let variants = |schema: RootSchema| -> Vec<String> {
+            schema
+                .schema
+                .subschemas
+                .unwrap()
+                .one_of
+                .unwrap()
+                .into_iter()
+                .flat_map(|variant| match variant {
+                    Schema::Object(object) => object.object.unwrap().required,
+                    Schema::Bool(_) => Default::default(),
+                })
+                .collect()
+        };
+        let execute_variants = variants(schema_for!(ExecuteMsg));
+        let query_variants = variants(schema_for!(QueryMsg));
+        let responses = QueryMsg::response_schemas().unwrap();
+
+        let (_, withdraw_msg) = withdraw_fixture();
+        let execute_msgs = vec![
+            ExecuteMsg::Deposit(DepositMsg {
+                commitment: "1".to_string(),
+                pool_id: Some(1),
+            }),
+            ExecuteMsg::Withdraw(withdraw_msg.clone()),
+            ExecuteMsg::RebuildTree(RebuildTreeMsg { pool_id: None }),
+            ExecuteMsg::FundFees(FundFeesMsg {}),
+        ];
+        for msg in execute_msgs {
+            let json = to_binary(&msg).unwrap();
+            assert_eq!(from_binary::<ExecuteMsg>(&json).unwrap(), msg);
+            assert!(execute_variants.contains(&top_level_key(&json)));
+        }
+
+        let query_msgs = vec![
+            QueryMsg::TreeInfo { pool_id: None },
+            QueryMsg::Config { pool_id: None },
+            QueryMsg::MerklePath {
+                leaf_index: 0,
+                pool_id: None,
+            },
+            QueryMsg::IsSpent(IsSpentMsg {
+                nullifier_hash: "1".to_string(),
+                pool_id: None,
+            }),
+            QueryMsg::WithdrawalCount { pool_id: None },
+            QueryMsg::TotalWithdrawalCount {},
+            QueryMsg::VerifyWithdraw(withdraw_msg),
+        ];
+        for msg in query_msgs {
+            let json = to_binary(&msg).unwrap();
+            assert_eq!(from_binary::<QueryMsg>(&json).unwrap(), msg);
+            let key = top_level_key(&json);
+            assert!(query_variants.contains(&key));
+            assert!(responses.contains_key(&key));
+        }
+    }
+
+    #[test]
+    fn test_query_tree_info() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            levels: Some(3),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let tree_info = |deps: Deps| -> TreeInfoResponse {
+            from_binary(&query(deps, mock_env(), QueryMsg::TreeInfo { pool_id: None }).unwrap())
+                .unwrap()
+        };
+
+        let res = tree_info(deps.as_ref());
+        assert_eq!(res.levels, 3);
+        assert_eq!(res.next_index, 0);
+        assert_eq!(res.capacity, 8);
+
+        for i in 1..=3_u32 {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: i.to_string(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+            let res = tree_info(deps.as_ref());
+            assert_eq!(res.next_index, i);
+            assert_eq!((res.used, res.capacity), (i as u64, 8));
+            assert_eq!(
+                res.last_root,
+                COMMITMENTS
+                    .load(&deps.storage)
+                    .unwrap()
+                    .get_last_root()
+                    .to_string()
+            );
+        }
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn test_query_debug_state() {
+        use crate::msg::DebugStateResponse;
+
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            levels: Some(3),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        for i in 1..=3_u32 {
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: i.to_string(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        }
+
+        let msg = QueryMsg::DebugState { pool_id: None };
+        let res: DebugStateResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        let tree = COMMITMENTS.load(&deps.storage).unwrap();
+        let strings = |values: &[U256]| values.iter().map(U256::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            res,
+            DebugStateResponse {
+                filled_subtrees: strings(&tree.filled_subtrees),
+                zeros: strings(&tree.zeros),
+                next_index: 3,
+                current_root_index: tree.current_root_index,
+            }
+        );
+    }
+
+    #[test]
+    fn test_instantiate_verification_key() {
+        let mut deps = mock_dependencies();
+
+        let vk: VerifyingKeyJson = from_slice(include_bytes!(
+            "../../../circuits/build/verification_key.json"
+        ))
+        .unwrap();
+        let instantiate_msg = InstantiateMsg {
+            verification_key: Some(vk),
+            ..default_instantiate_msg()
+        };
+        let withdraw_msg = setup_withdraw(deps.as_mut(), instantiate_msg);
+
+        let info = mock_info("Alice", &[]);
+        let msg = ExecuteMsg::Withdraw(withdraw_msg);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn test_query_config() {
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg {
+            levels: Some(7),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let res: ConfigResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Config { pool_id: None },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            res,
+            ConfigResponse {
+                denom: "TKN".to_string(),
+                amount: U128::from(10_u128),
+                levels: 7,
+                contract_version: CONTRACT_VERSION.to_string(),
+                fee_denom: None,
+                fee_reserve: U128::zero(),
+                max_fee: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_query_verification_key() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let res: Binary =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::VerificationKey {}).unwrap())
+                .unwrap();
+        let vk: VerifyingKeyJson = from_slice(&res).unwrap();
+        let verifier = Verifier::from_vk(vk).unwrap();
+
+        let public_signals = PublicSignals::from_values(
+            withdraw_msg.root,
+            withdraw_msg.nullifier_hash,
+            withdraw_msg.recipient,
+            withdraw_msg.relayer,
+            withdraw_msg.fee,
+        )
+        .unwrap();
+        assert_eq!(
+            verifier.verify_proof(
+                withdraw_msg.proof.to_proof().unwrap(),
+                &public_signals.get()
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_migrate_verification_key() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+        let tree = COMMITMENTS.load(&deps.storage).unwrap();
+
+        // a key from another trusted setup
+        let mut vk: VerifyingKeyJson = from_slice(include_bytes!(
+            "../../../circuits/build/verification_key.json"
+        ))
+        .unwrap();
+        vk.vk_alpha_1 = vec!["1".to_string(), "2".to_string(), "1".to_string()];
+
+        let msg = MigrateMsg {
+            verification_key: Some(vk),
+            partial_verification_key: None,
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(COMMITMENTS.load(&deps.storage).unwrap(), tree);
+
+        let info = mock_info("Alice", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Withdraw(withdraw_msg),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidProof {});
+    }
+
+    #[test]
+    fn test_known_roots_index() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            root_history_size: Some(3),
+            ..default_instantiate_msg()
+        };
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let mut roots = vec![U256::zero(), U256::from(1_u8)];
+        let assert_parity = |deps: Deps, roots: &[U256]| {
+            let tree = COMMITMENTS.load(deps.storage).unwrap();
+            for root in roots {
+                assert_eq!(
+                    is_known_root(deps.storage, None, &tree, root).unwrap(),
+                    tree.is_known_root(root),
+                    "root {}",
+                    root
+                );
+            }
+        };
+
+        // wraps the history twice, the zero value leaf repeats the last root
+        // in two slots
+        let commitments: Vec<U256> = (1..=4_u64)
+            .map(U256::from)
+            .chain([default_zero_value(), U256::from(5_u64)])
+            .collect();
+        for commitment in commitments {
+            roots.push(COMMITMENTS.load(&deps.storage).unwrap().get_last_root());
+            assert_parity(deps.as_ref(), &roots);
+
+            let msg = ExecuteMsg::Deposit(DepositMsg {
+                commitment: commitment.to_string(),
+                pool_id: None,
+            });
+            let info = mock_info("Alice", &coins(10, "TKN"));
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        }
+        roots.push(COMMITMENTS.load(&deps.storage).unwrap().get_last_root());
+        assert_parity(deps.as_ref(), &roots);
+        // the evicted genesis root keeps its entry, recorded with too few leaves
+        // to be known
+        let tree = COMMITMENTS.load(&deps.storage).unwrap();
+        assert!(!is_known_root(&deps.storage, None, &tree, &roots[2]).unwrap());
+        assert_eq!(
+            KNOWN_ROOTS.load(&deps.storage, (0, roots[2].to_string())),
+            Ok(0)
+        );
+
+        // migrating rebuilds a missing index
+        for root in &roots {
+            KNOWN_ROOTS.remove(&mut deps.storage, (0, root.to_string()));
+        }
+        let msg = MigrateMsg {
+            verification_key: None,
+            partial_verification_key: None,
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_parity(deps.as_ref(), &roots);
+    }
+
+    #[test]
+    fn test_migrate_from_baseline() {
+        // the only state the first release kept, its tree without the fields added since
+        #[allow(non_snake_case)]
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct BaselineTree {
+            levels: u32,
+            filled_subtrees: Vec<U256>,
+            zeros: Vec<U256>,
+            current_root_index: u32,
+            next_index: u32,
+            roots: Vec<U256>,
+            ZERO_VALUE: U256,
+        }
+        let mut deps = mock_dependencies();
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "0.1.0").unwrap();
+        BASE_COIN
+            .save(&mut deps.storage, &Coin::new(10, "TKN"))
+            .unwrap();
+        VERIFIER.save(&mut deps.storage, &Verifier::new()).unwrap();
+        let tree = MerkleTreeWithHistory::new(20);
+        let baseline_tree = BaselineTree {
+            levels: tree.levels,
+            filled_subtrees: tree.filled_subtrees.clone(),
+            zeros: tree.zeros.clone(),
+            current_root_index: tree.current_root_index,
+            next_index: tree.next_index,
+            roots: tree.roots.clone(),
+            ZERO_VALUE: tree.ZERO_VALUE,
+        };
+        cw_storage_plus::Item::new("COMMITMENTS")
+            .save(&mut deps.storage, &baseline_tree)
+            .unwrap();
+
+        let msg = MigrateMsg {
+            verification_key: None,
+            partial_verification_key: None,
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+
+        let (deposit, withdraw_msg) = withdraw_fixture();
+        let msg = ExecuteMsg::Deposit(DepositMsg {
+            commitment: deposit.get_commitment(),
+            pool_id: None,
+        });
+        let info = mock_info("Alice", &coins(10, "TKN"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let info = mock_info("Alice", &[]);
+        let msg = ExecuteMsg::Withdraw(withdraw_msg.clone());
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: withdraw_msg.recipient,
+                amount: coins(10, "TKN"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_migrate_rejects_downgrade() {
+        let mut deps = mock_dependencies();
+        let info = mock_info("Alice", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate_msg()).unwrap();
+
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let msg = MigrateMsg {
+            verification_key: None,
+            partial_verification_key: None,
+        };
+        let err = migrate(deps.as_mut(), mock_env(), msg.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotMigrate {
+                previous_contract: CONTRACT_NAME.to_string(),
+                previous_version: "99.0.0".to_string(),
+            }
+        );
+
+        set_contract_version(&mut deps.storage, "crates.io:cw20-base", "0.1.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::CannotMigrate { .. }));
+    }
+
+    #[test]
+    fn test_withdraw_signal_count_mismatch() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        // a key for a circuit with 6 public inputs
+        let mut vk: VerifyingKeyJson = from_slice(include_bytes!(
+            "../../../circuits/build/verification_key.json"
+        ))
+        .unwrap();
+        vk.ic.push(vk.ic[0].clone());
+
+        let verifier = Verifier::from_vk(vk).unwrap();
+        assert_eq!(verifier.public_inputs_count(), 6);
+        VERIFIER.save(&mut deps.storage, &verifier).unwrap();
+
+        let info = mock_info("Alice", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Withdraw(withdraw_msg),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::SignalCountMismatch {
+                expected: 6,
+                got: 5
+            }
+        );
+    }
+
+    #[test]
+    fn test_withdraw_point_not_on_curve() {
+        let mut deps = mock_dependencies();
+        let mut withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let mut proof = withdraw_msg.proof.to_circom().unwrap();
+        proof.pi_c[1] = "1".to_string();
+        withdraw_msg.proof = proof.into();
+
+        let info = mock_info("Alice", &[]);
+        let msg = ExecuteMsg::Withdraw(withdraw_msg);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Proof(ProofError::PointNotOnCurve {
+                point: "pi_c".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_reveal_withdraw_without_commit() {
+        let mut deps = mock_dependencies();
+        let withdraw_msg = setup_withdraw(deps.as_mut(), default_instantiate_msg());
+
+        let msg = ExecuteMsg::RevealWithdraw(RevealWithdrawMsg {
+            withdraw: withdraw_msg,
+            salt: "1337".to_string(),
+        });
+        let info = mock_info("Bob", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::UnknownWithdrawCommitment {});
     }
 
     // #[test]
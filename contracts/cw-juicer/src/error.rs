@@ -1,5 +1,8 @@
 use cosmwasm_std::{StdError, Uint128};
 use cw_utils::PaymentError;
+use juicer::merkle_tree::MerkleTreeError;
+use juicer::msg::{ProofError, SignalError};
+use juicer::verifier::VerifierError;
 
 use thiserror::Error;
 
@@ -32,12 +35,30 @@ pub enum ContractError {
     #[error("Invalid amount (this contract authorize only {amount} {denom})")]
     InvalidAmount { amount: Uint128, denom: String },
 
-    #[error("fees to high: {msg}")]
-    FeesTooHigh { msg: String },
+    #[error("Invalid denom {denom:?} (must match [a-zA-Z][a-zA-Z0-9/:._-]{{2,127}})")]
+    InvalidDenom { denom: String },
+
+    #[error("Pool amount must be greater than zero")]
+    ZeroAmount {},
+
+    #[error("Fees too high: {fee} (must be less than {amount})")]
+    FeesTooHigh { fee: Uint128, amount: Uint128 },
 
     #[error("Not enough founds")]
     NotEnoughFounds {},
 
+    #[error("Withdraw doesn't accept funds")]
+    UnexpectedFunds {},
+
+    #[error("A fee can only be paid to a relayer")]
+    FeeWithoutRelayer {},
+
+    #[error("Relayer can't be the recipient")]
+    SelfRelay {},
+
+    #[error("Pool doesn't accept this asset")]
+    WrongAsset {},
+
     #[error("Commitment has already been sent")]
     DuplicatedCommitment {},
 
@@ -47,6 +68,189 @@ pub enum ContractError {
     #[error("Unknown root")]
     UnknownRoot {},
 
+    #[error("Root is no longer in the root history, prove against a recent root")]
+    RootExpired {},
+
     #[error("Invalid Proof")]
     InvalidProof {},
+
+    #[error("{0}")]
+    Proof(#[from] ProofError),
+
+    #[error("Invalid verification key")]
+    InvalidVerificationKey {},
+
+    #[error("Malformed proof")]
+    MalformedProof {},
+
+    #[error("Expected {expected} public signals, got {got}")]
+    SignalCountMismatch { expected: usize, got: usize },
+
+    #[error("{address} isn't a canonical {prefix} address")]
+    InvalidSignalAddress { address: String, prefix: String },
+
+    #[error("Withdraw commitment has already been sent")]
+    DuplicatedWithdrawCommitment {},
+
+    #[error("Unknown withdraw commitment")]
+    UnknownWithdrawCommitment {},
+
+    #[error("Withdraw commitment must be revealed in a later block")]
+    WithdrawCommitmentTooRecent {},
+
+    #[error("Leaf {leaf_index} is missing")]
+    MissingLeaf { leaf_index: u32 },
+
+    #[error("Withdraw commitment has expired")]
+    WithdrawCommitmentExpired {},
+
+    #[error("Withdrawals must go through CommitWithdraw and RevealWithdraw")]
+    CommitRevealRequired {},
+
+    #[error("Nullifiers or trees can only be imported before the first deposit")]
+    PoolAlreadyActive {},
+
+    #[error("Tree snapshot is inconsistent")]
+    InvalidSnapshot {},
+
+    #[error("Tree snapshot levels or zero value differ from the pool's")]
+    SnapshotMismatch {},
+
+    #[error("Batch too large (max {max} items)")]
+    BatchTooLarge { max: u32 },
+
+    #[error("Merkle tree is full")]
+    TreeFull {},
+
+    #[error("Pool reached its maximum number of deposits")]
+    PoolFull {},
+
+    #[error("Merkle tree insert failed to hash the new leaf")]
+    InsertFailed {},
+
+    #[error("Invalid merkle tree levels {levels} (must be within 1..=31)")]
+    InvalidLevels { levels: u32 },
+
+    #[error("Root history size must be greater than zero")]
+    InvalidRootHistorySize {},
+
+    #[error("Zero value must be below the BN254 scalar field modulus")]
+    InvalidZeroValue {},
+
+    #[error("Pool is closed")]
+    PoolClosed {},
+
+    #[error("Pool is paused")]
+    Paused {},
+
+    #[error("Fee {fee} is below the relayer minimum of {min_fee}")]
+    FeeBelowMinimum { fee: Uint128, min_fee: Uint128 },
+
+    #[error("Fee {fee} exceeds the fee reserve of {available}")]
+    FeeReserveTooLow { fee: Uint128, available: Uint128 },
+
+    #[error("Fee {fee} is above the maximum of {max_fee}")]
+    FeeAboveMaximum { fee: Uint128, max_fee: Uint128 },
+
+    #[error("A fee denom needs a maximum fee")]
+    MissingMaxFee {},
+
+    #[error("Relayer fees are paid out of the notes, no fee denom is set")]
+    NoFeeDenom {},
+
+    #[error("Root is too recent to withdraw against")]
+    TooEarly {},
+
+    #[error("Root doesn't have enough confirmations yet")]
+    RootUnconfirmed {},
+
+    #[error("Unknown pool {pool_id}")]
+    UnknownPool { pool_id: u32 },
+
+    #[error("All batch items must target the same pool")]
+    MixedPools {},
+
+    #[error("Callback contract must be the recipient")]
+    CallbackNotRecipient {},
+
+    #[error("Callback withdrawals can't pay a relayer fee")]
+    CallbackWithFee {},
+
+    #[error("Callback withdrawals can't be split")]
+    CallbackWithSplit {},
+
+    #[error("Split withdrawals need at least one payout")]
+    EmptySplit {},
+
+    #[error("Recipient must be the first payout of a split withdrawal")]
+    SplitRecipient {},
+
+    #[error("Payouts must be greater than zero")]
+    ZeroPayout {},
+
+    #[error("Payouts sum to {total}, expected {expected} (the amount less the fee)")]
+    SplitMismatch { total: Uint128, expected: Uint128 },
+
+    #[error("Partial withdrawals aren't supported by this pool")]
+    PartialWithdrawUnsupported {},
+
+    #[error("Withdraw amount {amount} exceeds the note amount {max}")]
+    AmountTooHigh { amount: Uint128, max: Uint128 },
+
+    #[error("A deposit fee needs a fee collector or an admin")]
+    MissingFeeCollector {},
+
+    #[error("Pool still holds {amount} {denom}")]
+    PoolNotEmpty { amount: Uint128, denom: String },
+
+    #[error("Cannot migrate from {previous_contract} {previous_version}")]
+    CannotMigrate {
+        previous_contract: String,
+        previous_version: String,
+    },
+}
+
+impl From<VerifierError> for ContractError {
+    fn from(err: VerifierError) -> Self {
+        match err {
+            VerifierError::MalformedProof => ContractError::MalformedProof {},
+            VerifierError::InputCountMismatch { expected, got } => {
+                ContractError::SignalCountMismatch { expected, got }
+            }
+            VerifierError::PairingCheckFailed => ContractError::InvalidProof {},
+            VerifierError::InvalidKey => ContractError::InvalidVerificationKey {},
+            // signals are parsed into the field before verifying
+            VerifierError::InputOutOfField { .. } => ContractError::ParseError {
+                msg: err.to_string(),
+            },
+        }
+    }
+}
+
+// check_spend validates the message's fields before building the signals
+impl From<SignalError> for ContractError {
+    fn from(err: SignalError) -> Self {
+        ContractError::ParseError {
+            msg: err.to_string(),
+        }
+    }
+}
+
+impl From<MerkleTreeError> for ContractError {
+    fn from(err: MerkleTreeError) -> Self {
+        match err {
+            MerkleTreeError::TreeFull => ContractError::TreeFull {},
+            MerkleTreeError::InvalidLevels { levels } => ContractError::InvalidLevels { levels },
+            MerkleTreeError::InvalidRootHistorySize => ContractError::InvalidRootHistorySize {},
+            MerkleTreeError::InvalidZeroValue => ContractError::InvalidZeroValue {},
+            MerkleTreeError::InvalidSnapshot => ContractError::InvalidSnapshot {},
+            // only built from an imported snapshot
+            MerkleTreeError::InvalidPoseidonParams => ContractError::InvalidSnapshot {},
+            // the contract keeps leaves in LEAVES and hands them to the tree
+            MerkleTreeError::LeavesNotKept => {
+                ContractError::Std(StdError::generic_err("Tree leaves aren't kept"))
+            }
+            MerkleTreeError::HashFailed => ContractError::InsertFailed {},
+        }
+    }
 }
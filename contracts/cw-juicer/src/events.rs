@@ -0,0 +1,57 @@
+use cosmwasm_std::{Event, Timestamp, Uint128};
+
+// Bumped whenever an event loses or renames an attribute, new attributes
+// are only appended
+pub const SCHEMA_VERSION: &str = "1";
+
+// Event schema, keys follow the tornado.cash `Deposit`/`Withdrawal` events:
+//   deposit:    schema_version, commitment, leaf_index, timestamp
+//   withdrawal: schema_version, nullifier_hash, to, relayer, fee
+pub fn deposit_event(commitment: &str, leaf_index: u32, timestamp: Timestamp) -> Event {
+    Event::new("deposit")
+        .add_attribute("schema_version", SCHEMA_VERSION)
+        .add_attribute("commitment", commitment)
+        .add_attribute("leaf_index", leaf_index.to_string())
+        .add_attribute("timestamp", timestamp.seconds().to_string())
+}
+
+pub fn withdraw_event(nullifier_hash: &str, to: &str, relayer: &str, fee: Uint128) -> Event {
+    Event::new("withdrawal")
+        .add_attribute("schema_version", SCHEMA_VERSION)
+        .add_attribute("nullifier_hash", nullifier_hash)
+        .add_attribute("to", to)
+        .add_attribute("relayer", relayer)
+        .add_attribute("fee", fee.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(event: &Event) -> Vec<&str> {
+        event.attributes.iter().map(|a| a.key.as_str()).collect()
+    }
+
+    #[test]
+    fn test_deposit_event() {
+        let event = deposit_event("42", 3, Timestamp::from_seconds(1_600_000_000));
+        assert_eq!(event.ty, "deposit");
+        assert_eq!(
+            keys(&event),
+            vec!["schema_version", "commitment", "leaf_index", "timestamp"]
+        );
+        assert_eq!(event.attributes[0].value, SCHEMA_VERSION);
+        assert_eq!(event.attributes[3].value, "1600000000");
+    }
+
+    #[test]
+    fn test_withdraw_event() {
+        let event = withdraw_event("42", "juno1recipient", "", Uint128::zero());
+        assert_eq!(event.ty, "withdrawal");
+        assert_eq!(
+            keys(&event),
+            vec!["schema_version", "nullifier_hash", "to", "relayer", "fee"]
+        );
+        assert_eq!(event.attributes[0].value, SCHEMA_VERSION);
+    }
+}
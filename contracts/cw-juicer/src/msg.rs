@@ -1,45 +1,515 @@
-use juicer::msg::CircomProof;
+use cosmwasm_schema::QueryResponses;
+use juicer::merkle_tree::TreeSnapshot;
+use juicer::msg::{CircomProof, ProofInput};
+use juicer::verifier::VerifyingKeyJson;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 // use cosmwasm_std::{Addr, Api, Coin, StdResult};
-use cosmwasm_std::Uint128 as U128;
+use cosmwasm_std::{Binary, Uint128 as U128, Uint256 as U256};
+use cw20::Cw20ReceiveMsg;
 
-#[derive(Serialize, Deserialize, JsonSchema)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AssetInfo {
+    Native { denom: String },
+    Cw20 { address: String },
+}
+
+impl AssetInfo {
+    // Native denom or CW20 token address
+    pub fn denom(&self) -> &str {
+        match self {
+            AssetInfo::Native { denom } => denom,
+            AssetInfo::Cw20 { address } => address,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct InstantiateMsg {
     pub denom: String,
     pub amount: U128,
+
+    // Pooled asset, defaults to the native `denom`
+    pub asset: Option<AssetInfo>,
+
+    pub admin: Option<String>,
+
+    // Also emit `deposit`/`withdrawal` events for indexers
+    #[serde(default)]
+    pub emit_events: bool,
+
+    // Max number of items accepted by batch messages, defaults to 20
+    pub max_batch_size: Option<u32>,
+
+    // Depth of the commitments merkle tree, defaults to 20
+    pub levels: Option<u32>,
+
+    // Number of recent roots a withdrawal can be proven against, defaults to 100
+    pub root_history_size: Option<u32>,
+
+    // Leaf of empty tree slots, must match the circuit. Defaults to tornado.cash's
+    // keccak256("tornado") % FIELD_SIZE and is shared by pools created later
+    pub zero_value: Option<U256>,
+
+    // snarkjs `verification_key.json`, defaults to the bundled circuit key
+    pub verification_key: Option<VerifyingKeyJson>,
+
+    // Key of the partial withdraw circuit, `WithdrawWithChange` is disabled without it
+    pub partial_verification_key: Option<VerifyingKeyJson>,
+
+    // Bech32 prefix of withdrawal addresses, defaults to "juno"
+    pub bech32_prefix: Option<String>,
+
+    // Blocks between a deposit and its withdrawal, defaults to 0
+    pub min_delay_blocks: Option<u64>,
+
+    // Blocks a root must be confirmed by before withdrawals are proven against it,
+    // against roots of reorged blocks. Defaults to 0
+    pub min_confirmations: Option<u64>,
+
+    // Paid in the pool's asset on top of every note, defaults to 0
+    pub deposit_fee: Option<U128>,
+    // Receives deposit fees, defaults to the admin
+    pub fee_collector: Option<String>,
+
+    // Native denom relayer fees are paid in, out of a reserve funded with
+    // `FundFees` and kept apart from the pools. Recipients then get their whole
+    // note. Defaults to the pool's asset, the fee coming out of the note
+    pub fee_denom: Option<String>,
+    // Most a withdrawal's relayer fee may be, so a single withdrawal can't drain
+    // the reserve. Required with `fee_denom`, unbounded otherwise
+    pub max_fee: Option<U128>,
+
+    // Deposits a pool accepts below its tree capacity, shared by pools created
+    // later. Withdrawals keep working once reached
+    pub max_deposits: Option<u32>,
+
+    // Reject withdrawals relayed by their own recipient instead of paying
+    // them the whole amount at once
+    #[serde(default)]
+    pub reject_self_relay: bool,
+
+    // Only withdraw through CommitWithdraw and RevealWithdraw, so a withdrawal
+    // can't be front-run. Plain Withdraw is refused, partial withdrawals have no
+    // reveal and stay open
+    #[serde(default)]
+    pub require_commit_reveal: bool,
+    // Blocks a withdraw commit can be revealed in before it's dropped, defaults
+    // to 14_400 (about a day)
+    pub withdraw_commit_expiry: Option<u64>,
+
+    // Leaves of the pool this one replaces, inserted in order so its anonymity
+    // set carries over. The funds backing them are moved out of band
+    #[serde(default)]
+    pub initial_commitments: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct DepositMsg {
     pub commitment: String,
+    // Pool created with CreatePool, the instantiate pool when unset
+    pub pool_id: Option<u32>,
 }
 
+// Set as the data of a Deposit (or cw20 Receive) response
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DepositResponse {
+    pub leaf_index: u32,
+    pub commitment: String,
+    // root after inserting the commitment
+    pub root: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct WithdrawMsg {
-    pub proof: CircomProof,
+    pub proof: ProofInput,
 
     pub root: String,
     pub nullifier_hash: String,
     pub recipient: String,
     pub relayer: String,
     pub fee: U128,
+    pub pool_id: Option<u32>,
+    // pays the recipient contract through an execute carrying `msg` instead
+    pub callback: Option<WithdrawCallback>,
+    // splits the note less the fee between these payouts, `recipient` being the
+    // first one. The proof's recipient signal is then their `split_commitment_hash`
+    pub recipients: Option<Vec<Payout>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Payout {
+    pub address: String,
+    pub amount: U128,
+}
+
+// `contract` must be the proven recipient, `msg` isn't bound by the proof
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WithdrawCallback {
+    pub contract: String,
+    pub msg: Binary,
+}
+
+// Spends a note holding `note_value`, the pool amount for a deposit, for `amount`
+// (fee included). The rest goes to a change note, whose leaf the contract builds
+// as `change_commitment(change_commitment, note_value - amount)`. The proof opens
+// the spent note for `note_value`, so neither value can be inflated.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WithdrawPartialMsg {
+    pub withdraw: WithdrawMsg,
+    pub change_commitment: String,
+    pub note_value: U128,
+    pub amount: U128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CommitWithdrawMsg {
+    // poseidon(nullifier_hash, recipient, salt)
+    pub commitment_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RevealWithdrawMsg {
+    pub withdraw: WithdrawMsg,
+    pub salt: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ImportNullifiersMsg {
+    pub hashes: Vec<String>,
+    pub pool_id: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FundFeesMsg {}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RebuildTreeMsg {
+    pub pool_id: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ImportTreeMsg {
+    // `MerkleTreeWithHistory::export` of the migrated pool, with its leaves to
+    // keep serving merkle paths
+    pub snapshot: TreeSnapshot,
+    pub pool_id: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ClosePoolMsg {
+    // also block withdrawals, only allowed once the pool is empty
+    #[serde(default)]
+    pub withdrawals: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreatePoolMsg {
+    pub asset: AssetInfo,
+    pub amount: U128,
+    // Same defaults as instantiate
+    pub levels: Option<u32>,
+    pub root_history_size: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterRelayerMsg {
+    pub address: String,
+    // in units of the withdrawn pool's asset
+    pub min_fee: U128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SetPausedMsg {
+    pub deposits: bool,
+    pub withdrawals: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MigrateMsg {
+    // Replaces the stored verifier when set
+    pub verification_key: Option<VerifyingKeyJson>,
+    // Replaces the partial withdraw verifier when set
+    pub partial_verification_key: Option<VerifyingKeyJson>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
     Deposit(DepositMsg),
+    // Several notes in one transaction, paying `amount` per commitment
+    DepositBatch(Vec<DepositMsg>),
     Withdraw(WithdrawMsg),
+    // Withdraw part of a note and insert a change commitment for the rest, atomically.
+    // Also accepted under its former name
+    #[serde(alias = "withdraw_partial")]
+    WithdrawWithChange(WithdrawPartialMsg),
+
+    // Two-step withdrawal: commit to (nullifier_hash, recipient, salt) first,
+    // then reveal in a later block so the recipient can't be front-run
+    CommitWithdraw(CommitWithdrawMsg),
+    RevealWithdraw(RevealWithdrawMsg),
+
+    // Admin only, carry over the spent nullifiers of a migrated pool
+    ImportNullifiers(ImportNullifiersMsg),
+    // Admin only, carry over the commitments tree of a migrated pool
+    ImportTree(ImportTreeMsg),
+
+    // Admin only, recompute a pool's tree from its stored leaves
+    RebuildTree(RebuildTreeMsg),
+
+    // Admin only, decommission the pool: no more deposits, queries keep working
+    ClosePool(ClosePoolMsg),
+
+    // Admin only, temporarily stop deposits and/or withdrawals
+    SetPaused(SetPausedMsg),
+
+    // Admin only, add another (asset, amount) pool with its own tree and nullifiers
+    CreatePool(CreatePoolMsg),
+
+    // Admin only, withdrawals through a registered relayer must pay at least its fee
+    RegisterRelayer(RegisterRelayerMsg),
+
+    // Top up the relayer fee reserve with `fee_denom` coins
+    FundFees(FundFeesMsg),
+
+    // Deposit into a CW20 pool, sent by the token contract
+    Receive(Cw20ReceiveMsg),
 }
 
+// Messages embedded in `Cw20ReceiveMsg.msg`
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+    Deposit(DepositMsg),
+}
+
+// `returns` names each query's response, for the schema generated by `cargo schema`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, QueryResponses)]
+#[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
+    #[returns(bool)]
     IsKnownRoot(IsKnownRootMsg),
+    #[returns(ProofSizeResponse)]
+    ProofSize(CircomProof),
+    // Current root of a pool as 32 little-endian bytes
+    #[returns(Binary)]
+    GetLastRootBytes { pool_id: Option<u32> },
+    // Whether a deposit would be accepted right now
+    #[returns(CanDepositResponse)]
+    CanDeposit { pool_id: Option<u32> },
+    // Paginated leaves and current root, to bootstrap light clients
+    #[returns(ExportResponse)]
+    ExportState {
+        start_after: Option<u32>,
+        limit: Option<u32>,
+        pool_id: Option<u32>,
+    },
+    // Leaf count and capacity of the commitments tree
+    #[returns(TreeInfoResponse)]
+    TreeInfo { pool_id: Option<u32> },
+    // Whether a note has already been withdrawn
+    #[returns(bool)]
+    IsSpent(IsSpentMsg),
+    #[returns(Vec<bool>)]
+    IsSpentBatch(IsSpentBatchMsg),
+    // Number of withdrawals so far from a pool, the instantiate pool when unset
+    // like every other pool query
+    #[returns(u64)]
+    WithdrawalCount { pool_id: Option<u32> },
+    // Number of withdrawals so far across all pools
+    #[returns(u64)]
+    TotalWithdrawalCount {},
+    // Whether each root is known, in the order given
+    #[returns(Vec<bool>)]
+    AreKnownRoots {
+        roots: Vec<String>,
+        pool_id: Option<u32>,
+    },
+    // Roots a withdrawal can currently be proven against, oldest first
+    #[returns(KnownRootsResponse)]
+    KnownRoots { pool_id: Option<u32> },
+    #[returns(PausedResponse)]
+    Paused {},
+    // Asset, amount and tree state of a pool
+    #[returns(PoolResponse)]
+    Pool(PoolMsg),
+    // Authentication path of a deposited leaf against the current root
+    #[returns(MerklePathResponse)]
+    MerklePath {
+        leaf_index: u32,
+        pool_id: Option<u32>,
+    },
+    // Leaf index of a deposited commitment, null if it isn't in the pool
+    #[returns(Option<u32>)]
+    CommitmentIndex {
+        commitment: String,
+        pool_id: Option<u32>,
+    },
+    // When a leaf was inserted. Deposits are public anyway, nothing ties them to a withdrawal
+    #[returns(DepositTimeResponse)]
+    DepositTime {
+        leaf_index: u32,
+        pool_id: Option<u32>,
+    },
+    // Runs every withdrawal check without spending the note, for relayers
+    #[returns(VerifyWithdrawResponse)]
+    VerifyWithdraw(WithdrawMsg),
+    // snarkjs `verification_key.json` the proofs are checked against
+    #[returns(Binary)]
+    VerificationKey {},
+    // Denomination and depth of a pool, with the contract-wide settings
+    #[returns(ConfigResponse)]
+    Config { pool_id: Option<u32> },
+    // Internals of a pool's tree, to diagnose root mismatches with a prover
+    #[cfg(feature = "debug")]
+    #[returns(DebugStateResponse)]
+    DebugState { pool_id: Option<u32> },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct IsKnownRootMsg {
     pub root: String,
+    pub pool_id: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct IsSpentMsg {
+    pub nullifier_hash: String,
+    pub pool_id: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PoolMsg {
+    pub pool_id: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct IsSpentBatchMsg {
+    pub nullifier_hashes: Vec<String>,
+    pub pool_id: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    // native denom, or token address for CW20 pools
+    pub denom: String,
+    pub amount: U128,
+    pub levels: u32,
+    pub contract_version: String,
+    // relayer fees are paid in the pool's asset when unset
+    pub fee_denom: Option<String>,
+    pub fee_reserve: U128,
+    pub max_fee: Option<U128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CanDepositResponse {
+    pub allowed: bool,
+    // "closed", "paused", "tree_full" or "cap_reached" when deposits are refused
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct VerifyWithdrawResponse {
+    pub valid: bool,
+    // error the withdrawal would fail with
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ExportResponse {
+    // (leaf_index, commitment)
+    pub leaves: Vec<(u32, String)>,
+    pub current_root: String,
+    pub next_index: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PausedResponse {
+    pub deposits: bool,
+    pub withdrawals: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PoolResponse {
+    pub asset: AssetInfo,
+    pub amount: U128,
+    pub next_index: u32,
+    pub last_root: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MerklePathResponse {
+    pub path_elements: Vec<String>,
+    // 0 when the node at that level is a left child, 1 otherwise
+    pub path_indices: Vec<u8>,
+    pub root: String,
+}
+
+// Height and time are missing for leaves inserted before they were tracked
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DepositTimeResponse {
+    pub commitment: String,
+    pub block_height: Option<u64>,
+    // seconds since the unix epoch, like the `timestamp` event attribute
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct KnownRootsResponse {
+    pub roots: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TreeInfoResponse {
+    pub levels: u32,
+    // number of leaves inserted so far
+    pub next_index: u32,
+    // `used` out of `capacity` leaves is how full the pool is, e.g. to point
+    // users to a fresher pool. next_index and 2^levels as u64.
+    pub used: u64,
+    pub capacity: u64,
+    pub last_root: String,
+}
+
+#[cfg(feature = "debug")]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DebugStateResponse {
+    pub filled_subtrees: Vec<String>,
+    pub zeros: Vec<String>,
+    pub next_index: u32,
+    pub current_root_index: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ProofSizeResponse {
+    // length of the JSON serialized proof, in bytes
+    pub size: u32,
+    // length of the same proof as the 8 coordinates of `ProofInput::Flat`, in bytes
+    pub flat_size: u32,
+    // number of curve points decoded from the proof
+    pub points: u32,
 }
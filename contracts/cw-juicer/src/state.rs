@@ -1,13 +1,121 @@
-use cosmwasm_std::Coin;
+use cosmwasm_std::{Addr, Coin, Uint128};
 use cw_storage_plus::{Item, Map};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use juicer::merkle_tree::MerkleTreeWithHistory;
 use juicer::verifier::Verifier;
 
+use crate::msg::AssetInfo;
+
+// Default pool, created at instantiate. For CW20 pools `denom` holds the token address
 pub const BASE_COIN: Item<Coin> = Item::new("base_coin");
+pub const ASSET: Item<AssetInfo> = Item::new("ASSET");
+
+pub const ADMIN: Item<Option<Addr>> = Item::new("ADMIN");
+
+pub const EMIT_EVENTS: Item<bool> = Item::new("EMIT_EVENTS");
+
+// Missing means withdrawals with relayer == recipient are paid in one message
+pub const REJECT_SELF_RELAY: Item<bool> = Item::new("REJECT_SELF_RELAY");
+
+// Charged per note on top of the pool amount, missing means free deposits
+pub const DEPOSIT_FEE: Item<Uint128> = Item::new("DEPOSIT_FEE");
+// Set along a non-zero DEPOSIT_FEE
+pub const FEE_COLLECTOR: Item<Addr> = Item::new("FEE_COLLECTOR");
+
+// Denom relayer fees are paid in, missing means the pool's asset
+pub const FEE_DENOM: Item<String> = Item::new("FEE_DENOM");
+// FEE_DENOM balance set aside for relayer fees, only grown by FundFees
+pub const FEE_RESERVE: Item<Uint128> = Item::new("FEE_RESERVE");
+// Cap on a single withdrawal's relayer fee
+pub const MAX_FEE: Item<Uint128> = Item::new("MAX_FEE");
+
+pub const MAX_BATCH_SIZE: Item<u32> = Item::new("MAX_BATCH_SIZE");
+
+// Cap on the leaves of every pool, missing means the tree capacity
+pub const MAX_DEPOSITS: Item<u32> = Item::new("MAX_DEPOSITS");
+
+pub const BECH32_PREFIX: Item<String> = Item::new("BECH32_PREFIX");
+
+// Set by ClosePool, missing means open
+pub const DEPOSITS_CLOSED: Item<bool> = Item::new("DEPOSITS_CLOSED");
+pub const WITHDRAWALS_CLOSED: Item<bool> = Item::new("WITHDRAWALS_CLOSED");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct PauseState {
+    pub deposits: bool,
+    pub withdrawals: bool,
+}
+
+// Set by SetPaused, missing means nothing is paused
+pub const PAUSED: Item<PauseState> = Item::new("PAUSED");
 
 pub const VERIFIER: Item<Verifier> = Item::new("VERIFIER");
 
+// Verifier of the partial withdraw circuit, missing if partial withdrawals aren't supported
+pub const PARTIAL_VERIFIER: Item<Verifier> = Item::new("PARTIAL_VERIFIER");
+
 pub const COMMITMENTS: Item<MerkleTreeWithHistory> = Item::new("COMMITMENTS");
 
+// leaf_index -> commitment
+pub const LEAVES: Map<u32, String> = Map::new("LEAVES");
+
+// leaf_index -> block height of the deposit, missing for leaves inserted before it was tracked
+pub const LEAF_HEIGHTS: Map<u32, u64> = Map::new("LEAF_HEIGHTS");
+// leaf_index -> block time of the deposit in seconds, missing for leaves inserted before it was tracked
+pub const LEAF_TIMES: Map<u32, u64> = Map::new("LEAF_TIMES");
+
+// Blocks a root must age before withdrawals can be proven against it, missing means 0
+pub const MIN_DELAY_BLOCKS: Item<u64> = Item::new("MIN_DELAY_BLOCKS");
+
+// Blocks a root must be confirmed by before withdrawals, missing means 0
+pub const MIN_CONFIRMATIONS: Item<u64> = Item::new("MIN_CONFIRMATIONS");
+
 pub const NULLIFIER_HASHES: Map<String, bool> = Map::new("NULLIFIER_HASHES");
+
+// Withdrawals of every pool, partial ones included, missing means 0. Only ever
+// incremented, imported nullifiers aren't counted.
+pub const WITHDRAWAL_COUNT: Item<u64> = Item::new("WITHDRAWAL_COUNT");
+// pool_id -> withdrawals of that pool since it was added, pool 0 is the default pool
+pub const POOL_WITHDRAWAL_COUNTS: Map<u32, u64> = Map::new("POOL_WITHDRAWAL_COUNTS");
+
+// (pool_id, root) -> number of leaves the tree held when the root was current,
+// for O(1) root lookups. Pool 0 is the default pool. Entries outlive the root
+// history: a root recorded with less leaves than the tree's oldest known root
+// has expired, one without an entry was never a root.
+pub const KNOWN_ROOTS: Map<(u32, String), u32> = Map::new("KNOWN_ROOTS");
+
+// (pool_id, commitment) -> leaf index of every deposit, pool 0 being the
+// instantiate pool. Leaves inserted before it was tracked aren't in it.
+pub const DEPOSITED_COMMITMENTS: Map<(u32, String), u32> = Map::new("DEPOSITED_COMMITMENTS");
+
+// registered relayer address -> minimum fee it accepts
+pub const RELAYERS: Map<String, Uint128> = Map::new("RELAYERS");
+
+// withdraw commitment hash -> block height of the commit
+pub const WITHDRAW_COMMITS: Map<String, u64> = Map::new("WITHDRAW_COMMITS");
+// (block height, withdraw commitment hash) of WITHDRAW_COMMITS, oldest first for pruning
+pub const WITHDRAW_COMMIT_HEIGHTS: Map<(u64, String), bool> = Map::new("WITHDRAW_COMMIT_HEIGHTS");
+// Blocks a withdraw commit stays revealable, missing means the default
+pub const WITHDRAW_COMMIT_EXPIRY: Item<u64> = Item::new("WITHDRAW_COMMIT_EXPIRY");
+// Plain withdrawals are refused, missing means false
+pub const REQUIRE_COMMIT_REVEAL: Item<bool> = Item::new("REQUIRE_COMMIT_REVEAL");
+
+// Pools added with CreatePool, the default pool keeps living in the items above
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Pool {
+    pub asset: AssetInfo,
+    pub amount: Uint128,
+    pub commitments: MerkleTreeWithHistory,
+}
+
+pub const POOLS: Map<u32, Pool> = Map::new("POOLS");
+pub const NEXT_POOL_ID: Item<u32> = Item::new("NEXT_POOL_ID");
+
+// (pool_id, leaf_index) -> commitment
+pub const POOL_LEAVES: Map<(u32, u32), String> = Map::new("POOL_LEAVES");
+pub const POOL_LEAF_HEIGHTS: Map<(u32, u32), u64> = Map::new("POOL_LEAF_HEIGHTS");
+pub const POOL_LEAF_TIMES: Map<(u32, u32), u64> = Map::new("POOL_LEAF_TIMES");
+
+pub const POOL_NULLIFIER_HASHES: Map<(u32, String), bool> = Map::new("POOL_NULLIFIER_HASHES");
@@ -0,0 +1,246 @@
+use alloc::format;
+use alloc::string::String;
+use core::convert::{TryFrom, TryInto};
+use core::str::FromStr;
+
+use cosmwasm_std::Uint256 as U256;
+
+/// The number parsing error types.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BignumError {
+    /// Returned if a `0x` number isn't exactly 32 hex encoded bytes
+    InvalidHex,
+    /// Returned if a decimal number has non digit characters or overflows 256 bits
+    InvalidDecimal,
+    /// Returned if a number isn't below the BN254 scalar field modulus
+    OutOfField,
+}
+
+impl core::fmt::Display for BignumError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BignumError::InvalidHex => {
+                write!(f, "Hex numbers must be 0x followed by 64 hex digits")
+            }
+            BignumError::InvalidDecimal => write!(f, "Invalid decimal number"),
+            BignumError::OutOfField => write!(f, "Number is outside the BN254 scalar field"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BignumError {}
+
+// BN254 scalar field modulus, every circuit input is reduced by it
+// 21888242871839275222246405745257275088548364400416034343698204186575808495617
+pub const FIELD_MODULUS: U256 = U256::from_be_bytes([
+    0x30, 0x64, 0x4e, 0x72, 0xe1, 0x31, 0xa0, 0x29, 0xb8, 0x50, 0x45, 0xb6, 0x81, 0x81, 0x58, 0x5d,
+    0x28, 0x33, 0xe8, 0x48, 0x79, 0xb9, 0x70, 0x91, 0x43, 0xe1, 0xf5, 0x93, 0xf0, 0x00, 0x00, 0x01,
+]);
+
+// Whether the circuit sees `x` as is, larger values alias a smaller one
+pub fn is_in_field(x: &U256) -> bool {
+    x < &FIELD_MODULUS
+}
+
+// The field element the circuit sees for `x`
+pub fn reduce(x: U256) -> U256 {
+    x % FIELD_MODULUS
+}
+
+// A BN254 scalar field element as the circuits see it. Its bytes are always
+// little-endian, the order of circom witnesses and of the poseidon hasher, a
+// big-endian slip silently hashes another value. Only hex strings are big-endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldElement(U256);
+
+impl FieldElement {
+    pub fn to_le_bytes(self) -> [u8; 32] {
+        self.0.to_le_bytes()
+    }
+
+    pub fn to_u256(self) -> U256 {
+        self.0
+    }
+}
+
+// Values outside the field are reduced, as the circuit would
+impl From<U256> for FieldElement {
+    fn from(x: U256) -> Self {
+        FieldElement(reduce(x))
+    }
+}
+
+// 32 little-endian bytes, reduced like `From<U256>`
+impl From<[u8; 32]> for FieldElement {
+    fn from(bytes: [u8; 32]) -> Self {
+        FieldElement::from(U256::from_le_bytes(bytes))
+    }
+}
+
+impl From<FieldElement> for U256 {
+    fn from(x: FieldElement) -> Self {
+        x.0
+    }
+}
+
+// Decimal or `u256_to_hex` strings, rejected rather than reduced outside the field
+impl TryFrom<&str> for FieldElement {
+    type Error = BignumError;
+
+    fn try_from(s: &str) -> Result<Self, BignumError> {
+        let x = parse_u256(s)?;
+        if !is_in_field(&x) {
+            return Err(BignumError::OutOfField);
+        }
+        Ok(FieldElement(x))
+    }
+}
+
+impl core::fmt::Display for FieldElement {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// 0x prefixed, 32 bytes big-endian like the circom side
+pub fn u256_to_hex(n: &U256) -> String {
+    format!("0x{}", hex::encode(n.to_be_bytes()))
+}
+
+pub fn u256_from_hex(s: &str) -> Result<U256, BignumError> {
+    let digits = s.strip_prefix("0x").ok_or(BignumError::InvalidHex)?;
+    if digits.len() != 64 {
+        return Err(BignumError::InvalidHex);
+    }
+
+    let bytes: [u8; 32] = hex::decode(digits)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(BignumError::InvalidHex)?;
+
+    Ok(U256::from_be_bytes(bytes))
+}
+
+// Either a `u256_to_hex` string or a plain decimal one, nothing else
+pub fn parse_u256(s: &str) -> Result<U256, BignumError> {
+    if s.starts_with("0x") {
+        return u256_from_hex(s);
+    }
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(BignumError::InvalidDecimal);
+    }
+
+    U256::from_str(s).map_err(|_| BignumError::InvalidDecimal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::bignum;
+
+    #[test]
+    fn test_u256_hex() {
+        let root = bignum!(
+            "19476726467694243150694636071195943429153087843379888650723427850220480216251"
+        );
+        let hex = u256_to_hex(&root);
+        assert_eq!(hex.len(), 66);
+        assert_eq!(u256_from_hex(&hex), Ok(root));
+        assert_eq!(parse_u256(&hex), Ok(root));
+        assert_eq!(parse_u256(&root.to_string()), Ok(root));
+
+        assert_eq!(u256_to_hex(&U256::from(1_u8)), format!("0x{:0>64}", "1"));
+    }
+
+    #[test]
+    fn test_field_modulus() {
+        use ark_bn254::Fr;
+        use ark_ff::{BigInteger, FpParameters, PrimeField};
+
+        let modulus = <<Fr as PrimeField>::Params as FpParameters>::MODULUS;
+        assert_eq!(
+            FIELD_MODULUS,
+            U256::from_le_bytes(modulus.to_bytes_le().try_into().unwrap())
+        );
+
+        let one = U256::from(1_u8);
+        let max = FIELD_MODULUS - one;
+        assert!(is_in_field(&U256::zero()));
+        assert!(is_in_field(&max));
+        assert!(!is_in_field(&FIELD_MODULUS));
+        assert!(!is_in_field(&U256::MAX));
+
+        assert_eq!(reduce(max), max);
+        assert_eq!(reduce(FIELD_MODULUS), U256::zero());
+        assert_eq!(reduce(FIELD_MODULUS + one), one);
+        assert!(is_in_field(&reduce(U256::MAX)));
+    }
+
+    #[test]
+    fn test_field_element() {
+        use crate::poseidon::POSEIDON;
+
+        let one = FieldElement::try_from("1").unwrap();
+        let two = FieldElement::try_from(format!("0x{:0>64}", "2").as_str()).unwrap();
+        assert_eq!(two, FieldElement::from(U256::from(2_u8)));
+        assert_eq!(one.to_le_bytes()[0], 1);
+        assert_eq!(one.to_le_bytes()[31], 0);
+
+        // circomlibjs `poseidon([1, 2])`, which only matches little-endian inputs
+        // and output
+        let hash = POSEIDON
+            .hash(vec![one.to_le_bytes(), two.to_le_bytes()])
+            .unwrap();
+        assert_eq!(
+            FieldElement::from(hash).to_string(),
+            "7853200120776062878684798364095072458815029376092732009249414926327459813530"
+        );
+        let mut be = hash;
+        be.reverse();
+        assert_ne!(FieldElement::from(be), FieldElement::from(hash));
+
+        assert_eq!(
+            FieldElement::try_from(FIELD_MODULUS.to_string().as_str()),
+            Err(BignumError::OutOfField)
+        );
+        assert_eq!(
+            FieldElement::try_from("x"),
+            Err(BignumError::InvalidDecimal)
+        );
+        assert_eq!(
+            U256::from(FieldElement::from(FIELD_MODULUS + U256::from(1_u8))),
+            U256::from(1_u8)
+        );
+    }
+
+    #[test]
+    fn test_parse_u256_rejects() {
+        let one = format!("0x{:0>64}", "1");
+
+        // missing prefix, short, overlong, upper case prefix, not hex
+        assert_eq!(u256_from_hex(&one[2..]), Err(BignumError::InvalidHex));
+        assert_eq!(parse_u256("0x1"), Err(BignumError::InvalidHex));
+        assert_eq!(
+            parse_u256(&format!("{}0", one)),
+            Err(BignumError::InvalidHex)
+        );
+        assert_eq!(
+            parse_u256(&one.replace("0x", "0X")),
+            Err(BignumError::InvalidDecimal)
+        );
+        assert_eq!(
+            parse_u256(&one.replace('1', "g")),
+            Err(BignumError::InvalidHex)
+        );
+
+        assert_eq!(parse_u256(""), Err(BignumError::InvalidDecimal));
+        assert_eq!(parse_u256("-1"), Err(BignumError::InvalidDecimal));
+        assert_eq!(parse_u256("1e3"), Err(BignumError::InvalidDecimal));
+        assert_eq!(
+            parse_u256(&format!("{}0", U256::MAX)),
+            Err(BignumError::InvalidDecimal)
+        );
+    }
+}
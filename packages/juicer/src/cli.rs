@@ -0,0 +1,136 @@
+use cosmwasm_std::Uint128 as U128;
+use serde::Serialize;
+
+use crate::msg::{Deposit, NoteFormat, ProofInput};
+use crate::testing::WithdrawProof;
+
+// JSON of the contract messages operators otherwise write by hand. The shapes
+// mirror `juno_juicer::msg`, which this crate can't depend on. Unset options
+// are left out rather than sent as null.
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ExecuteMsg<'a> {
+    Deposit(DepositMsg<'a>),
+    Withdraw(WithdrawMsg<'a>),
+}
+
+#[derive(Serialize)]
+struct DepositMsg<'a> {
+    commitment: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pool_id: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct WithdrawMsg<'a> {
+    proof: ProofInput,
+    root: &'a str,
+    nullifier_hash: &'a str,
+    recipient: &'a str,
+    relayer: &'a str,
+    fee: U128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pool_id: Option<u32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum QueryMsg<'a> {
+    IsKnownRoot { root: &'a str },
+}
+
+pub fn deposit_msg(commitment: &str, pool_id: Option<u32>) -> String {
+    to_json(&ExecuteMsg::Deposit(DepositMsg {
+        commitment,
+        pool_id,
+    }))
+}
+
+// `proof` as built by `ProofBuilder`, e.g. with a prover running snarkjs
+pub fn withdraw_msg(proof: &WithdrawProof, pool_id: Option<u32>) -> String {
+    to_json(&ExecuteMsg::Withdraw(WithdrawMsg {
+        proof: ProofInput::from(proof.proof.clone()),
+        root: &proof.root,
+        nullifier_hash: &proof.nullifier_hash,
+        recipient: &proof.recipient,
+        relayer: &proof.relayer,
+        fee: proof.fee,
+        pool_id,
+    }))
+}
+
+pub fn is_known_root_query(root: &str) -> String {
+    to_json(&QueryMsg::IsKnownRoot { root })
+}
+
+// The note to back up and the commitment to deposit, one per line
+pub fn print_deposit(deposit: &Deposit, format: &NoteFormat) -> String {
+    format!(
+        "note: {}\ncommitment: {}\n",
+        deposit.to_note_with(format),
+        deposit.clone().get_commitment()
+    )
+}
+
+// Only fails on maps with non-string keys, which none of the messages have
+fn to_json<T: Serialize>(msg: &T) -> String {
+    serde_json::to_string(msg).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::msg::CircomProof;
+
+    #[test]
+    fn test_cli_json() {
+        assert_eq!(
+            deposit_msg("42", None),
+            r#"{"deposit":{"commitment":"42"}}"#
+        );
+        assert_eq!(
+            deposit_msg("42", Some(1)),
+            r#"{"deposit":{"commitment":"42","pool_id":1}}"#
+        );
+        assert_eq!(
+            is_known_root_query("42"),
+            r#"{"is_known_root":{"root":"42"}}"#
+        );
+
+        let proof = WithdrawProof {
+            proof: CircomProof::from_flat(&vec!["1".to_string(); 8]).unwrap(),
+            root: "1".to_string(),
+            nullifier_hash: "2".to_string(),
+            recipient: "recipient".to_string(),
+            relayer: "0".to_string(),
+            fee: U128::zero(),
+        };
+        let json = withdraw_msg(&proof, None);
+        assert!(json.starts_with(r#"{"withdraw":{"proof":{"circom":{"pi_a":["1","1","1"]"#));
+        assert!(json.ends_with(
+            r#""root":"1","nullifier_hash":"2","recipient":"recipient","relayer":"0","fee":"0"}}"#
+        ));
+    }
+
+    #[test]
+    fn test_print_deposit() {
+        let deposit = Deposit::new("42".to_string());
+        let format = NoteFormat::default();
+
+        let printed = print_deposit(&deposit, &format);
+        let lines: Vec<&str> = printed.lines().collect();
+        assert_eq!(lines[0], format!("note: {}", deposit.to_note_with(&format)));
+        assert_eq!(
+            lines[1],
+            format!("commitment: {}", deposit.clone().get_commitment())
+        );
+        assert_eq!(
+            Deposit::from_note(lines[0].strip_prefix("note: ").unwrap())
+                .unwrap()
+                .nullifier,
+            deposit.nullifier
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use alloc::vec::Vec;
+use cosmwasm_std::Uint256 as U256;
+
+use crate::bignum::FieldElement;
+use crate::poseidon::POSEIDON;
+
+// Preimages follow circuits/withdraw.circom:
+//   commitment     = poseidon(nullifier, secret), the circuit fixes `secret` to 0
+//   nullifier_hash = poseidon(nullifier, 1, leaf_index)
+//   change         = poseidon(commitment, value), the leaf of a partial withdrawal's
+//                    change note. Its value sits where the withdraw circuit fixes
+//                    the secret to 0, so only the partial circuit opens it.
+//
+// `leaf_index` is a private input: the circuit derives it from the merkle path
+// bits (`MerklePath::leaf_index`) that place the commitment under the public root.
+// A nullifier hash for any other index, in range or not, has no valid proof, so
+// the contract only ever sees it through a failing proof verification.
+//
+// With the `domain-separation` feature (circuit v2) each preimage is prefixed by
+// its own domain tag, see `Poseidon::hash_with_domain`. Domain 0 is the v1 hash.
+
+#[cfg(not(feature = "domain-separation"))]
+pub const COMMITMENT_DOMAIN: u64 = 0;
+#[cfg(not(feature = "domain-separation"))]
+pub const NULLIFIER_HASH_DOMAIN: u64 = 0;
+#[cfg(not(feature = "domain-separation"))]
+pub const CHANGE_DOMAIN: u64 = 0;
+
+#[cfg(feature = "domain-separation")]
+pub const COMMITMENT_DOMAIN: u64 = 1;
+#[cfg(feature = "domain-separation")]
+pub const NULLIFIER_HASH_DOMAIN: u64 = 2;
+#[cfg(feature = "domain-separation")]
+pub const CHANGE_DOMAIN: u64 = 3;
+
+pub fn commitment(nullifier: &U256, secret: &U256) -> U256 {
+    poseidon(COMMITMENT_DOMAIN, &[(*nullifier).into(), (*secret).into()])
+}
+
+pub fn nullifier_hash(nullifier: &U256, leaf_index: u128) -> U256 {
+    poseidon(
+        NULLIFIER_HASH_DOMAIN,
+        &[
+            (*nullifier).into(),
+            U256::from(1_u16).into(),
+            U256::from(leaf_index).into(),
+        ],
+    )
+}
+
+// A zero value would be the deposit commitment of `commitment` as a nullifier,
+// which the withdraw circuit pays out in full
+pub fn change_commitment(commitment: &U256, value: u128) -> Option<U256> {
+    match value {
+        0 => None,
+        value => Some(poseidon(
+            CHANGE_DOMAIN,
+            &[(*commitment).into(), U256::from(value).into()],
+        )),
+    }
+}
+
+fn poseidon(domain: u64, inputs: &[FieldElement]) -> U256 {
+    let inputs: Vec<U256> = inputs.iter().map(|&x| x.into()).collect();
+
+    FieldElement::from(POSEIDON.hash_with_domain(domain, &inputs).unwrap()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(feature = "domain-separation"))]
+    use crate::bignum;
+
+    // v1 vectors, the domain-separated circuit hashes to other values
+    #[cfg(not(feature = "domain-separation"))]
+    #[test]
+    fn test_commitment() {
+        // notes of `test_generate_deposit` and of the contract withdraw fixture,
+        // whose proof was generated by the circuit
+        let nullifier =
+            bignum!("276277773929387392791096474084808108569850403587654342680891529007770412737");
+        assert_eq!(
+            commitment(&nullifier, &U256::zero()),
+            bignum!("6236796047772841813667132166633849358445729975292785870973181152954966652594")
+        );
+
+        let nullifier =
+            bignum!("54154714943715201094961901040590459639892306160131965986154511512546000403");
+        assert_eq!(
+            commitment(&nullifier, &U256::zero()),
+            bignum!("4344666601552206537564374315934112610851580244546230263574239921760163429811")
+        );
+        assert_ne!(
+            commitment(&nullifier, &U256::from(1_u16)),
+            commitment(&nullifier, &U256::zero())
+        );
+    }
+
+    #[cfg(not(feature = "domain-separation"))]
+    #[test]
+    fn test_nullifier_hash() {
+        let nullifier =
+            bignum!("276277773929387392791096474084808108569850403587654342680891529007770412737");
+        assert_eq!(
+            nullifier_hash(&nullifier, 0),
+            bignum!(
+                "10174783302134252183272028399003089320089964203118066360883858790559353379370"
+            )
+        );
+
+        let nullifier =
+            bignum!("54154714943715201094961901040590459639892306160131965986154511512546000403");
+        assert_eq!(
+            nullifier_hash(&nullifier, 0),
+            bignum!("8887875860913872932647323972257566744860838826363057182304624773759853955810")
+        );
+        assert_ne!(nullifier_hash(&nullifier, 1), nullifier_hash(&nullifier, 0));
+    }
+
+    #[test]
+    fn test_change_commitment() {
+        let inner = commitment(&U256::from(42_u16), &U256::zero());
+        assert_eq!(change_commitment(&inner, 0), None);
+
+        let change = change_commitment(&inner, 6).unwrap();
+        assert_ne!(change, commitment(&inner, &U256::zero()));
+        assert_ne!(Some(change), change_commitment(&inner, 4));
+        assert_eq!(
+            change,
+            POSEIDON
+                .hash_with_domain(CHANGE_DOMAIN, &[inner, U256::from(6_u16)])
+                .unwrap()
+        );
+    }
+
+    #[cfg(feature = "domain-separation")]
+    #[test]
+    fn test_domain_separation() {
+        let nullifier = U256::from(42_u16);
+        let v1 = POSEIDON.hash_n(&[nullifier, U256::zero()]).unwrap();
+        assert_ne!(commitment(&nullifier, &U256::zero()), v1);
+        assert_eq!(
+            commitment(&nullifier, &U256::zero()),
+            POSEIDON
+                .hash_n(&[U256::from(1_u16), nullifier, U256::zero()])
+                .unwrap()
+        );
+        assert_eq!(
+            nullifier_hash(&nullifier, 0),
+            POSEIDON
+                .hash_n(&[
+                    U256::from(2_u16),
+                    nullifier,
+                    U256::from(1_u16),
+                    U256::zero()
+                ])
+                .unwrap()
+        );
+    }
+}
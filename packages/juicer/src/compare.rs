@@ -0,0 +1,43 @@
+use cosmwasm_std::Uint256 as U256;
+
+// Equality for sensitive values (roots, nullifier hashes).
+// On-chain timing isn't observable, but relayer servers reusing this code could
+// leak through an early-exit comparison, so the `constant-time` feature compares
+// every byte regardless of where the first difference is.
+#[cfg(feature = "constant-time")]
+pub fn ct_eq(a: &U256, b: &U256) -> bool {
+    use subtle::ConstantTimeEq;
+
+    a.to_le_bytes().ct_eq(&b.to_le_bytes()).into()
+}
+
+#[cfg(not(feature = "constant-time"))]
+pub fn ct_eq(a: &U256, b: &U256) -> bool {
+    a == b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bignum;
+
+    #[test]
+    fn test_ct_eq() {
+        let a = bignum!(
+            "19476726467694243150694636071195943429153087843379888650723427850220480216251"
+        );
+        let b = bignum!(
+            "13801027358871474054350913888493740197706640469969388660938924863508695867545"
+        );
+
+        for (x, y) in [
+            (a, a),
+            (a, b),
+            (b, a),
+            (U256::zero(), U256::zero()),
+            (U256::zero(), a),
+        ] {
+            assert_eq!(x == y, ct_eq(&x, &y));
+        }
+    }
+}
@@ -1,9 +1,27 @@
+extern crate alloc;
+
+pub mod bignum;
+pub mod commitment;
+pub mod compare;
 pub mod merkle_tree;
+#[cfg(feature = "std")]
 pub mod msg;
+#[cfg(feature = "std")]
 pub mod verifier;
 
 pub mod poseidon;
 
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(all(test, feature = "std"))]
+mod test_fixtures;
+
+#[cfg(feature = "cli")]
+pub mod cli;
+
+pub use commitment::{commitment, nullifier_hash};
+
 // #[macro_use]
 // extern crate lazy_static;
 
@@ -27,8 +45,8 @@ pub mod poseidon;
 #[macro_export]
 macro_rules! bignum {
     ($c0: expr) => {{
+        use core::str::FromStr;
         use cosmwasm_std::Uint256 as U256;
-        use std::str::FromStr;
         U256::from_str($c0).unwrap()
     }};
 }
@@ -1,75 +1,337 @@
 #![allow(non_snake_case)]
 
 use crate::bignum;
-use crate::poseidon::Poseidon;
+use crate::bignum::{FieldElement, FIELD_MODULUS};
+use crate::compare::ct_eq;
+use crate::poseidon::{Poseidon, PoseidonParams, POSEIDON};
+use alloc::vec;
+use alloc::vec::Vec;
 use cosmwasm_std::Uint256 as U256;
 
+#[cfg(feature = "std")]
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-const ROOT_HISTORY_SIZE: u32 = 100;
+pub const ROOT_HISTORY_SIZE: u32 = 100;
+
+fn default_root_history_size() -> u32 {
+    ROOT_HISTORY_SIZE
+}
+
+// keccak256("tornado") % FIELD_SIZE, the zero leaf of tornado.cash trees
+pub fn default_zero_value() -> U256 {
+    bignum!("21663839004416932945382355908790599225266501822907911457504978515578255421292")
+}
+
+// BN254 scalar field modulus, leaves and zero values must stay below it
+pub fn field_modulus() -> U256 {
+    FIELD_MODULUS
+}
+
+fn hash_pair(left: &U256, right: &U256) -> Result<U256, MerkleTreeError> {
+    hash_pair_with(&POSEIDON, left, right)
+}
+
+fn hash_pair_with(hasher: &Poseidon, left: &U256, right: &U256) -> Result<U256, MerkleTreeError> {
+    // hashed from the stack, without an intermediate Vec per level
+    let mut packed = [0_u8; 64];
+    packed[..32].copy_from_slice(&FieldElement::from(*left).to_le_bytes());
+    packed[32..].copy_from_slice(&FieldElement::from(*right).to_le_bytes());
+
+    let hash = hasher
+        .hash_packed(&packed, 2)
+        .map_err(|_| MerkleTreeError::HashFailed)?;
+    Ok(FieldElement::from(hash).into())
+}
+
+/// The merkle tree error types.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MerkleTreeError {
+    /// Returned if there is no room left for a new leaf
+    TreeFull,
+    /// Returned if the tree depth isn't in 1..=31
+    InvalidLevels { levels: u32 },
+    /// Returned if the root history can't hold a single root
+    InvalidRootHistorySize,
+    /// Returned if the zero value isn't a BN254 scalar field element
+    InvalidZeroValue,
+    /// Returned if an imported snapshot doesn't recompute to its current root
+    InvalidSnapshot,
+    /// Returned if the pair hash rounds aren't a valid 2 input Poseidon
+    InvalidPoseidonParams,
+    /// Returned if the tree wasn't built `with_leaves`
+    LeavesNotKept,
+    /// Returned if Poseidon fails to hash a pair of nodes
+    HashFailed,
+}
+
+// Merkle authentication path of a leaf, as consumed by the withdraw circuit
+// path_indices[i] is 0 when the node at level i is a left child, 1 otherwise
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "std", derive(JsonSchema))]
+pub struct MerklePath {
+    pub path_elements: Vec<U256>,
+    pub path_indices: Vec<u8>,
+}
+
+impl MerklePath {
+    // Position of the leaf, `Bits2Num(pathIndices)` in the circuit. The circuit
+    // hashes this same value into the nullifier hash, so a note can only be
+    // spent under the index where its commitment sits in the proven root.
+    pub fn leaf_index(&self) -> u32 {
+        self.path_indices
+            .iter()
+            .rev()
+            .fold(0, |index, bit| (index << 1) | *bit as u32)
+    }
+}
+
+// Full state of a tree, to move it to another chain or contract
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "std", derive(JsonSchema))]
+pub struct TreeSnapshot {
+    pub levels: u32,
+    pub root_history_size: u32,
+    pub zero_value: U256,
+    pub filled_subtrees: Vec<U256>,
+    pub zeros: Vec<U256>,
+    pub roots: Vec<U256>,
+    // Missing in snapshots of trees that didn't keep them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub root_sizes: Vec<u32>,
+    pub current_root_index: u32,
+    pub next_index: u32,
+    pub leaves: Option<Vec<U256>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poseidon_params: Option<PoseidonParams>,
+}
 
 #[derive(Default, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct MerkleTreeWithHistory {
     pub levels: u32,
     pub filled_subtrees: Vec<U256>,
     pub zeros: Vec<U256>,
+    // Slot of the last root in `roots`. Slot 0 starts with the genesis (empty tree)
+    // root, which stays known until `root_history_size` inserts overwrite it.
+    // The index wraps back to 0, use `next_index` to tell whether anything was inserted.
     pub current_root_index: u32,
     pub next_index: u32,
     pub roots: Vec<U256>,
+    // Leaves the tree held when the root in the same slot of `roots` was current.
+    // Empty in trees stored before it was kept, read it through `root_size()`
+    #[serde(default)]
+    pub root_sizes: Vec<u32>,
+    // number of recent roots accepted by `is_known_root`
+    #[serde(default = "default_root_history_size")]
+    pub root_history_size: u32,
+    // 2^levels, set at construction. 0 in trees stored before it was kept,
+    // read it through `capacity()`
+    #[serde(default)]
+    pub capacity: u64,
 
     pub ZERO_VALUE: U256,
+
+    // Every inserted leaf, only kept when built `with_leaves`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leaves: Option<Vec<U256>>,
+
+    // Rounds of the pair hash, missing means circomlib's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poseidon_params: Option<PoseidonParams>,
 }
 
 impl MerkleTreeWithHistory {
     pub fn new(levels: u32) -> Self {
+        Self::try_new(levels, ROOT_HISTORY_SIZE, None).expect("_treeLevels should be within 1..=31")
+    }
+
+    // Keep every inserted leaf so paths can be generated with `get_path`.
+    // Must be called before the first insert.
+    pub fn with_leaves(mut self) -> Self {
+        self.leaves = Some(Vec::new());
+        self
+    }
+
+    // `zero_value` defaults to ZERO_VALUE, the leaf of empty slots
+    pub fn try_new(
+        levels: u32,
+        root_history_size: u32,
+        zero_value: Option<U256>,
+    ) -> Result<Self, MerkleTreeError> {
+        Self::try_new_with_params(
+            levels,
+            root_history_size,
+            zero_value,
+            PoseidonParams::default(),
+        )
+    }
+
+    // For circuits hashing pairs with other Poseidon rounds. Each hash then
+    // rebuilds its hasher, making inserts several times costlier.
+    pub fn try_new_with_params(
+        levels: u32,
+        root_history_size: u32,
+        zero_value: Option<U256>,
+        poseidon_params: PoseidonParams,
+    ) -> Result<Self, MerkleTreeError> {
         let mut this: Self = Default::default();
-        assert!(levels > 0, "_treeLevels should be greater than zero");
-        assert!(levels < 32, "_treeLevels should be less than 32");
+        if poseidon_params.width != 3 || Poseidon::with_params(&[poseidon_params]).is_err() {
+            return Err(MerkleTreeError::InvalidPoseidonParams);
+        }
+        if poseidon_params != PoseidonParams::default() {
+            this.poseidon_params = Some(poseidon_params);
+        }
+        if levels == 0 || levels >= 32 {
+            return Err(MerkleTreeError::InvalidLevels { levels });
+        }
+        if root_history_size == 0 {
+            return Err(MerkleTreeError::InvalidRootHistorySize);
+        }
 
-        let ZERO_VALUE = bignum!(
-            "21663839004416932945382355908790599225266501822907911457504978515578255421292"
-        );
+        let ZERO_VALUE = zero_value.unwrap_or_else(default_zero_value);
+        if ZERO_VALUE >= field_modulus() {
+            return Err(MerkleTreeError::InvalidZeroValue);
+        }
 
         this.levels = levels;
-        this.roots = vec![U256::zero(); ROOT_HISTORY_SIZE as usize];
+        this.capacity = 1 << levels;
+        this.root_history_size = root_history_size;
+        this.roots = vec![U256::zero(); root_history_size as usize];
+        this.root_sizes = vec![0; root_history_size as usize];
 
-        this.ZERO_VALUE = ZERO_VALUE.clone();
+        this.ZERO_VALUE = ZERO_VALUE;
 
-        let mut current_zero = ZERO_VALUE.clone();
-        this.zeros.push(current_zero.clone());
-        this.filled_subtrees.push(current_zero.clone());
+        let mut current_zero = ZERO_VALUE;
+        this.zeros.push(current_zero);
+        this.filled_subtrees.push(current_zero);
 
         for _ in 1..levels {
-            current_zero = this.hash_left_right(&current_zero, &current_zero);
-            this.zeros.push(current_zero.clone());
-            this.filled_subtrees.push(current_zero.clone());
+            current_zero = this.hash_left_right(&current_zero, &current_zero)?;
+            this.zeros.push(current_zero);
+            this.filled_subtrees.push(current_zero);
         }
 
-        this.roots[0] = this.hash_left_right(&current_zero, &current_zero);
-        this
+        this.roots[0] = this.hash_left_right(&current_zero, &current_zero)?;
+        Ok(this)
     }
 
-    pub fn hash_left_right(&self, left: &U256, right: &U256) -> U256 {
-        let poseidon = Poseidon::new();
-        // let mut left_bytes: [u8; 32] = [0; 32];
-        // let mut right_bytes: [u8; 32] = [0; 32];
+    pub fn hash_left_right(&self, left: &U256, right: &U256) -> Result<U256, MerkleTreeError> {
+        match &self.poseidon_params {
+            None => hash_pair(left, right),
+            Some(params) => {
+                // validated when the tree was built, but a stored tree can be edited
+                let hasher =
+                    Poseidon::with_params(&[*params]).map_err(|_| MerkleTreeError::HashFailed)?;
+                hash_pair_with(&hasher, left, right)
+            }
+        }
+    }
 
-        let left_bytes = left.to_le_bytes();
-        let right_bytes = right.to_le_bytes();
+    // Root of an empty `levels` deep tree, what `get_last_root` returns right
+    // after `try_new`. `zero_value` defaults to ZERO_VALUE.
+    pub fn empty_root(levels: u32, zero_value: Option<U256>) -> Result<U256, MerkleTreeError> {
+        let mut current_zero = zero_value.unwrap_or_else(default_zero_value);
+        for _ in 0..levels {
+            current_zero = hash_pair(&current_zero, &current_zero)?;
+        }
+        Ok(current_zero)
+    }
 
-        let inputs = vec![left_bytes, right_bytes];
+    // A HashFailed error leaves the tree half updated, it must then be dropped
+    pub fn insert(&mut self, leaf: &U256) -> Result<u32, MerkleTreeError> {
+        if self.is_full() {
+            //"Merkle tree is full. No more leafs can be added");
+            return Err(MerkleTreeError::TreeFull);
+        }
 
-        poseidon.hash_as_u256(inputs).unwrap()
+        self.keep_root_sizes();
+        let root = self.append(leaf)?;
+        self.push_root(root);
+        Ok(self.next_index - 1)
     }
 
-    pub fn insert(&mut self, leaf: &U256) -> Option<u32> {
-        let mut idx = self.next_index;
-        if idx == 2_u32.saturating_pow(self.levels) {
-            //"Merkle tree is full. No more leafs can be added");
-            return None;
+    // Inserts every leaf but only records the final root, intermediate roots are
+    // never known. Meant for seeding a tree, e.g. during a migration.
+    // Nothing is inserted if the leaves don't all fit.
+    pub fn insert_many(&mut self, leaves: &[U256]) -> Result<Vec<u32>, MerkleTreeError> {
+        if self.next_index as u64 + leaves.len() as u64 > self.capacity() {
+            return Err(MerkleTreeError::TreeFull);
+        }
+        if leaves.is_empty() {
+            return Ok(vec![]);
+        }
+
+        self.keep_root_sizes();
+        let first = self.next_index;
+        let mut root = U256::zero();
+        for leaf in leaves {
+            root = self.append(leaf)?;
+        }
+        self.push_root(root);
+
+        Ok((first..self.next_index).collect())
+    }
+
+    // Recomputes `filled_subtrees`, `next_index` and the current root from the
+    // kept leaves, e.g. after a faulty migration. The current root is replaced in
+    // its slot, older roots stay in the history as they were.
+    pub fn rebuild_from_leaves(&mut self) -> Result<(), MerkleTreeError> {
+        let leaves = self.leaves.as_ref().ok_or(MerkleTreeError::LeavesNotKept)?;
+        let mut rebuilt = Self::try_new_with_params(
+            self.levels,
+            self.root_history_size,
+            Some(self.ZERO_VALUE),
+            self.poseidon_params.unwrap_or_default(),
+        )?;
+        rebuilt.insert_many(leaves)?;
+
+        self.keep_root_sizes();
+        self.roots[self.current_root_index as usize] = rebuilt.get_last_root();
+        self.root_sizes[self.current_root_index as usize] = rebuilt.next_index;
+        self.zeros = rebuilt.zeros;
+        self.filled_subtrees = rebuilt.filled_subtrees;
+        self.next_index = rebuilt.next_index;
+        Ok(())
+    }
+
+    // `keep_root_sizes` must have been called before the leaves were appended
+    fn push_root(&mut self, root: U256) {
+        self.current_root_index = (self.current_root_index + 1) % self.root_history_size;
+        self.roots[self.current_root_index as usize] = root;
+        self.root_sizes[self.current_root_index as usize] = self.next_index;
+    }
+
+    // Fills `root_sizes` for trees stored before it was kept
+    fn keep_root_sizes(&mut self) {
+        if self.root_sizes.len() != self.roots.len() {
+            self.root_sizes = (0..self.root_history_size)
+                .map(|i| self.root_size(i))
+                .collect();
         }
+    }
 
+    // Number of leaves when the root in slot `i` was current. Trees stored before
+    // sizes were kept had one root per leaf: k slots back, the tree had k less leaves.
+    fn root_size(&self, i: u32) -> u32 {
+        match self.root_sizes.get(i as usize) {
+            Some(size) => *size,
+            None => {
+                let size = self.root_history_size;
+                let k = (self.current_root_index + size - i) % size;
+                self.next_index.saturating_sub(k)
+            }
+        }
+    }
+
+    // Adds a leaf to the filled subtrees and returns the new root, without
+    // touching the root history. The caller checks the tree isn't full.
+    // On error the tree is left half updated and must be dropped.
+    fn append(&mut self, leaf: &U256) -> Result<U256, MerkleTreeError> {
+        let mut idx = self.next_index;
         self.next_index += 1;
+        if let Some(leaves) = &mut self.leaves {
+            leaves.push(*leaf);
+        }
         let mut current_level_hash: U256 = *leaf;
         let mut left: &U256;
         let mut right: &U256;
@@ -79,49 +341,368 @@ impl MerkleTreeWithHistory {
                 left = &current_level_hash;
                 right = &self.zeros[i as usize];
 
-                self.filled_subtrees[i as usize] = current_level_hash.clone();
+                self.filled_subtrees[i as usize] = current_level_hash;
             } else {
                 left = &self.filled_subtrees[i as usize];
                 right = &current_level_hash;
             }
 
-            current_level_hash = self.hash_left_right(left, right);
+            current_level_hash = self.hash_left_right(left, right)?;
 
             idx /= 2;
         }
 
-        self.current_root_index = (self.current_root_index + 1) % ROOT_HISTORY_SIZE;
-        self.roots[self.current_root_index as usize] = current_level_hash;
-        Some(self.next_index as u32 - 1)
+        Ok(current_level_hash)
     }
 
     pub fn is_known_root(&self, root: &U256) -> bool {
         if root == &U256::zero() {
             return false;
         }
-        let mut i = self.current_root_index;
 
-        for _ in 0..ROOT_HISTORY_SIZE {
-            if *root == self.roots[i as usize] {
+        // walk every slot once, from the most recent root backwards
+        let size = self.root_history_size;
+        for k in 0..size {
+            let i = (self.current_root_index + size - k) % size;
+            if ct_eq(root, &self.roots[i as usize]) {
                 return true;
             }
-            if i == 0 {
-                i = ROOT_HISTORY_SIZE;
-            }
+        }
 
-            i -= 1;
+        false
+    }
 
-            if i == self.current_root_index {
-                break;
+    // Known roots, oldest first, with the number of leaves the tree held when
+    // each was current
+    pub fn root_history(&self) -> Vec<(U256, u32)> {
+        let size = self.root_history_size;
+        (1..=size)
+            .map(|k| (self.current_root_index + k) % size)
+            .filter(|i| self.roots[*i as usize] != U256::zero())
+            .map(|i| (self.roots[i as usize], self.root_size(i)))
+            .collect()
+    }
+
+    // Number of leaves when the oldest known root was current. Each root is
+    // recorded with more leaves than the one before, so a root recorded with
+    // less has left the history.
+    pub fn oldest_root_size(&self) -> u32 {
+        let size = self.root_history_size;
+        (1..=size)
+            .map(|k| (self.current_root_index + k) % size)
+            .find(|i| self.roots[*i as usize] != U256::zero())
+            .map_or(0, |i| self.root_size(i))
+    }
+
+    // Number of leaves the tree held when a known root was current, None for
+    // unknown roots
+    pub fn known_root_size(&self, root: &U256) -> Option<u32> {
+        if root == &U256::zero() {
+            return None;
+        }
+
+        let size = self.root_history_size;
+        for k in 0..size {
+            let i = (self.current_root_index + size - k) % size;
+            if ct_eq(root, &self.roots[i as usize]) {
+                return Some(self.root_size(i));
             }
         }
 
-        false
+        None
     }
 
     pub fn get_last_root(&self) -> U256 {
         self.roots[self.current_root_index as usize].clone()
     }
+
+    // Root of the tree before any insert
+    pub fn genesis_root(&self) -> Result<U256, MerkleTreeError> {
+        let top = &self.zeros[self.levels as usize - 1];
+        self.hash_left_right(top, top)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.next_index == 0
+    }
+
+    // (leaves inserted, leaves the tree holds), a ratio rather than an f64 as
+    // contracts can't use floats. In u64, 2^31 leaves at level 31 is close to u32::MAX.
+    pub fn fill_ratio(&self) -> (u64, u64) {
+        (self.next_index as u64, self.capacity())
+    }
+
+    // Number of leaves the tree holds, 2^31 at most so it never overflows
+    pub fn capacity(&self) -> u64 {
+        match self.capacity {
+            0 => 1 << self.levels,
+            capacity => capacity,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.next_index as u64 >= self.capacity()
+    }
+
+    // Root stored in slot `i` of the history ring buffer
+    pub fn get_root_at_index(&self, i: u32) -> Option<U256> {
+        self.roots.get(i as usize).copied()
+    }
+
+    // Roots still accepted by `is_known_root`, oldest first
+    pub fn known_roots(&self) -> Vec<U256> {
+        let size = self.root_history_size;
+        (1..=size)
+            .map(|k| self.roots[((self.current_root_index + k) % size) as usize])
+            .filter(|root| !root.is_zero())
+            .collect()
+    }
+
+    // Last root in the byte order used by `hash_left_right`, for byte oriented provers
+    pub fn get_last_root_le_bytes(&self) -> [u8; 32] {
+        self.get_last_root().to_le_bytes()
+    }
+
+    // Path of `leaves[leaf_index]`, `leaves` being every leaf inserted so far.
+    // None for an index not inserted yet, or if hashing fails.
+    pub fn merkle_proof(&self, leaves: &[U256], leaf_index: u32) -> Option<MerklePath> {
+        let mut idx = leaf_index as usize;
+        if idx >= leaves.len() {
+            return None;
+        }
+
+        let mut path_elements = Vec::with_capacity(self.levels as usize);
+        let mut path_indices = Vec::with_capacity(self.levels as usize);
+
+        let mut layer = leaves.to_vec();
+        for zero in &self.zeros {
+            let is_right = idx & 1;
+            let sibling = if is_right == 0 {
+                layer.get(idx + 1).unwrap_or(zero)
+            } else {
+                &layer[idx - 1]
+            };
+            path_elements.push(*sibling);
+            path_indices.push(is_right as u8);
+
+            layer = layer
+                .chunks(2)
+                .map(|pair| self.hash_left_right(&pair[0], pair.get(1).unwrap_or(zero)))
+                .collect::<Result<_, _>>()
+                .ok()?;
+            idx /= 2;
+        }
+
+        Some(MerklePath {
+            path_elements,
+            path_indices,
+        })
+    }
+
+    // Path of a stored leaf, None if the tree doesn't keep leaves or the index isn't inserted yet
+    pub fn get_path(&self, leaf_index: u32) -> Option<MerklePath> {
+        self.leaves
+            .as_ref()
+            .and_then(|leaves| self.merkle_proof(leaves, leaf_index))
+    }
+
+    // Root obtained by hashing `leaf` up `path`, None for a malformed path or if hashing fails
+    pub fn path_root(&self, leaf: &U256, path: &MerklePath) -> Option<U256> {
+        if path.path_elements.len() != self.levels as usize
+            || path.path_indices.len() != self.levels as usize
+        {
+            return None;
+        }
+
+        let mut current = *leaf;
+        for (element, index) in path.path_elements.iter().zip(&path.path_indices) {
+            current = match index {
+                0 => self.hash_left_right(&current, element).ok()?,
+                1 => self.hash_left_right(element, &current).ok()?,
+                _ => return None,
+            };
+        }
+
+        Some(current)
+    }
+
+    // Hashes `leaf` up `path` and checks the resulting root is known
+    pub fn verify_merkle_proof(&self, leaf: &U256, path: &MerklePath) -> bool {
+        match self.path_root(leaf, path) {
+            Some(root) => self.is_known_root(&root),
+            None => false,
+        }
+    }
+
+    // Everything needed to rebuild the tree with `import`
+    pub fn export(&self) -> TreeSnapshot {
+        TreeSnapshot {
+            levels: self.levels,
+            root_history_size: self.root_history_size,
+            zero_value: self.ZERO_VALUE,
+            filled_subtrees: self.filled_subtrees.clone(),
+            zeros: self.zeros.clone(),
+            roots: self.roots.clone(),
+            root_sizes: self.root_sizes.clone(),
+            current_root_index: self.current_root_index,
+            next_index: self.next_index,
+            leaves: self.leaves.clone(),
+            poseidon_params: self.poseidon_params,
+        }
+    }
+
+    // Rebuilds an exported tree, checking its current root against the leaves
+    // when they're kept, or else against `filled_subtrees`. The latter can't tell
+    // trailing zero-value leaves from empty slots, and a full tree has no empty
+    // slot to recompute the root from, so it needs its leaves.
+    pub fn import(snapshot: TreeSnapshot) -> Result<Self, MerkleTreeError> {
+        let mut this = Self::try_new_with_params(
+            snapshot.levels,
+            snapshot.root_history_size,
+            Some(snapshot.zero_value),
+            snapshot.poseidon_params.unwrap_or_default(),
+        )?;
+        let capacity = this.capacity();
+
+        let consistent = snapshot.zeros == this.zeros
+            && snapshot.filled_subtrees.len() == this.levels as usize
+            && snapshot.roots.len() == this.root_history_size as usize
+            && snapshot.current_root_index < this.root_history_size
+            && snapshot.next_index as u64 <= capacity
+            && snapshot.filled_subtrees.iter().all(bignum::is_in_field)
+            && snapshot.roots.iter().all(bignum::is_in_field)
+            && (snapshot.root_sizes.is_empty()
+                || snapshot.root_sizes.len() == this.root_history_size as usize
+                    && snapshot.root_sizes[snapshot.current_root_index as usize]
+                        == snapshot.next_index
+                    && snapshot
+                        .root_sizes
+                        .iter()
+                        .all(|size| *size <= snapshot.next_index));
+        if !consistent {
+            return Err(MerkleTreeError::InvalidSnapshot);
+        }
+        let root = snapshot.roots[snapshot.current_root_index as usize];
+
+        match &snapshot.leaves {
+            Some(leaves) => {
+                if leaves.len() != snapshot.next_index as usize
+                    || !leaves.iter().all(bignum::is_in_field)
+                {
+                    return Err(MerkleTreeError::InvalidSnapshot);
+                }
+                let mut rebuilt = this.clone();
+                rebuilt.insert_many(leaves)?;
+                if rebuilt.get_last_root() != root
+                    || rebuilt.filled_subtrees != snapshot.filled_subtrees
+                {
+                    return Err(MerkleTreeError::InvalidSnapshot);
+                }
+            }
+            None => {
+                let mut edge = this.clone();
+                edge.filled_subtrees = snapshot.filled_subtrees.clone();
+                edge.next_index = snapshot.next_index;
+                if edge.edge_root() != Some(root) {
+                    return Err(MerkleTreeError::InvalidSnapshot);
+                }
+            }
+        }
+
+        this.filled_subtrees = snapshot.filled_subtrees;
+        this.roots = snapshot.roots;
+        this.root_sizes = snapshot.root_sizes;
+        this.current_root_index = snapshot.current_root_index;
+        this.next_index = snapshot.next_index;
+        this.leaves = snapshot.leaves;
+        Ok(this)
+    }
+
+    // Rightmost branch of the tree: for each level, the sibling the next
+    // inserted leaf's path will be hashed with.
+    // When the next leaf's node at level `i` is a right child, its sibling is the
+    // left subtree already filled (`filled_subtrees[i]`), otherwise nothing has been
+    // inserted on its right yet and the sibling is the empty subtree (`zeros[i]`).
+    pub fn current_edge(&self) -> Vec<U256> {
+        (0..self.levels as usize)
+            .map(|i| {
+                if (self.next_index >> i) & 1 == 1 {
+                    self.filled_subtrees[i]
+                } else {
+                    self.zeros[i]
+                }
+            })
+            .collect()
+    }
+
+    // Root recomputed from `current_edge`, that of the tree with an empty leaf at
+    // `next_index`. None for a full tree, which has no empty leaf left, or if hashing fails.
+    pub fn edge_root(&self) -> Option<U256> {
+        if self.is_full() {
+            return None;
+        }
+        let edge = self.current_edge();
+        let mut node = self.zeros[0];
+        for (i, sibling) in edge.iter().enumerate() {
+            node = if (self.next_index >> i) & 1 == 1 {
+                self.hash_left_right(sibling, &node).ok()?
+            } else {
+                self.hash_left_right(&node, sibling).ok()?
+            };
+        }
+        Some(node)
+    }
+}
+
+// Tree of a depth known at compile time, e.g. the depth a prover's circuit was
+// built for. Depths outside 1..=31 fail to compile rather than to construct.
+// MerkleTreeWithHistory stays the runtime depth tree, this wraps it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleTree<const LEVELS: usize> {
+    inner: MerkleTreeWithHistory,
+}
+
+impl<const LEVELS: usize> MerkleTree<LEVELS> {
+    const VALID_LEVELS: () = assert!(LEVELS >= 1 && LEVELS < 32, "LEVELS must be in 1..=31");
+
+    pub const LEVELS: u32 = LEVELS as u32;
+    pub const CAPACITY: u64 = 1 << LEVELS;
+
+    pub fn new() -> Self {
+        let () = Self::VALID_LEVELS;
+
+        MerkleTree {
+            inner: MerkleTreeWithHistory::new(Self::LEVELS),
+        }
+    }
+
+    pub fn insert(&mut self, leaf: &U256) -> Result<u32, MerkleTreeError> {
+        self.inner.insert(leaf)
+    }
+
+    pub fn get_last_root(&self) -> U256 {
+        self.inner.get_last_root()
+    }
+
+    pub fn is_known_root(&self, root: &U256) -> bool {
+        self.inner.is_known_root(root)
+    }
+
+    pub fn inner(&self) -> &MerkleTreeWithHistory {
+        &self.inner
+    }
+}
+
+impl<const LEVELS: usize> Default for MerkleTree<LEVELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const LEVELS: usize> From<MerkleTree<LEVELS>> for MerkleTreeWithHistory {
+    fn from(tree: MerkleTree<LEVELS>) -> Self {
+        tree.inner
+    }
 }
 
 #[cfg(test)]
@@ -129,6 +710,38 @@ mod tests {
     use super::*;
     use cosmwasm_std::Uint256 as U256;
 
+    // The tree hashes with the shared POSEIDON, it must agree with a fresh hasher
+    #[test]
+    fn test_merkletree_shared_hasher() {
+        let fresh = Poseidon::new();
+        let pairs = [
+            (U256::zero(), U256::zero()),
+            (U256::from(1_u8), U256::from(2_u8)),
+            (default_zero_value(), field_modulus() - U256::from(1_u8)),
+        ];
+        for (left, right) in pairs {
+            let expected = hash_pair_with(&fresh, &left, &right).unwrap();
+            assert_eq!(hash_pair(&left, &right).unwrap(), expected);
+            assert_eq!(hash_pair_with(&POSEIDON, &left, &right).unwrap(), expected);
+        }
+
+        // every root, recomputed from the edge with the fresh hasher
+        let mut mt = MerkleTreeWithHistory::new(7);
+        for i in 0..10_u32 {
+            let leaf = U256::from(i);
+            mt.insert(&leaf).unwrap();
+            let mut node = leaf;
+            for level in 0..7 {
+                node = if (i >> level) & 1 == 0 {
+                    hash_pair_with(&fresh, &node, &mt.zeros[level]).unwrap()
+                } else {
+                    hash_pair_with(&fresh, &mt.filled_subtrees[level], &node).unwrap()
+                };
+            }
+            assert_eq!(mt.get_last_root(), node);
+        }
+    }
+
     #[test]
     fn test_merkletree_new() {
         let mt = MerkleTreeWithHistory::new(16);
@@ -136,6 +749,341 @@ mod tests {
         assert_eq!(mt.zeros[0], mt.ZERO_VALUE);
     }
 
+    #[test]
+    fn test_merkletree_try_new_levels() {
+        assert_eq!(
+            MerkleTreeWithHistory::try_new(0, ROOT_HISTORY_SIZE, None),
+            Err(MerkleTreeError::InvalidLevels { levels: 0 })
+        );
+        assert_eq!(
+            MerkleTreeWithHistory::try_new(32, ROOT_HISTORY_SIZE, None),
+            Err(MerkleTreeError::InvalidLevels { levels: 32 })
+        );
+
+        let mt = MerkleTreeWithHistory::try_new(31, ROOT_HISTORY_SIZE, None).unwrap();
+        assert_eq!(mt.levels, 31);
+        assert_eq!(mt.zeros.len(), 31);
+
+        assert_eq!(
+            MerkleTreeWithHistory::try_new(20, 0, None),
+            Err(MerkleTreeError::InvalidRootHistorySize)
+        );
+    }
+
+    #[test]
+    fn test_merkletree_root_history_size() {
+        let mut mt = MerkleTreeWithHistory::try_new(4, 5, None).unwrap();
+        assert_eq!(mt.roots.len(), 5);
+
+        let mut roots = vec![];
+        for i in 1..=5_u32 {
+            mt.insert(&U256::from(i)).unwrap();
+            roots.push(mt.get_last_root());
+        }
+        assert!(roots.iter().all(|root| mt.is_known_root(root)));
+
+        // the 6th root evicts the first one
+        mt.insert(&U256::from(6_u32)).unwrap();
+        assert!(!mt.is_known_root(&roots[0]));
+        assert!(roots[1..].iter().all(|root| mt.is_known_root(root)));
+        assert!(mt.is_known_root(&mt.get_last_root()));
+    }
+
+    #[test]
+    fn test_merkletree_genesis_root() {
+        let mut mt = MerkleTreeWithHistory::try_new(4, 3, None).unwrap();
+        let genesis = mt.genesis_root().unwrap();
+        assert_eq!(mt.get_last_root(), genesis);
+        assert!(mt.is_empty());
+        assert!(mt.is_known_root(&genesis));
+
+        mt.insert(&U256::from(1_u32)).unwrap();
+        assert!(!mt.is_empty());
+        assert_ne!(mt.get_last_root(), genesis);
+        assert!(mt.is_known_root(&genesis));
+
+        // slot 0 is reused by the 3rd insert
+        mt.insert(&U256::from(2_u32)).unwrap();
+        assert!(mt.is_known_root(&genesis));
+        mt.insert(&U256::from(3_u32)).unwrap();
+        assert_eq!(mt.current_root_index, 0);
+        assert!(!mt.is_known_root(&genesis));
+        assert_eq!(mt.genesis_root().unwrap(), genesis);
+    }
+
+    #[test]
+    fn test_merkletree_known_root_size() {
+        let mut mt = MerkleTreeWithHistory::try_new(4, 3, None).unwrap();
+        let mut roots = vec![mt.get_last_root()];
+        assert_eq!(mt.known_root_size(&roots[0]), Some(0));
+        assert_eq!(mt.oldest_root_size(), 0);
+
+        for i in 1..=4_u32 {
+            mt.insert(&U256::from(i)).unwrap();
+            roots.push(mt.get_last_root());
+        }
+
+        // only the last 3 roots are kept
+        assert_eq!(mt.known_root_size(&roots[0]), None);
+        assert_eq!(mt.known_root_size(&roots[1]), None);
+        assert_eq!(mt.known_root_size(&roots[2]), Some(2));
+        assert_eq!(mt.known_root_size(&roots[4]), Some(4));
+        assert_eq!(mt.known_root_size(&U256::zero()), None);
+        assert_eq!(
+            mt.root_history(),
+            vec![(roots[2], 2), (roots[3], 3), (roots[4], 4)]
+        );
+        assert_eq!(mt.oldest_root_size(), 2);
+
+        // a tree stored before sizes were kept
+        let mut legacy = mt.clone();
+        legacy.root_sizes.clear();
+        assert_eq!(legacy.known_root_size(&roots[2]), Some(2));
+        assert_eq!(legacy.known_root_size(&roots[4]), Some(4));
+        legacy.insert(&U256::from(5_u8)).unwrap();
+        assert_eq!(legacy.known_root_size(&roots[3]), Some(3));
+        assert_eq!(legacy.known_root_size(&legacy.get_last_root()), Some(5));
+
+        // a batch records one root for several leaves
+        mt.insert_many(&[U256::from(5_u8), U256::from(6_u8), U256::from(7_u8)])
+            .unwrap();
+        let batch_root = mt.get_last_root();
+        mt.insert(&U256::from(8_u8)).unwrap();
+        assert_eq!(mt.known_root_size(&roots[4]), Some(4));
+        assert_eq!(mt.known_root_size(&batch_root), Some(7));
+        assert_eq!(mt.known_root_size(&mt.get_last_root()), Some(8));
+        assert_eq!(mt.oldest_root_size(), 4);
+
+        let snapshot = mt.export();
+        let imported = MerkleTreeWithHistory::import(snapshot.clone()).unwrap();
+        assert_eq!(imported.known_root_size(&batch_root), Some(7));
+        let mut tampered = snapshot;
+        tampered.root_sizes[tampered.current_root_index as usize] = 7;
+        assert_eq!(
+            MerkleTreeWithHistory::import(tampered),
+            Err(MerkleTreeError::InvalidSnapshot)
+        );
+    }
+
+    #[test]
+    fn test_merkletree_hash_failed() {
+        let mut mt = MerkleTreeWithHistory::new(4);
+        mt.poseidon_params = Some(PoseidonParams {
+            full_rounds: 0,
+            ..PoseidonParams::default()
+        });
+        assert_eq!(
+            mt.insert(&U256::from(1_u8)),
+            Err(MerkleTreeError::HashFailed)
+        );
+        assert_eq!(mt.genesis_root(), Err(MerkleTreeError::HashFailed));
+        assert_eq!(mt.edge_root(), None);
+    }
+
+    #[test]
+    fn test_merkletree_known_root_size_rebuild() {
+        let mut mt = MerkleTreeWithHistory::try_new(4, 3, None)
+            .unwrap()
+            .with_leaves();
+        for i in 1..=3_u32 {
+            mt.insert(&U256::from(i)).unwrap();
+        }
+        let previous = mt.roots[((mt.current_root_index + 2) % 3) as usize];
+
+        // a leaf the tree missed, the current root now covers 4 leaves
+        mt.leaves.as_mut().unwrap().push(U256::from(4_u8));
+        mt.rebuild_from_leaves().unwrap();
+        assert_eq!(mt.next_index, 4);
+        assert_eq!(mt.known_root_size(&mt.get_last_root()), Some(4));
+        assert_eq!(mt.known_root_size(&previous), Some(2));
+    }
+
+    #[test]
+    fn test_merkletree_zero_value() {
+        let default = MerkleTreeWithHistory::new(4);
+        assert_eq!(default.ZERO_VALUE, default_zero_value());
+
+        let zero_value = U256::from(42_u32);
+        let mt = MerkleTreeWithHistory::try_new(4, ROOT_HISTORY_SIZE, Some(zero_value)).unwrap();
+        assert_eq!(mt.ZERO_VALUE, zero_value);
+        assert_eq!(mt.zeros[0], zero_value);
+        assert_eq!(
+            mt.zeros[1],
+            mt.hash_left_right(&zero_value, &zero_value).unwrap()
+        );
+        for (zero, default_zero) in mt.zeros.iter().zip(&default.zeros) {
+            assert_ne!(zero, default_zero);
+        }
+        assert_ne!(mt.get_last_root(), default.get_last_root());
+
+        assert_eq!(
+            MerkleTreeWithHistory::try_new(4, ROOT_HISTORY_SIZE, Some(field_modulus())),
+            Err(MerkleTreeError::InvalidZeroValue)
+        );
+        let max = field_modulus() - U256::from(1_u8);
+        assert!(MerkleTreeWithHistory::try_new(4, ROOT_HISTORY_SIZE, Some(max)).is_ok());
+    }
+
+    #[test]
+    fn test_merkletree_insert_many() {
+        let leaves: Vec<U256> = (1..=5_u32).map(U256::from).collect();
+
+        let mut sequential = MerkleTreeWithHistory::new(3).with_leaves();
+        let mut roots = vec![];
+        for leaf in &leaves {
+            sequential.insert(leaf).unwrap();
+            roots.push(sequential.get_last_root());
+        }
+
+        let mut mt = MerkleTreeWithHistory::new(3).with_leaves();
+        assert_eq!(mt.insert_many(&leaves), Ok(vec![0, 1, 2, 3, 4]));
+        assert_eq!(mt.get_last_root(), sequential.get_last_root());
+        assert_eq!(mt.filled_subtrees, sequential.filled_subtrees);
+        assert_eq!(mt.leaves, sequential.leaves);
+        assert_eq!(mt.next_index, 5);
+        assert_eq!(mt.current_root_index, 1);
+
+        // intermediate roots were never recorded
+        assert!(!mt.is_known_root(&roots[3]));
+        assert!(mt.is_known_root(&roots[4]));
+
+        // all or nothing
+        assert_eq!(mt.insert_many(&leaves[..4]), Err(MerkleTreeError::TreeFull));
+        assert_eq!(mt.next_index, 5);
+        assert_eq!(mt.insert_many(&[]), Ok(vec![]));
+        assert_eq!(mt.insert_many(&leaves[..3]), Ok(vec![5, 6, 7]));
+        assert_eq!(mt.insert(&leaves[0]), Err(MerkleTreeError::TreeFull));
+    }
+
+    #[test]
+    fn test_merkletree_snapshot() {
+        let mut mt = MerkleTreeWithHistory::try_new(3, 4, None).unwrap();
+        let mut with_leaves = mt.clone().with_leaves();
+        for leaf in 1..=5_u64 {
+            mt.insert(&U256::from(leaf)).unwrap();
+            with_leaves.insert(&U256::from(leaf)).unwrap();
+        }
+
+        for mt in [mt.clone(), with_leaves.clone()] {
+            let snapshot = mt.export();
+            let imported = MerkleTreeWithHistory::import(snapshot.clone()).unwrap();
+            assert_eq!(imported, mt);
+            assert_eq!(imported.export(), snapshot);
+
+            // a subtree or the current root that doesn't match the rest
+            let mut tampered = snapshot.clone();
+            tampered.filled_subtrees[2] = U256::from(1_u8);
+            assert_eq!(
+                MerkleTreeWithHistory::import(tampered),
+                Err(MerkleTreeError::InvalidSnapshot)
+            );
+            let mut tampered = snapshot.clone();
+            tampered.roots[tampered.current_root_index as usize] = U256::from(1_u8);
+            assert_eq!(
+                MerkleTreeWithHistory::import(tampered),
+                Err(MerkleTreeError::InvalidSnapshot)
+            );
+            let mut tampered = snapshot.clone();
+            tampered.next_index -= 1;
+            assert_eq!(
+                MerkleTreeWithHistory::import(tampered),
+                Err(MerkleTreeError::InvalidSnapshot)
+            );
+            let mut tampered = snapshot;
+            tampered.roots.pop();
+            assert_eq!(
+                MerkleTreeWithHistory::import(tampered),
+                Err(MerkleTreeError::InvalidSnapshot)
+            );
+        }
+
+        let mut tampered = with_leaves.export();
+        tampered.leaves.as_mut().unwrap()[0] = U256::from(9_u8);
+        assert_eq!(
+            MerkleTreeWithHistory::import(tampered),
+            Err(MerkleTreeError::InvalidSnapshot)
+        );
+
+        // the genesis tree, and a full one that can only be checked with its leaves
+        let empty = MerkleTreeWithHistory::try_new(3, 4, None).unwrap();
+        assert_eq!(MerkleTreeWithHistory::import(empty.export()), Ok(empty));
+        for leaf in 6..=8_u64 {
+            mt.insert(&U256::from(leaf)).unwrap();
+            with_leaves.insert(&U256::from(leaf)).unwrap();
+        }
+        assert_eq!(
+            MerkleTreeWithHistory::import(mt.export()),
+            Err(MerkleTreeError::InvalidSnapshot)
+        );
+        assert_eq!(
+            MerkleTreeWithHistory::import(with_leaves.export()),
+            Ok(with_leaves)
+        );
+    }
+
+    #[test]
+    fn test_merkletree_rebuild_from_leaves() {
+        let mut mt = MerkleTreeWithHistory::new(4).with_leaves();
+        for i in 1..=5_u32 {
+            mt.insert(&U256::from(i)).unwrap();
+        }
+        let expected = mt.clone();
+
+        mt.filled_subtrees[1] = U256::from(42_u8);
+        mt.filled_subtrees[3] = U256::zero();
+        mt.next_index = 3;
+        mt.roots[mt.current_root_index as usize] = U256::from(42_u8);
+        mt.rebuild_from_leaves().unwrap();
+        assert_eq!(mt, expected);
+        assert_eq!(mt.get_last_root(), expected.get_last_root());
+
+        // and inserts carry on as if nothing happened
+        let mut expected = expected;
+        expected.insert(&U256::from(6_u8)).unwrap();
+        mt.insert(&U256::from(6_u8)).unwrap();
+        assert_eq!(mt.get_last_root(), expected.get_last_root());
+
+        let mut mt = MerkleTreeWithHistory::new(4);
+        assert_eq!(
+            mt.rebuild_from_leaves(),
+            Err(MerkleTreeError::LeavesNotKept)
+        );
+    }
+
+    #[test]
+    fn test_merkle_path_leaf_index() {
+        use crate::commitment::{commitment, nullifier_hash};
+
+        let nullifiers: Vec<U256> = (1..=5_u32).map(U256::from).collect();
+        let mut mt = MerkleTreeWithHistory::new(3).with_leaves();
+        for nullifier in &nullifiers {
+            mt.insert(&commitment(nullifier, &U256::zero())).unwrap();
+        }
+
+        for (i, nullifier) in nullifiers.iter().enumerate() {
+            let leaf = commitment(nullifier, &U256::zero());
+            let mut path = mt.get_path(i as u32).unwrap();
+            assert_eq!(path.leaf_index(), i as u32);
+            assert!(mt.verify_merkle_proof(&leaf, &path));
+
+            // claiming another index changes the nullifier hash and breaks the path
+            path.path_indices[0] ^= 1;
+            let other = path.leaf_index();
+            assert_ne!(other, i as u32);
+            assert_ne!(
+                nullifier_hash(nullifier, other as u128),
+                nullifier_hash(nullifier, i as u128)
+            );
+            assert!(!mt.verify_merkle_proof(&leaf, &path));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_merkletree_new_invalid_levels() {
+        MerkleTreeWithHistory::new(0);
+    }
+
     #[test]
     fn test_merkletree_root() {
         let mt = MerkleTreeWithHistory::new(20);
@@ -150,10 +1098,77 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_merkletree_poseidon_params() {
+        let with_params = |params| {
+            MerkleTreeWithHistory::try_new_with_params(20, ROOT_HISTORY_SIZE, None, params)
+        };
+
+        let mt = with_params(PoseidonParams::default()).unwrap();
+        assert_eq!(mt, MerkleTreeWithHistory::new(20));
+        assert_eq!(
+            mt.get_last_root(),
+            bignum!(
+                "19476726467694243150694636071195943429153087843379888650723427850220480216251"
+            )
+        );
+
+        let params = PoseidonParams {
+            partial_rounds: 56,
+            ..PoseidonParams::default()
+        };
+        let mut custom = with_params(params).unwrap();
+        assert_ne!(custom.get_last_root(), mt.get_last_root());
+
+        let mut mt = mt;
+        mt.insert(&U256::from(1_u8)).unwrap();
+        custom.insert(&U256::from(1_u8)).unwrap();
+        assert_ne!(custom.get_last_root(), mt.get_last_root());
+        assert_eq!(
+            MerkleTreeWithHistory::import(custom.export()).unwrap(),
+            custom
+        );
+
+        // only 2 input hashes, and rounds the constants cover
+        for params in [
+            PoseidonParams::circomlib(2).unwrap(),
+            PoseidonParams {
+                partial_rounds: 58,
+                ..PoseidonParams::default()
+            },
+        ] {
+            assert_eq!(
+                with_params(params),
+                Err(MerkleTreeError::InvalidPoseidonParams)
+            );
+        }
+    }
+
+    #[test]
+    fn test_merkletree_empty_root() {
+        assert_eq!(
+            MerkleTreeWithHistory::empty_root(16, None).unwrap(),
+            bignum!("4227387664466178643628175945231814400524887119677268757709033164980107894508")
+        );
+        assert_eq!(
+            MerkleTreeWithHistory::empty_root(20, None).unwrap(),
+            bignum!(
+                "19476726467694243150694636071195943429153087843379888650723427850220480216251"
+            )
+        );
+
+        let zero_value = U256::from(7_u8);
+        let mt = MerkleTreeWithHistory::try_new(4, ROOT_HISTORY_SIZE, Some(zero_value)).unwrap();
+        assert_eq!(
+            MerkleTreeWithHistory::empty_root(4, Some(zero_value)).unwrap(),
+            mt.get_last_root()
+        );
+    }
+
     #[test]
     fn test_merkletree_insert_single_01() {
         let mut mt = MerkleTreeWithHistory::new(20);
-        mt.insert(&U256::from(42 as u32));
+        mt.insert(&U256::from(42 as u32)).unwrap();
         let expected = bignum!(
             "13801027358871474054350913888493740197706640469969388660938924863508695867545"
         );
@@ -161,6 +1176,237 @@ mod tests {
         assert_eq!(root, expected);
     }
 
+    #[test]
+    fn test_merkletree_get_root_at_index() {
+        let mut mt = MerkleTreeWithHistory::try_new(4, 3, None).unwrap();
+        let initial = mt.get_last_root();
+        assert_eq!(mt.get_root_at_index(0), Some(initial));
+        assert_eq!(mt.get_root_at_index(1), Some(U256::zero()));
+        assert_eq!(mt.get_root_at_index(3), None);
+        assert_eq!(mt.known_roots(), vec![initial]);
+
+        let mut roots = vec![initial];
+        for i in 1..=4_u32 {
+            mt.insert(&U256::from(i)).unwrap();
+            roots.push(mt.get_last_root());
+        }
+
+        // after wrapping, slot 0 holds the 4th root
+        assert_eq!(mt.get_root_at_index(0), Some(roots[3]));
+        assert_eq!(mt.known_roots(), roots[2..].to_vec());
+        assert!(mt.known_roots().iter().all(|root| mt.is_known_root(root)));
+    }
+
+    #[test]
+    fn test_merkletree_hash_left_right() {
+        let mt = MerkleTreeWithHistory::new(2);
+        let (left, right) = (U256::from(1_u32), U256::from(2_u32));
+
+        assert_eq!(
+            mt.hash_left_right(&left, &right).unwrap(),
+            POSEIDON.hash_n(&[left, right]).unwrap()
+        );
+        assert_ne!(
+            mt.hash_left_right(&left, &right).unwrap(),
+            mt.hash_left_right(&right, &left).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_merkletree_full() {
+        let mut mt = MerkleTreeWithHistory::new(3);
+        for i in 0..8_u32 {
+            assert_eq!(mt.insert(&U256::from(i + 42)), Ok(i));
+        }
+
+        let root = mt.get_last_root();
+        assert_eq!(
+            mt.insert(&U256::from(1337_u32)),
+            Err(MerkleTreeError::TreeFull)
+        );
+        assert_eq!(mt.next_index, 8);
+        assert_eq!(mt.get_last_root(), root);
+    }
+
+    #[test]
+    fn test_merkletree_const_levels() {
+        let mut mt4 = MerkleTree::<4>::new();
+        let mut mt8 = MerkleTree::<8>::default();
+        let mut dyn4 = MerkleTreeWithHistory::new(4);
+        let mut dyn8 = MerkleTreeWithHistory::new(8);
+        assert_eq!(MerkleTree::<4>::CAPACITY, 16);
+        assert_eq!(MerkleTree::<8>::CAPACITY, 256);
+        assert_eq!(mt4.get_last_root(), dyn4.get_last_root());
+        assert_eq!(mt8.get_last_root(), dyn8.get_last_root());
+
+        for i in 0..5_u32 {
+            let leaf = U256::from(i + 42);
+            assert_eq!(mt4.insert(&leaf), dyn4.insert(&leaf));
+            assert_eq!(mt8.insert(&leaf), dyn8.insert(&leaf));
+        }
+        assert_eq!(mt4.get_last_root(), dyn4.get_last_root());
+        assert_eq!(mt8.get_last_root(), dyn8.get_last_root());
+        assert!(mt8.is_known_root(&dyn8.get_last_root()));
+        assert_eq!(MerkleTreeWithHistory::from(mt4), dyn4);
+    }
+
+    #[test]
+    fn test_merkletree_capacity() {
+        assert_eq!(MerkleTreeWithHistory::new(3).capacity(), 8);
+
+        // the largest tree
+        let mut mt = MerkleTreeWithHistory::new(31);
+        assert_eq!(mt.capacity(), 1 << 31);
+        assert_eq!(mt.capacity, 1 << 31);
+
+        // the last slot, then the overflowing insert
+        mt.next_index = (1 << 31) - 1;
+        assert!(!mt.is_full());
+        assert_eq!(mt.insert(&U256::from(42_u32)), Ok((1 << 31) - 1));
+        assert!(mt.is_full());
+        assert_eq!(
+            mt.insert(&U256::from(43_u32)),
+            Err(MerkleTreeError::TreeFull)
+        );
+        assert_eq!(mt.next_index, 1 << 31);
+        assert_eq!(
+            mt.insert_many(&[U256::from(43_u32)]),
+            Err(MerkleTreeError::TreeFull)
+        );
+
+        // stored before the capacity was kept
+        let mt = MerkleTreeWithHistory::new(3);
+        let json = serde_json::to_string(&mt).unwrap();
+        let legacy = json.replace(r#""capacity":8,"#, "");
+        assert_ne!(legacy, json);
+        let legacy: MerkleTreeWithHistory = serde_json::from_str(&legacy).unwrap();
+        assert_eq!(legacy.capacity, 0);
+        assert_eq!(legacy.capacity(), 8);
+    }
+
+    #[test]
+    fn test_merkletree_fill_ratio() {
+        let mut mt = MerkleTreeWithHistory::new(3);
+        assert_eq!(mt.fill_ratio(), (0, 8));
+
+        for i in 0..6_u32 {
+            mt.insert(&U256::from(i + 42)).unwrap();
+        }
+        assert_eq!(mt.fill_ratio(), (6, 8));
+
+        // the largest tree, one leaf short of full
+        let mut mt = MerkleTreeWithHistory::new(31);
+        mt.next_index = (1 << 31) - 1;
+        assert_eq!(mt.fill_ratio(), ((1 << 31) - 1, 1 << 31));
+    }
+
+    #[test]
+    fn test_merkletree_is_known_root_wrap() {
+        let mut mt = MerkleTreeWithHistory::new(7);
+        let mut roots = vec![mt.get_last_root()];
+        for i in 0..(ROOT_HISTORY_SIZE + 10) {
+            mt.insert(&U256::from(i + 1)).unwrap();
+            roots.push(mt.get_last_root());
+        }
+
+        // genesis and the first 10 insertions have been evicted
+        let (evicted, retained) = roots.split_at(roots.len() - ROOT_HISTORY_SIZE as usize);
+        assert_eq!(evicted.len(), 11);
+        for root in evicted {
+            assert!(!mt.is_known_root(root));
+        }
+        for root in retained {
+            assert!(mt.is_known_root(root));
+        }
+        assert!(!mt.is_known_root(&U256::zero()));
+    }
+
+    #[test]
+    fn test_merkletree_merkle_proof() {
+        let mut mt = MerkleTreeWithHistory::new(3);
+        let leaves: Vec<U256> = (1..=5_u32).map(U256::from).collect();
+        for leaf in &leaves {
+            mt.insert(leaf).unwrap();
+        }
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            let path = mt.merkle_proof(&leaves, i as u32).unwrap();
+            assert_eq!(path.path_elements.len(), 3);
+            assert!(mt.verify_merkle_proof(leaf, &path));
+            assert!(!mt.verify_merkle_proof(&U256::from(42_u32), &path));
+        }
+
+        assert!(mt.merkle_proof(&leaves, 5).is_none());
+    }
+
+    #[test]
+    fn test_merkletree_get_path() {
+        let mut mt = MerkleTreeWithHistory::new(4).with_leaves();
+        let leaves: Vec<U256> = (1..=6_u32).map(|i| U256::from(i * 1000)).collect();
+        for leaf in &leaves {
+            mt.insert(leaf).unwrap();
+        }
+        assert_eq!(mt.leaves.as_ref(), Some(&leaves));
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            let path = mt.get_path(i as u32).unwrap();
+            assert_eq!(path.path_elements.len(), 4);
+            assert_eq!(path.path_indices.len(), 4);
+            assert_eq!(mt.path_root(leaf, &path), Some(mt.get_last_root()));
+        }
+        assert!(mt.get_path(6).is_none());
+
+        // leaves aren't kept by default
+        let mut mt = MerkleTreeWithHistory::new(4);
+        mt.insert(&leaves[0]).unwrap();
+        assert!(mt.leaves.is_none());
+        assert!(mt.get_path(0).is_none());
+    }
+
+    #[test]
+    fn test_merkletree_last_root_le_bytes() {
+        let mut mt = MerkleTreeWithHistory::new(20);
+        mt.insert(&U256::from(42_u32)).unwrap();
+
+        let bytes = mt.get_last_root_le_bytes();
+        assert_eq!(
+            U256::from_le_bytes(bytes).to_string(),
+            "13801027358871474054350913888493740197706640469969388660938924863508695867545"
+        );
+    }
+
+    #[test]
+    fn test_merkletree_current_edge() {
+        let mut mt = MerkleTreeWithHistory::new(3);
+        assert_eq!(mt.current_edge(), mt.zeros);
+
+        let leaves: Vec<U256> = (1..=3_u32).map(U256::from).collect();
+        for leaf in &leaves {
+            mt.insert(leaf).unwrap();
+        }
+
+        // proof for the last leaf (index 2 = 0b010)
+        let h01 = mt.hash_left_right(&leaves[0], &leaves[1]).unwrap();
+        let h2z = mt.hash_left_right(&leaves[2], &mt.zeros[0]).unwrap();
+        let h = mt.hash_left_right(&h01, &h2z).unwrap();
+        assert_eq!(
+            mt.hash_left_right(&h, &mt.zeros[2]).unwrap(),
+            mt.get_last_root()
+        );
+
+        // next leaf (index 3 = 0b011) sits right of leaf 2, and right of (0, 1)
+        let edge = mt.current_edge();
+        assert_eq!(edge, vec![leaves[2], h01, mt.zeros[2]]);
+
+        let leaf = U256::from(4_u32);
+        let h23 = mt.hash_left_right(&edge[0], &leaf).unwrap();
+        let h = mt.hash_left_right(&edge[1], &h23).unwrap();
+        let root = mt.hash_left_right(&h, &edge[2]).unwrap();
+
+        mt.insert(&leaf).unwrap();
+        assert_eq!(root, mt.get_last_root());
+    }
+
     // #[test]
     // fn test_merkletree_insert_single_3() {
     //     let mut mt = MerkleTreeWithHistory::new(3);
@@ -279,3 +1525,139 @@ mod tests {
     //     // assert_eq!(expected_root, tree.roots[1]);
     // }
 }
+
+// Randomized checks that insert, merkle_proof and verify_merkle_proof agree, and
+// that roots and their history match a from-scratch recomputation. Cases come
+// from fixed seeds, a failure isn't shrunk, it reports the seed to replay.
+// cargo test -p juicer --features randomized-tests
+#[cfg(all(test, feature = "randomized-tests"))]
+mod randomized_tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const CASES: u64 = 8;
+
+    // below 2^248, always in the bn254 scalar field
+    fn random_leaf(rng: &mut StdRng) -> U256 {
+        let mut bytes = [0u8; 32];
+        rng.fill(&mut bytes[..31]);
+        U256::from_le_bytes(bytes)
+    }
+
+    // A tree of 2..=8 levels with a short root history so eviction is exercised,
+    // filled anywhere up to capacity. Failures report the seed to replay.
+    fn random_case(seed: u64) -> (u32, u32, Vec<U256>) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let levels = rng.gen_range(2..=(2 + seed as u32 % 7));
+        let root_history_size = rng.gen_range(1..=8);
+        let count = rng.gen_range(0..=2_u32.pow(levels));
+        let leaves = (0..count).map(|_| random_leaf(&mut rng)).collect();
+        (levels, root_history_size, leaves)
+    }
+
+    // Hashes the full level above `nodes` until a single root is left
+    fn naive_root(mt: &MerkleTreeWithHistory, leaves: &[U256]) -> U256 {
+        let mut nodes = leaves.to_vec();
+        nodes.resize(2_usize.pow(mt.levels), mt.ZERO_VALUE);
+        while nodes.len() > 1 {
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| mt.hash_left_right(&pair[0], &pair[1]).unwrap())
+                .collect();
+        }
+        nodes[0]
+    }
+
+    #[test]
+    fn random_root_matches_recomputation() {
+        for seed in 0..CASES * 4 {
+            let (levels, root_history_size, leaves) = random_case(seed);
+            let mut mt = MerkleTreeWithHistory::try_new(levels, root_history_size, None).unwrap();
+            assert_eq!(mt.get_last_root(), naive_root(&mt, &[]), "seed {}", seed);
+
+            for (i, leaf) in leaves.iter().enumerate() {
+                assert_eq!(mt.insert(leaf), Ok(i as u32), "seed {} leaf {}", seed, i);
+            }
+            assert_eq!(
+                mt.get_last_root(),
+                naive_root(&mt, &leaves),
+                "seed {}",
+                seed
+            );
+
+            if leaves.len() == 2_usize.pow(levels) {
+                assert_eq!(
+                    mt.insert(&U256::from(1_u64)),
+                    Err(MerkleTreeError::TreeFull),
+                    "seed {}",
+                    seed
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn random_roots_known_until_evicted() {
+        for seed in 0..CASES * 4 {
+            let (levels, root_history_size, leaves) = random_case(seed);
+            let mut mt = MerkleTreeWithHistory::try_new(levels, root_history_size, None).unwrap();
+
+            let mut roots = vec![mt.get_last_root()];
+            for leaf in &leaves {
+                mt.insert(leaf).unwrap();
+                roots.push(mt.get_last_root());
+
+                // only the last `root_history_size` roots are kept
+                let kept = roots.len().saturating_sub(root_history_size as usize);
+                for (i, root) in roots.iter().enumerate() {
+                    assert_eq!(
+                        mt.is_known_root(root),
+                        i >= kept,
+                        "seed {} root {} of {}",
+                        seed,
+                        i,
+                        roots.len()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_merkle_proof_consistency() {
+        for seed in 0..CASES {
+            let mut rng = StdRng::seed_from_u64(seed);
+
+            let levels = rng.gen_range(1..=4);
+            let count = rng.gen_range(1..=2_u32.pow(levels));
+
+            let mut mt = MerkleTreeWithHistory::new(levels);
+            let leaves: Vec<U256> = (0..count).map(|_| random_leaf(&mut rng)).collect();
+            for leaf in &leaves {
+                mt.insert(leaf).unwrap();
+            }
+
+            for (i, leaf) in leaves.iter().enumerate() {
+                let path = mt.merkle_proof(&leaves, i as u32).unwrap();
+                assert!(
+                    mt.verify_merkle_proof(leaf, &path),
+                    "seed {} leaf {}",
+                    seed,
+                    i
+                );
+
+                let mut tampered = path.clone();
+                let level = rng.gen_range(0..levels as usize);
+                tampered.path_elements[level] = random_leaf(&mut rng);
+                assert!(
+                    !mt.verify_merkle_proof(leaf, &tampered),
+                    "seed {} leaf {} level {}",
+                    seed,
+                    i,
+                    level
+                );
+            }
+        }
+    }
+}
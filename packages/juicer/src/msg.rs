@@ -1,16 +1,24 @@
-use crate::poseidon::Poseidon;
+use crate::bignum::FieldElement;
+use crate::commitment::{commitment, nullifier_hash};
+use crate::poseidon::POSEIDON;
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::str::FromStr;
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use ark_bn254::{Bn254, Fr, G1Affine, G2Affine};
-use ark_ff::{Fp256, QuadExtField};
+use ark_bn254::{Bn254, Fq, Fr, G1Affine, G2Affine};
+use ark_ff::{BigInteger, PrimeField, QuadExtField};
 use ark_groth16::Proof;
+use ark_std::rand::RngCore;
+use ark_std::UniformRand;
 
-use cosmwasm_std::{Uint128 as U128, Uint256 as U256};
+use bech32::{FromBase32, ToBase32};
+use cosmwasm_std::{Addr, Api, StdError, StdResult, Uint128 as U128, Uint256 as U256};
+
+#[cfg(test)]
+use crate::test_fixtures::PROOF_JSON;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct PublicSignals(pub Vec<String>);
@@ -27,15 +35,50 @@ impl PublicSignals {
         recipient: String,
         relayer: String,
         fee: U128,
+    ) -> Result<Self, SignalError> {
+        let root =
+            FieldElement::try_from(root.as_str()).map_err(|_| SignalError::InvalidRoot { root })?;
+        let nullifier_hash = FieldElement::try_from(nullifier_hash.as_str())
+            .map_err(|_| SignalError::InvalidNullifierHash { nullifier_hash })?;
+        let recipient = address_signal(&recipient)
+            .ok_or(SignalError::InvalidRecipient { recipient })?
+            .to_string();
+        // no relayer is "" or "0"
+        let relayer = PublicSignals::bech32_to_u256(&relayer)
+            .ok_or(SignalError::InvalidRelayer { relayer })?;
+
+        let signals = vec![
+            root.to_string(),
+            nullifier_hash.to_string(),
+            recipient,
+            relayer,
+            fee.to_string(),
+        ];
+
+        Ok(PublicSignals(signals))
+    }
+    // Partial withdraw circuit, the withdraw signals followed by
+    // [changeCommitment, denomination, noteValue, amount]
+    pub fn with_change(
+        mut self,
+        change_commitment: String,
+        denomination: U128,
+        note_value: U128,
+        amount: U128,
     ) -> Self {
-        let mut signals: Vec<String> = Vec::new();
-        signals.push(root);
-        signals.push(nullifier_hash);
-        signals.push(PublicSignals::bech32_to_u256(recipient));
-        signals.push(PublicSignals::bech32_to_u256(relayer));
-        signals.push(fee.to_string());
+        self.0.push(change_commitment);
+        self.0.push(denomination.to_string());
+        self.0.push(note_value.to_string());
+        self.0.push(amount.to_string());
 
-        PublicSignals(signals)
+        self
+    }
+    // Split withdrawal, the recipient signal is the `split_commitment_hash` of
+    // the payouts instead of an address
+    pub fn with_split(mut self, split_hash: String) -> Self {
+        self.0[2] = split_hash;
+
+        self
     }
     pub fn from_json(public_signals_json: String) -> Self {
         let v: Vec<String> = serde_json::from_str(&public_signals_json).unwrap();
@@ -50,18 +93,238 @@ impl PublicSignals {
         inputs
     }
 
-    fn bech32_to_u256(addr: String) -> String {
-        if addr == "" || addr == "0" {
-            return "0".to_string();
+    // Field encoding of `addr`, only if it's the canonical bech32 encoding under `prefix`.
+    // The proof commits to the decoded payload alone, so an address with another prefix
+    // or another casing would verify while funds go elsewhere.
+    pub fn encode_address(addr: &str, prefix: &str) -> Option<String> {
+        let (hrp, payloads, variant) = bech32::decode(addr).ok()?;
+        if hrp != prefix || payloads.len() != 32 {
+            return None;
+        }
+        if bech32::encode(&hrp, &payloads, variant).ok()? != addr {
+            return None;
         }
-        let (_, payloads, _) = bech32::decode(&addr).unwrap();
 
-        let words: Vec<u8> = payloads.iter().map(|x| x.to_u8()).collect();
-        // TODO: take a look at a cleaner way
-        U256::from_be_bytes(words.try_into().unwrap()).to_string()
+        PublicSignals::bech32_to_u256(addr)
+    }
+
+    // Field encoding of any bech32 address with a 20 byte payload, whatever its
+    // prefix, "" and "0" being no address. None for anything else
+    pub(crate) fn bech32_to_u256(addr: &str) -> Option<String> {
+        if addr.is_empty() || addr == "0" {
+            return Some("0".to_string());
+        }
+        let (_, payloads, _) = bech32::decode(addr).ok()?;
+        let canonical = Vec::<u8>::from_base32(&payloads).ok()?;
+
+        canonical_to_field(&canonical).map(|field| field.to_string())
+    }
+}
+
+// Field element the circuit takes for an address, which it must rebuild the same way:
+// the 20 canonical bytes are split into 32 5-bit words, most significant bits first,
+// and the words are read as a 32 byte big-endian integer, one word per byte. So
+// it's the bech32 data part without the checksum, and the same for every prefix.
+// None unless `canonical` holds 20 bytes.
+pub fn canonical_to_field(canonical: &[u8]) -> Option<U256> {
+    if canonical.len() != 20 {
+        return None;
+    }
+    let words: Vec<u8> = canonical.to_base32().iter().map(|x| x.to_u8()).collect();
+
+    Some(U256::from_be_bytes(words.try_into().ok()?))
+}
+
+// `canonical_to_field` of the bytes from `addr_canonicalize`, so chains and relayers
+// agree on the recipient signal whatever the address prefix
+pub fn address_to_field(api: &dyn Api, addr: &Addr) -> StdResult<U256> {
+    let canonical = api.addr_canonicalize(addr.as_str())?;
+    canonical_to_field(canonical.as_slice())
+        .ok_or_else(|| StdError::generic_err(format!("{} isn't a 20 byte address", addr)))
+}
+
+// Hash binding a withdrawal to its recipient for the commit-reveal flow
+// poseidon(nullifierHash, recipient, salt)
+pub fn withdraw_commitment_hash(
+    nullifier_hash: &str,
+    recipient: &str,
+    salt: &str,
+) -> Result<String, SignalError> {
+    let nullifier_hash: U256 = FieldElement::try_from(nullifier_hash)
+        .map_err(|_| SignalError::InvalidNullifierHash {
+            nullifier_hash: nullifier_hash.to_string(),
+        })?
+        .into();
+    let recipient = address_signal(recipient).ok_or_else(|| SignalError::InvalidRecipient {
+        recipient: recipient.to_string(),
+    })?;
+    let salt: U256 = FieldElement::try_from(salt)
+        .map_err(|_| SignalError::InvalidSalt {
+            salt: salt.to_string(),
+        })?
+        .into();
+
+    let inputs = vec![
+        nullifier_hash.to_le_bytes(),
+        recipient.to_le_bytes(),
+        salt.to_le_bytes(),
+    ];
+
+    Ok(POSEIDON.hash_as_u256(inputs).unwrap().to_string())
+}
+
+// Hash binding a withdrawal to every (address, amount) it pays, in order
+// h = poseidon(h, address, amount), starting from h = 0
+pub fn split_commitment_hash(payouts: &[(String, U128)]) -> Result<String, SignalError> {
+    let mut hash = U256::zero();
+    for (address, amount) in payouts {
+        let address = address_signal(address).ok_or_else(|| SignalError::InvalidRecipient {
+            recipient: address.clone(),
+        })?;
+        hash = POSEIDON
+            .hash_n(&[hash, address, U256::from(amount.u128())])
+            .unwrap();
+    }
+
+    Ok(hash.to_string())
+}
+
+// An actual address, unlike the relayer signal where "" and "0" are none
+fn address_signal(addr: &str) -> Option<U256> {
+    match addr {
+        "" | "0" => None,
+        addr => U256::from_str(&PublicSignals::bech32_to_u256(addr)?).ok(),
+    }
+}
+
+/// The proof error types.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProofError {
+    /// Returned if the proof isn't a groth16 proof over bn128
+    UnsupportedProtocol { protocol: String, curve: String },
+    /// Returned if a point isn't on BN254 or outside its prime order subgroup
+    PointNotOnCurve { point: String },
+    /// Returned if a flat proof doesn't hold exactly 8 coordinates
+    InvalidFlatProof { len: usize },
+    /// Returned if a point lacks a coordinate or one isn't a base field element
+    MalformedPoint { point: String },
+}
+
+impl std::fmt::Display for ProofError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProofError::UnsupportedProtocol { protocol, curve } => write!(
+                f,
+                "Unsupported proof: {} over {} (expected {} over {})",
+                protocol, curve, PROOF_PROTOCOL, PROOF_CURVE
+            ),
+            ProofError::PointNotOnCurve { point } => {
+                write!(f, "Proof point {} isn't on the curve", point)
+            }
+            ProofError::InvalidFlatProof { len } => {
+                write!(f, "Flat proofs hold 8 coordinates, got {}", len)
+            }
+            ProofError::MalformedPoint { point } => write!(f, "Proof point {} is malformed", point),
+        }
+    }
+}
+
+impl std::error::Error for ProofError {}
+
+/// The public signal error types.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignalError {
+    /// Returned if the root isn't a BN254 scalar field element
+    InvalidRoot { root: String },
+    /// Returned if the nullifier hash isn't a BN254 scalar field element
+    InvalidNullifierHash { nullifier_hash: String },
+    /// Returned if the recipient isn't a bech32 address
+    InvalidRecipient { recipient: String },
+    /// Returned if the relayer isn't a bech32 address, "" or "0"
+    InvalidRelayer { relayer: String },
+    /// Returned if a commit-reveal salt isn't a BN254 scalar field element
+    InvalidSalt { salt: String },
+}
+
+impl std::fmt::Display for SignalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignalError::InvalidRoot { root } => write!(f, "Invalid root signal: {}", root),
+            SignalError::InvalidNullifierHash { nullifier_hash } => {
+                write!(f, "Invalid nullifier hash signal: {}", nullifier_hash)
+            }
+            SignalError::InvalidRecipient { recipient } => {
+                write!(f, "Invalid recipient signal: {}", recipient)
+            }
+            SignalError::InvalidRelayer { relayer } => {
+                write!(f, "Invalid relayer signal: {}", relayer)
+            }
+            SignalError::InvalidSalt { salt } => write!(f, "Invalid salt: {}", salt),
+        }
+    }
+}
+
+impl std::error::Error for SignalError {}
+
+/// The note parsing error types.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoteError {
+    /// Returned if the note doesn't start with `<network>-juicer-`
+    InvalidPrefix,
+    /// Returned if the note was made for another network's pools
+    WrongNetwork { expected: String, got: String },
+    /// Returned if the note payload isn't 32 hex encoded bytes
+    InvalidHex,
+}
+
+impl std::fmt::Display for NoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoteError::InvalidPrefix => write!(f, "Note must start with <network>-juicer-"),
+            NoteError::WrongNetwork { expected, got } => {
+                write!(f, "Note is for {} pools, expected {}", got, expected)
+            }
+            NoteError::InvalidHex => write!(f, "Note must hold 32 hex encoded bytes"),
+        }
     }
 }
 
+impl std::error::Error for NoteError {}
+
+// Notes are `<network>-juicer-<hex>`, the hex being the nullifier as 32 little-endian
+// bytes. `Deposit::to_note` and `from_note` use the juno network.
+pub const NOTE_PREFIX: &str = "juno-juicer-";
+pub const DEFAULT_NOTE_NETWORK: &str = "juno";
+
+// Network of the pools notes are made for, e.g. "osmo" or "stars" when deployed
+// on Osmosis or Stargaze, so a note can't be imported into another chain's pool
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoteFormat {
+    pub network: String,
+}
+
+impl NoteFormat {
+    pub fn new(network: &str) -> Self {
+        NoteFormat {
+            network: network.to_string(),
+        }
+    }
+
+    pub fn prefix(&self) -> String {
+        format!("{}-juicer-", self.network)
+    }
+}
+
+impl Default for NoteFormat {
+    fn default() -> Self {
+        NoteFormat::new(DEFAULT_NOTE_NETWORK)
+    }
+}
+
+// snarkjs names of the proving system and curve the verifier supports
+pub const PROOF_PROTOCOL: &str = "groth16";
+pub const PROOF_CURVE: &str = "bn128";
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CircomProof {
@@ -75,38 +338,138 @@ pub struct CircomProof {
     pub curve: String,
 }
 
+// A proof as the snarkjs JSON object, or as its 8 affine coordinates
+// [a.x, a.y, b.x.c0, b.x.c1, b.y.c0, b.y.c1, c.x, c.y], about a third of the size.
+// Tagged since serde-json-wasm can't deserialize untagged enums
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofInput {
+    Circom(CircomProof),
+    Flat(Vec<String>),
+}
+
+impl ProofInput {
+    pub fn to_circom(self) -> Result<CircomProof, ProofError> {
+        match self {
+            ProofInput::Circom(proof) => Ok(proof),
+            ProofInput::Flat(values) => CircomProof::from_flat(&values),
+        }
+    }
+
+    pub fn to_proof(self) -> Result<Proof<Bn254>, ProofError> {
+        self.to_circom()?.to_proof()
+    }
+}
+
+impl From<CircomProof> for ProofInput {
+    fn from(proof: CircomProof) -> Self {
+        ProofInput::Circom(proof)
+    }
+}
+
 impl CircomProof {
     pub fn from(json_str: String) -> Self {
         serde_json::from_str(&json_str).unwrap()
     }
 
-    pub fn to_proof(self) -> Proof<Bn254> {
+    // Groth16 over bn128, from the coordinates listed on `ProofInput`
+    pub fn from_flat(values: &[String]) -> Result<Self, ProofError> {
+        if values.len() != 8 {
+            return Err(ProofError::InvalidFlatProof { len: values.len() });
+        }
+        let one = || "1".to_string();
+
+        Ok(CircomProof {
+            pi_a: vec![values[0].clone(), values[1].clone(), one()],
+            pi_b: vec![
+                vec![values[2].clone(), values[3].clone()],
+                vec![values[4].clone(), values[5].clone()],
+                vec![one(), "0".to_string()],
+            ],
+            pi_c: vec![values[6].clone(), values[7].clone(), one()],
+            protocol: PROOF_PROTOCOL.to_string(),
+            curve: PROOF_CURVE.to_string(),
+        })
+    }
+
+    pub fn to_flat(&self) -> Vec<String> {
+        vec![
+            self.pi_a[0].clone(),
+            self.pi_a[1].clone(),
+            self.pi_b[0][0].clone(),
+            self.pi_b[0][1].clone(),
+            self.pi_b[1][0].clone(),
+            self.pi_b[1][1].clone(),
+            self.pi_c[0].clone(),
+            self.pi_c[1].clone(),
+        ]
+    }
+
+    pub fn to_proof(self) -> Result<Proof<Bn254>, ProofError> {
+        if self.protocol != PROOF_PROTOCOL || self.curve != PROOF_CURVE {
+            return Err(ProofError::UnsupportedProtocol {
+                protocol: self.protocol,
+                curve: self.curve,
+            });
+        }
+
+        // only the affine coordinates are read, the projective ones are always 1
         let a = G1Affine::new(
-            Fp256::from_str(&self.pi_a[0]).unwrap(),
-            Fp256::from_str(&self.pi_a[1]).unwrap(),
+            coordinate("pi_a", &self.pi_a, 0)?,
+            coordinate("pi_a", &self.pi_a, 1)?,
             false,
         );
+        let pi_b = |i: usize| {
+            self.pi_b.get(i).ok_or(ProofError::MalformedPoint {
+                point: "pi_b".to_string(),
+            })
+        };
         let b = G2Affine::new(
             QuadExtField::new(
-                Fp256::from_str(&self.pi_b[0][0]).unwrap(),
-                Fp256::from_str(&self.pi_b[0][1]).unwrap(),
+                coordinate("pi_b", pi_b(0)?, 0)?,
+                coordinate("pi_b", pi_b(0)?, 1)?,
             ),
             QuadExtField::new(
-                Fp256::from_str(&self.pi_b[1][0]).unwrap(),
-                Fp256::from_str(&self.pi_b[1][1]).unwrap(),
+                coordinate("pi_b", pi_b(1)?, 0)?,
+                coordinate("pi_b", pi_b(1)?, 1)?,
             ),
             false,
         );
 
         let c = G1Affine::new(
-            Fp256::from_str(&self.pi_c[0]).unwrap(),
-            Fp256::from_str(&self.pi_c[1]).unwrap(),
+            coordinate("pi_c", &self.pi_c, 0)?,
+            coordinate("pi_c", &self.pi_c, 1)?,
             false,
         );
-        Proof { a, b, c }
+
+        // the pairing is only meaningful for points of the prime order subgroups
+        let not_on_curve = |point: &str| ProofError::PointNotOnCurve {
+            point: point.to_string(),
+        };
+        if !a.is_on_curve() || !a.is_in_correct_subgroup_assuming_on_curve() {
+            return Err(not_on_curve("pi_a"));
+        }
+        if !b.is_on_curve() || !b.is_in_correct_subgroup_assuming_on_curve() {
+            return Err(not_on_curve("pi_b"));
+        }
+        if !c.is_on_curve() || !c.is_in_correct_subgroup_assuming_on_curve() {
+            return Err(not_on_curve("pi_c"));
+        }
+
+        Ok(Proof { a, b, c })
     }
 }
 
+// The `index`th coordinate of `point`, a decimal base field element
+fn coordinate(point: &str, coordinates: &[String], index: usize) -> Result<Fq, ProofError> {
+    coordinates
+        .get(index)
+        .and_then(|coordinate| Fq::from_str(coordinate).ok())
+        .ok_or_else(|| ProofError::MalformedPoint {
+            point: point.to_string(),
+        })
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Deposit {
     pub nullifier: String,
@@ -120,36 +483,66 @@ impl Deposit {
         }
     }
 
-    pub fn get_commitment(self) -> String {
-        let poseidon = Poseidon::new();
+    // Samples a nullifier uniformly below the BN254 scalar modulus.
+    // Meant for the host (wallet, CLI): there is no secure randomness on chain.
+    pub fn new_random<R: RngCore>(rng: &mut R) -> Deposit {
+        let nullifier = Fr::rand(rng).into_repr().to_bytes_le();
 
-        let right = U256::zero();
+        Deposit::new(U256::from_le_bytes(nullifier.try_into().unwrap()).to_string())
+    }
 
+    pub fn to_note(&self) -> String {
+        self.to_note_with(&NoteFormat::default())
+    }
+
+    pub fn to_note_with(&self, format: &NoteFormat) -> String {
         let nullifier = U256::from_str(&self.nullifier).unwrap();
-        let inputs = vec![nullifier.to_le_bytes(), right.to_le_bytes()];
 
-        let res = poseidon.hash(inputs).unwrap();
+        format!(
+            "{}{}",
+            format.prefix(),
+            hex::encode(nullifier.to_le_bytes())
+        )
+    }
 
-        U256::from_le_bytes(res).to_string()
+    pub fn from_note(note: &str) -> Result<Deposit, NoteError> {
+        Deposit::from_note_with(note, &NoteFormat::default())
     }
 
-    pub fn get_nullifier_hash(self, leaf_index: u128) -> String {
-        let poseidon = Poseidon::new();
+    pub fn from_note_with(note: &str, format: &NoteFormat) -> Result<Deposit, NoteError> {
+        let (network, payload) = note
+            .split_once("-juicer-")
+            .filter(|(network, _)| !network.is_empty())
+            .ok_or(NoteError::InvalidPrefix)?;
+        if network != format.network {
+            return Err(NoteError::WrongNetwork {
+                expected: format.network.clone(),
+                got: network.to_string(),
+            });
+        }
 
-        let nullifier = U256::from_str(&self.nullifier).unwrap();
+        let bytes: [u8; 32] = hex::decode(payload)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(NoteError::InvalidHex)?;
 
-        let secret = U256::from(1 as u16);
-        let leaf_i = U256::from(leaf_index);
+        Ok(Deposit::new(U256::from_le_bytes(bytes).to_string()))
+    }
 
-        let inputs = vec![
-            nullifier.to_le_bytes(),
-            secret.to_le_bytes(),
-            leaf_i.to_le_bytes(),
-        ];
+    pub fn get_commitment(self) -> String {
+        let nullifier = FieldElement::try_from(self.nullifier.as_str())
+            .unwrap()
+            .into();
 
-        let res = poseidon.hash(inputs).unwrap();
+        commitment(&nullifier, &U256::zero()).to_string()
+    }
 
-        U256::from_le_bytes(res).to_string()
+    pub fn get_nullifier_hash(self, leaf_index: u128) -> String {
+        let nullifier = FieldElement::try_from(self.nullifier.as_str())
+            .unwrap()
+            .into();
+
+        nullifier_hash(&nullifier, leaf_index).to_string()
     }
 
     pub fn commitment_as_array(self) -> [u8; 32] {
@@ -160,6 +553,7 @@ impl Deposit {
     }
 }
 
+#[cfg(not(feature = "domain-separation"))]
 #[test]
 fn test_generate_deposit() {
     let d = Deposit {
@@ -179,6 +573,319 @@ fn test_generate_deposit() {
     )
 }
 
+#[test]
+fn test_deposit_new_random() {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use ark_ff::FpParameters;
+
+    let modulus: [u8; 32] = <<Fr as PrimeField>::Params as FpParameters>::MODULUS
+        .to_bytes_le()
+        .try_into()
+        .unwrap();
+    let modulus = U256::from_le_bytes(modulus);
+
+    let mut rng = StdRng::seed_from_u64(0x4a55_4943);
+    let deposits: Vec<Deposit> = (0..1000).map(|_| Deposit::new_random(&mut rng)).collect();
+    for d in &deposits {
+        assert!(U256::from_str(&d.nullifier).unwrap() < modulus);
+    }
+    assert_ne!(deposits[0].nullifier, deposits[1].nullifier);
+
+    // same seed, same nullifier
+    let mut rng = StdRng::seed_from_u64(0x4a55_4943);
+    assert_eq!(
+        Deposit::new_random(&mut rng).nullifier,
+        deposits[0].nullifier
+    );
+}
+
+#[test]
+fn test_deposit_note() {
+    let d = Deposit::new(
+        "276277773929387392791096474084808108569850403587654342680891529007770412737".to_string(),
+    );
+
+    let note = d.to_note();
+    assert!(note.starts_with(NOTE_PREFIX));
+    assert_eq!(note.len(), NOTE_PREFIX.len() + 64);
+    assert_eq!(Deposit::from_note(&note).unwrap().nullifier, d.nullifier);
+
+    let d = Deposit::new("0".to_string());
+    assert_eq!(Deposit::from_note(&d.to_note()).unwrap().nullifier, "0");
+}
+
+#[test]
+fn test_deposit_note_network() {
+    let d = Deposit::new("42".to_string());
+    let osmosis = NoteFormat::new("osmo");
+
+    let note = d.to_note_with(&osmosis);
+    assert!(note.starts_with("osmo-juicer-"));
+    assert_eq!(
+        Deposit::from_note_with(&note, &osmosis).unwrap().nullifier,
+        d.nullifier
+    );
+
+    // a juno note in an osmosis pool, and the other way around
+    let err = Deposit::from_note_with(&d.to_note(), &osmosis).unwrap_err();
+    assert_eq!(
+        err,
+        NoteError::WrongNetwork {
+            expected: "osmo".to_string(),
+            got: "juno".to_string(),
+        }
+    );
+    assert_eq!(err.to_string(), "Note is for juno pools, expected osmo");
+    assert_eq!(
+        Deposit::from_note(&note).unwrap_err(),
+        NoteError::WrongNetwork {
+            expected: "juno".to_string(),
+            got: "osmo".to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_deposit_invalid_note() {
+    let note = Deposit::new("42".to_string()).to_note();
+
+    assert_eq!(
+        Deposit::from_note(&note.replace("juno-", "osmo-")).unwrap_err(),
+        NoteError::WrongNetwork {
+            expected: "juno".to_string(),
+            got: "osmo".to_string(),
+        }
+    );
+    assert_eq!(
+        Deposit::from_note(&note.replace("juno-", "")).unwrap_err(),
+        NoteError::InvalidPrefix
+    );
+    assert_eq!(
+        Deposit::from_note(&note[..note.len() - 2]).unwrap_err(),
+        NoteError::InvalidHex
+    );
+    assert_eq!(
+        Deposit::from_note(&format!("{}00", note)).unwrap_err(),
+        NoteError::InvalidHex
+    );
+    assert_eq!(
+        Deposit::from_note("juno-juicer-not hex at all").unwrap_err(),
+        NoteError::InvalidHex
+    );
+    assert_eq!(
+        Deposit::from_note("").unwrap_err(),
+        NoteError::InvalidPrefix
+    );
+}
+
+#[test]
+fn test_proof_unsupported_protocol() {
+    assert!(CircomProof::from(PROOF_JSON.to_string()).to_proof().is_ok());
+
+    let plonk = CircomProof::from(PROOF_JSON.replace("groth16", "plonk"));
+    assert_eq!(
+        plonk.to_proof().unwrap_err(),
+        ProofError::UnsupportedProtocol {
+            protocol: "plonk".to_string(),
+            curve: "bn128".to_string(),
+        }
+    );
+
+    let bls = CircomProof::from(PROOF_JSON.replace("bn128", "bls12381"));
+    assert_eq!(
+        bls.to_proof().unwrap_err(),
+        ProofError::UnsupportedProtocol {
+            protocol: "groth16".to_string(),
+            curve: "bls12381".to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_proof_point_not_on_curve() {
+    let proof = CircomProof::from(PROOF_JSON.to_string());
+
+    let mut off_curve = proof.clone();
+    off_curve.pi_a[1] = "1".to_string();
+    assert_eq!(
+        off_curve.to_proof().unwrap_err(),
+        ProofError::PointNotOnCurve {
+            point: "pi_a".to_string()
+        }
+    );
+
+    let mut off_curve = proof.clone();
+    off_curve.pi_b[1][0] = "1".to_string();
+    assert_eq!(
+        off_curve.to_proof().unwrap_err(),
+        ProofError::PointNotOnCurve {
+            point: "pi_b".to_string()
+        }
+    );
+
+    let mut off_curve = proof;
+    off_curve.pi_c[0] = "2".to_string();
+    assert_eq!(
+        off_curve.to_proof().unwrap_err(),
+        ProofError::PointNotOnCurve {
+            point: "pi_c".to_string()
+        }
+    );
+}
+
+#[test]
+fn test_proof_malformed_point() {
+    let proof = CircomProof::from(PROOF_JSON.to_string());
+    let malformed = |point: &str| {
+        Err(ProofError::MalformedPoint {
+            point: point.to_string(),
+        })
+    };
+
+    let mut short = proof.clone();
+    short.pi_a.truncate(1);
+    assert_eq!(short.to_proof(), malformed("pi_a"));
+
+    let mut short = proof.clone();
+    short.pi_b.truncate(1);
+    assert_eq!(short.to_proof(), malformed("pi_b"));
+    let mut short = proof.clone();
+    short.pi_b[1].truncate(1);
+    assert_eq!(short.to_proof(), malformed("pi_b"));
+
+    let mut short = proof.clone();
+    short.pi_c.clear();
+    assert_eq!(short.to_proof(), malformed("pi_c"));
+
+    for value in ["", "0x1", "-1", "one"] {
+        let mut non_numeric = proof.clone();
+        non_numeric.pi_c[1] = value.to_string();
+        assert_eq!(non_numeric.to_proof(), malformed("pi_c"));
+    }
+    let mut non_numeric = proof;
+    non_numeric.pi_b[0][1] = "1e3".to_string();
+    assert_eq!(non_numeric.to_proof(), malformed("pi_b"));
+}
+
+#[test]
+fn test_proof_flat() {
+    let proof = CircomProof::from(PROOF_JSON.to_string());
+
+    let flat = proof.to_flat();
+    assert_eq!(CircomProof::from_flat(&flat), Ok(proof.clone()));
+    assert_eq!(
+        CircomProof::from_flat(&flat[..7]),
+        Err(ProofError::InvalidFlatProof { len: 7 })
+    );
+
+    let input: ProofInput =
+        serde_json::from_str(&format!(r#"{{"circom":{}}}"#, PROOF_JSON)).unwrap();
+    assert_eq!(input, ProofInput::Circom(proof.clone()));
+    let flat_json = format!(r#"{{"flat":{}}}"#, serde_json::to_string(&flat).unwrap());
+    let input: ProofInput = serde_json::from_str(&flat_json).unwrap();
+    assert_eq!(input, ProofInput::Flat(flat));
+    assert_eq!(input.to_proof(), proof.to_proof());
+}
+
+#[test]
+fn test_encode_address() {
+    let juno = "juno14spgzl9ps5tyev32ny74fa6m0s9q9828v0vrga";
+    assert_eq!(
+        PublicSignals::encode_address(juno, "juno").unwrap(),
+        "9526846490934353717899961266123756195211556155320547954451400665347450669575"
+    );
+
+    // same payload, another chain
+    let osmo = "osmo14spgzl9ps5tyev32ny74fa6m0s9q9828jxugen";
+    assert_eq!(
+        PublicSignals::bech32_to_u256(osmo),
+        PublicSignals::bech32_to_u256(juno)
+    );
+    assert_eq!(PublicSignals::encode_address(osmo, "juno"), None);
+    assert!(PublicSignals::encode_address(osmo, "osmo").is_some());
+
+    assert_eq!(
+        PublicSignals::encode_address(&juno.to_uppercase(), "juno"),
+        None
+    );
+    assert_eq!(PublicSignals::encode_address("juno1garbage", "juno"), None);
+}
+
+#[test]
+fn test_address_to_field() {
+    use cosmwasm_std::testing::MockApi;
+
+    // canonical bytes of juno14spgzl9ps5tyev32ny74fa6m0s9q9828v0vrga
+    let canonical = hex::decode("ac02817ca185164cb22a993d54f75b7c0a029d47").unwrap();
+    let field = canonical_to_field(&canonical).unwrap();
+    assert_eq!(
+        field.to_string(),
+        "9526846490934353717899961266123756195211556155320547954451400665347450669575"
+    );
+    // the first 5-bit word is 0b10101, the top bits of 0xac
+    assert_eq!(field.to_be_bytes()[0], 0b10101);
+    assert_eq!(
+        field.to_string(),
+        PublicSignals::bech32_to_u256("juno14spgzl9ps5tyev32ny74fa6m0s9q9828v0vrga").unwrap()
+    );
+
+    assert_eq!(canonical_to_field(&canonical[1..]), None);
+    assert_eq!(canonical_to_field(&[0; 32]), None);
+
+    // the mock api canonicalizes to more than 20 bytes
+    assert!(address_to_field(&MockApi::default(), &Addr::unchecked("juno1recipient")).is_err());
+}
+
+#[test]
+fn test_public_signals_from_values() {
+    let recipient = "juno14spgzl9ps5tyev32ny74fa6m0s9q9828v0vrga".to_string();
+    let relayer = "juno1am5sw4geda8xfvmn4pkzruhv8ah0l3jx5hgchh".to_string();
+    let signals = |root: &str, nullifier_hash: &str, recipient: &str, relayer: &str| {
+        PublicSignals::from_values(
+            root.to_string(),
+            nullifier_hash.to_string(),
+            recipient.to_string(),
+            relayer.to_string(),
+            U128::from(3_u128),
+        )
+    };
+
+    let valid = signals("1", "2", &recipient, &relayer).unwrap();
+    assert_eq!(valid.0[..2], ["1".to_string(), "2".to_string()]);
+    assert_eq!(valid.0[4], "3");
+    assert_eq!(signals("1", "2", &recipient, "").unwrap().0[3], "0");
+    assert_eq!(signals("1", "2", &recipient, "0").unwrap().0[3], "0");
+
+    // one past the largest field element
+    let modulus = crate::bignum::FIELD_MODULUS.to_string();
+    assert_eq!(
+        signals(&modulus, "2", &recipient, &relayer),
+        Err(SignalError::InvalidRoot {
+            root: modulus.clone()
+        })
+    );
+    assert_eq!(
+        signals("1", "abc", &recipient, &relayer),
+        Err(SignalError::InvalidNullifierHash {
+            nullifier_hash: "abc".to_string()
+        })
+    );
+    assert_eq!(
+        signals("1", "2", "0", &relayer),
+        Err(SignalError::InvalidRecipient {
+            recipient: "0".to_string()
+        })
+    );
+    assert_eq!(
+        signals("1", "2", &recipient, "juno1garbage"),
+        Err(SignalError::InvalidRelayer {
+            relayer: "juno1garbage".to_string()
+        })
+    );
+}
+
 #[test]
 fn test_parse_juno_addr() {
     // 9526846490934353717899961266123756195211556155320547954451400665347450669575
@@ -215,3 +922,77 @@ fn test_parse_juno_addr() {
         assert_eq!(recipient, addr);
     }
 }
+
+#[test]
+fn test_withdraw_commitment_hash() {
+    let nullifier_hash =
+        "10174783302134252183272028399003089320089964203118066360883858790559353379370";
+    let recipient = "juno14spgzl9ps5tyev32ny74fa6m0s9q9828v0vrga";
+
+    let h1 = withdraw_commitment_hash(nullifier_hash, recipient, "42").unwrap();
+    let h2 = withdraw_commitment_hash(nullifier_hash, recipient, "42").unwrap();
+    let h3 = withdraw_commitment_hash(nullifier_hash, recipient, "43").unwrap();
+
+    assert_eq!(h1, h2);
+    assert_ne!(h1, h3);
+
+    // revealed by anyone, so malformed input is an error rather than a panic
+    assert_eq!(
+        withdraw_commitment_hash(nullifier_hash, recipient, "salt"),
+        Err(SignalError::InvalidSalt {
+            salt: "salt".to_string()
+        })
+    );
+    assert_eq!(
+        withdraw_commitment_hash("-1", recipient, "42"),
+        Err(SignalError::InvalidNullifierHash {
+            nullifier_hash: "-1".to_string()
+        })
+    );
+    for recipient in ["juno1garbage", "0", ""] {
+        assert_eq!(
+            withdraw_commitment_hash(nullifier_hash, recipient, "42"),
+            Err(SignalError::InvalidRecipient {
+                recipient: recipient.to_string()
+            })
+        );
+    }
+}
+
+#[test]
+fn test_split_commitment_hash() {
+    let a = "juno14spgzl9ps5tyev32ny74fa6m0s9q9828v0vrga".to_string();
+    let b = "juno1am5sw4geda8xfvmn4pkzruhv8ah0l3jx5hgchh".to_string();
+
+    let h1 = split_commitment_hash(&[
+        (a.clone(), U128::from(3_u128)),
+        (b.clone(), U128::from(7_u128)),
+    ])
+    .unwrap();
+    let h2 = split_commitment_hash(&[
+        (a.clone(), U128::from(3_u128)),
+        (b.clone(), U128::from(7_u128)),
+    ])
+    .unwrap();
+    let h3 = split_commitment_hash(&[
+        (b.clone(), U128::from(7_u128)),
+        (a.clone(), U128::from(3_u128)),
+    ])
+    .unwrap();
+    let h4 =
+        split_commitment_hash(&[(a.clone(), U128::from(4_u128)), (b, U128::from(6_u128))]).unwrap();
+
+    assert_eq!(h1, h2);
+    assert_ne!(h1, h3);
+    assert_ne!(h1, h4);
+
+    // it replaces the recipient signal only
+    let signals =
+        PublicSignals::from_values("1".into(), "2".into(), a, "0".into(), U128::zero()).unwrap();
+    let split = signals.clone().with_split(h1.clone());
+    assert_eq!(split.0[2], h1);
+    assert_eq!(
+        (&signals.0[..2], &signals.0[3..]),
+        (&split.0[..2], &split.0[3..])
+    );
+}
@@ -1,4 +1,14 @@
+pub use self::poseidon::Error as PoseidonError;
 pub use self::poseidon::Poseidon;
+pub use self::poseidon::PoseidonParams;
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    // Building the round constants and MDS matrices costs about ten hashes,
+    // share a single instance instead of calling `Poseidon::new()` per hash
+    pub static ref POSEIDON: Poseidon = Poseidon::new();
+}
 
 mod hasher {
     use ark_crypto_primitives::Error;
@@ -29,16 +39,20 @@ mod hasher {
 #[allow(clippy::all)]
 pub mod poseidon {
     use ark_bn254::Fr as Bn254Fr;
+    use ark_std::vec::Vec;
     use arkworks_setups::common::setup_params;
     use arkworks_setups::Curve;
     use serde::{Deserialize, Serialize};
 
     use cosmwasm_std::Uint256 as U256;
+    #[cfg(feature = "std")]
+    use schemars::JsonSchema;
 
     use super::hasher::ArkworksPoseidonHasherBn254;
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct Poseidon {
+        hasher_params_width_2_bytes: Vec<u8>,
         hasher_params_width_3_bytes: Vec<u8>,
         hasher_params_width_4_bytes: Vec<u8>,
         hasher_params_width_5_bytes: Vec<u8>,
@@ -51,48 +65,150 @@ pub mod poseidon {
         HashError,
         /// Invalid hash width
         InvalidHashInputWidth,
+        /// Round counts the constant tables can't serve
+        InvalidParams,
     }
 
     /// The Hash result type.
     pub type Result<T> = core::result::Result<T, Error>;
 
+    // Rounds of the x^5 permutation over `width` elements, i.e. `width - 1` inputs
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    #[cfg_attr(feature = "std", derive(JsonSchema))]
+    pub struct PoseidonParams {
+        pub width: u8,
+        pub full_rounds: u8,
+        pub partial_rounds: u8,
+    }
+
+    impl PoseidonParams {
+        // circomlib's rounds, the ones the constant tables were generated for
+        pub fn circomlib(width: u8) -> Result<Self> {
+            let partial_rounds = match width {
+                2 => 56,
+                3 => 57,
+                4 => 56,
+                5 => 60,
+                _ => return Err(Error::InvalidHashInputWidth),
+            };
+            Ok(Self {
+                width,
+                full_rounds: 8,
+                partial_rounds,
+            })
+        }
+
+        // Serialized hasher parameters. The circomlib constants of `width` are
+        // kept, so only as many rounds as they have keys for can be run.
+        fn to_bytes(&self) -> Result<Vec<u8>> {
+            Self::circomlib(self.width)?;
+            let mut params = setup_params::<Bn254Fr>(Curve::Bn254, 5, self.width);
+
+            let rounds = self.full_rounds as usize + self.partial_rounds as usize;
+            if self.full_rounds == 0
+                || self.full_rounds % 2 != 0
+                || rounds * self.width as usize > params.round_keys.len()
+            {
+                return Err(Error::InvalidParams);
+            }
+
+            params.full_rounds = self.full_rounds;
+            params.partial_rounds = self.partial_rounds;
+            Ok(params.to_bytes())
+        }
+    }
+
+    // The merkle tree's pair hash
+    impl Default for PoseidonParams {
+        fn default() -> Self {
+            Self::circomlib(3).unwrap()
+        }
+    }
+
     impl Poseidon {
         pub fn new() -> Self {
             Self {
+                hasher_params_width_2_bytes: setup_params::<Bn254Fr>(Curve::Bn254, 5, 2).to_bytes(),
                 hasher_params_width_3_bytes: setup_params::<Bn254Fr>(Curve::Bn254, 5, 3).to_bytes(),
                 hasher_params_width_4_bytes: setup_params::<Bn254Fr>(Curve::Bn254, 5, 4).to_bytes(),
                 hasher_params_width_5_bytes: setup_params::<Bn254Fr>(Curve::Bn254, 5, 5).to_bytes(),
             }
         }
 
+        // A hasher of only the widths in `params`, the others fail with
+        // `InvalidHashInputWidth`. Cheaper to build than `new` for a single width.
+        pub fn with_params(params: &[PoseidonParams]) -> Result<Self> {
+            let mut this = Self {
+                hasher_params_width_2_bytes: Vec::new(),
+                hasher_params_width_3_bytes: Vec::new(),
+                hasher_params_width_4_bytes: Vec::new(),
+                hasher_params_width_5_bytes: Vec::new(),
+            };
+            for params in params {
+                let bytes = params.to_bytes()?;
+                match params.width {
+                    2 => this.hasher_params_width_2_bytes = bytes,
+                    3 => this.hasher_params_width_3_bytes = bytes,
+                    4 => this.hasher_params_width_4_bytes = bytes,
+                    _ => this.hasher_params_width_5_bytes = bytes,
+                }
+            }
+            Ok(this)
+        }
+
         pub fn hash_as_u256(&self, inputs: Vec<[u8; 32]>) -> Result<U256> {
-            let res = self.hash(inputs)?;
+            let res = self.hash_as_bytes(inputs)?;
 
             Ok(U256::from_le_bytes(res))
         }
 
+        // Little-endian, the same layout as `U256::to_le_bytes`
+        pub fn hash_as_bytes(&self, inputs: Vec<[u8; 32]>) -> Result<[u8; 32]> {
+            self.hash(inputs)
+        }
+
+        // circomlib compatible poseidon of 1 to 4 field elements
+        pub fn hash_n(&self, inputs: &[U256]) -> Result<U256> {
+            let inputs = inputs.iter().map(|input| input.to_le_bytes()).collect();
+
+            self.hash_as_u256(inputs)
+        }
+
+        // Domain 0 is `hash_n` itself. Any other domain is absorbed as a leading
+        // element, so the same inputs hash differently per domain, at one input less.
+        pub fn hash_with_domain(&self, domain: u64, inputs: &[U256]) -> Result<U256> {
+            if domain == 0 {
+                return self.hash_n(inputs);
+            }
+
+            let mut tagged = Vec::with_capacity(inputs.len() + 1);
+            tagged.push(U256::from(domain));
+            tagged.extend_from_slice(inputs);
+            self.hash_n(&tagged)
+        }
+
         pub fn hash(&self, inputs: Vec<[u8; 32]>) -> Result<[u8; 32]> {
-            let num_inputs = inputs.len();
-            let mut packed_inputs = Vec::new();
-            for inp in inputs {
-                packed_inputs.extend_from_slice(&inp);
+            self.hash_packed(&inputs.concat(), inputs.len())
+        }
+
+        // `packed_inputs` holds `num_inputs` little-endian elements back to back
+        pub fn hash_packed(&self, packed_inputs: &[u8], num_inputs: usize) -> Result<[u8; 32]> {
+            if packed_inputs.len() != num_inputs * 32 {
+                return Err(Error::InvalidHashInputWidth);
             }
 
-            let hash_result = match num_inputs {
-                2 => ArkworksPoseidonHasherBn254::hash(
-                    &packed_inputs,
-                    &self.hasher_params_width_3_bytes,
-                ),
-                3 => ArkworksPoseidonHasherBn254::hash(
-                    &packed_inputs,
-                    &self.hasher_params_width_4_bytes,
-                ),
-                4 => ArkworksPoseidonHasherBn254::hash(
-                    &packed_inputs,
-                    &self.hasher_params_width_5_bytes,
-                ),
+            let params = match num_inputs {
+                1 => &self.hasher_params_width_2_bytes,
+                2 => &self.hasher_params_width_3_bytes,
+                3 => &self.hasher_params_width_4_bytes,
+                4 => &self.hasher_params_width_5_bytes,
                 _ => return Err(Error::InvalidHashInputWidth),
             };
+            // a width left out of `with_params`
+            if params.is_empty() {
+                return Err(Error::InvalidHashInputWidth);
+            }
+            let hash_result = ArkworksPoseidonHasherBn254::hash(packed_inputs, params);
 
             hash_result
                 .map(|h| {
@@ -130,4 +246,136 @@ mod tests {
         let n = U256::from_le_bytes(h);
         println!("{:?}", n);
     }
+
+    #[test]
+    fn test_poseidon_hash_n() {
+        let hasher = Poseidon::new();
+        let inputs: Vec<U256> = (1..=5_u32).map(U256::from).collect();
+
+        // circomlibjs `poseidon([1])`, `poseidon([1, 2])`, `poseidon([1, 2, 3])`
+        assert_eq!(
+            hasher.hash_n(&inputs[..1]).unwrap().to_string(),
+            "18586133768512220936620570745912940619677854269274689475585506675881198879027"
+        );
+        assert_eq!(
+            hasher.hash_n(&inputs[..2]).unwrap().to_string(),
+            "7853200120776062878684798364095072458815029376092732009249414926327459813530"
+        );
+        assert_eq!(
+            hasher.hash_n(&inputs[..3]).unwrap().to_string(),
+            "6542985608222806190361240322586112750744169038454362455181422643027100751666"
+        );
+
+        assert!(hasher.hash_n(&inputs[..4]).is_ok());
+        assert!(matches!(
+            hasher.hash_n(&inputs),
+            Err(PoseidonError::InvalidHashInputWidth)
+        ));
+        assert!(matches!(
+            hasher.hash_n(&[]),
+            Err(PoseidonError::InvalidHashInputWidth)
+        ));
+    }
+
+    #[test]
+    fn test_poseidon_hash_with_domain() {
+        let hasher = Poseidon::new();
+        let inputs = [U256::from(1_u16), U256::from(2_u16)];
+
+        assert_eq!(
+            hasher.hash_with_domain(0, &inputs).unwrap(),
+            hasher.hash_n(&inputs).unwrap()
+        );
+
+        // circomlibjs `poseidon([1, 1, 2])` and `poseidon([2, 1, 2])`
+        let one = hasher.hash_with_domain(1, &inputs).unwrap();
+        let two = hasher.hash_with_domain(2, &inputs).unwrap();
+        assert_eq!(
+            one.to_string(),
+            "6354665679016046132477078691702450238632755922703794747137561699023803289645"
+        );
+        assert_eq!(
+            two.to_string(),
+            "12448107141648110753339079111365879398049652284040593012870233782552794396784"
+        );
+        assert_ne!(one, hasher.hash_n(&inputs).unwrap());
+
+        // the tag takes one of the 4 inputs
+        let inputs: Vec<U256> = (1..=4_u32).map(U256::from).collect();
+        assert!(hasher.hash_with_domain(0, &inputs).is_ok());
+        assert!(matches!(
+            hasher.hash_with_domain(1, &inputs),
+            Err(PoseidonError::InvalidHashInputWidth)
+        ));
+    }
+
+    #[test]
+    fn test_poseidon_params() {
+        let inputs = [U256::from(1_u16), U256::from(2_u16)];
+        let default = Poseidon::with_params(&[PoseidonParams::default()]).unwrap();
+        assert_eq!(
+            default.hash_n(&inputs).unwrap(),
+            Poseidon::new().hash_n(&inputs).unwrap()
+        );
+        assert!(matches!(
+            default.hash_n(&inputs[..1]),
+            Err(PoseidonError::InvalidHashInputWidth)
+        ));
+        let inputs: Vec<U256> = (1..=4_u32).map(U256::from).collect();
+        for width in 2..=5_u8 {
+            let hasher =
+                Poseidon::with_params(&[PoseidonParams::circomlib(width).unwrap()]).unwrap();
+            let inputs = &inputs[..width as usize - 1];
+            assert_eq!(
+                hasher.hash_n(inputs).unwrap(),
+                Poseidon::new().hash_n(inputs).unwrap()
+            );
+        }
+        let inputs = [U256::from(1_u16), U256::from(2_u16)];
+
+        let fewer_rounds = PoseidonParams {
+            partial_rounds: 56,
+            ..PoseidonParams::default()
+        };
+        let custom = Poseidon::with_params(&[fewer_rounds]).unwrap();
+        assert_ne!(
+            custom.hash_n(&inputs).unwrap(),
+            default.hash_n(&inputs).unwrap()
+        );
+
+        // more rounds than round constants, odd full rounds, unknown width
+        for params in [
+            PoseidonParams {
+                partial_rounds: 58,
+                ..PoseidonParams::default()
+            },
+            PoseidonParams {
+                full_rounds: 7,
+                ..PoseidonParams::default()
+            },
+            PoseidonParams {
+                width: 6,
+                ..PoseidonParams::default()
+            },
+        ] {
+            assert!(Poseidon::with_params(&[params]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_as_bytes() {
+        let hasher = Poseidon::new();
+        let inputs = vec![
+            U256::from(1_u16).to_le_bytes(),
+            U256::from(2_u16).to_le_bytes(),
+        ];
+
+        let bytes = hasher.hash_as_bytes(inputs.clone()).unwrap();
+        let n = hasher.hash_as_u256(inputs).unwrap();
+        assert_eq!(bytes, n.to_le_bytes());
+        assert_eq!(
+            n.to_string(),
+            "7853200120776062878684798364095072458815029376092732009249414926327459813530"
+        );
+    }
 }
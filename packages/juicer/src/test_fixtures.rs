@@ -0,0 +1,4 @@
+// The bundled circuit's proof of a withdrawal, and its public signals
+pub const PROOF_JSON: &str = r#"{"pi_a":["6552461980235014125512485858574907252011786385287906273628562834862223432928","11860269414224780188951814911792416959087517485707388287639623625338071932162","1"],"pi_b":[["9681761101763472006554831866824970971123704940950423917193355053768804987946","5927707442728914367014416326038522894294769459332802500499248118399686183810"],["8311882933934792033033929758431827548593018661457980967793576235624041094075","9580190490647948836337327257463300132379241952918693313899161298407425234595"],["1","0"]],"pi_c":["14983953388384713833269817801465516661871057866090801236649846900102814228715","17793356028202487683602645872301205836685643881182882218786806260625920223574","1"],"protocol":"groth16","curve":"bn128"}"#;
+
+pub const PUBLIC_SIGNALS_JSON: &str = r#"["12102025269368723514786154929741041693298912567371778248333614260822400991070","11026638163601698230824004152583421065610480743788604283266155961805787602581","0","0","0"]"#;
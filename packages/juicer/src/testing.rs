@@ -0,0 +1,234 @@
+use std::convert::TryInto;
+use std::str::FromStr;
+
+use ark_bn254::{Fr, G1Affine};
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ff::{BigInteger, PrimeField};
+use cosmwasm_std::{Uint128 as U128, Uint256 as U256};
+
+use crate::merkle_tree::{MerklePath, MerkleTreeWithHistory};
+use crate::msg::{CircomProof, Deposit};
+
+// Circuit inputs of a withdrawal, public ones first
+#[derive(Clone, Debug, PartialEq)]
+pub struct WithdrawWitness {
+    pub root: U256,
+    pub nullifier_hash: String,
+    pub recipient: String,
+    pub relayer: String,
+    pub fee: U128,
+
+    pub nullifier: String,
+    pub path: MerklePath,
+}
+
+// Turns a witness into a groth16 proof, e.g. by running snarkjs
+pub trait Prover {
+    fn prove(&self, witness: &WithdrawWitness) -> Result<CircomProof, String>;
+}
+
+// Returns the same proof for every witness, for tests replaying a known withdrawal
+pub struct CannedProver(pub CircomProof);
+
+impl Prover for CannedProver {
+    fn prove(&self, _witness: &WithdrawWitness) -> Result<CircomProof, String> {
+        Ok(self.0.clone())
+    }
+}
+
+// Everything a `WithdrawMsg` needs but its pool id
+#[derive(Clone, Debug, PartialEq)]
+pub struct WithdrawProof {
+    pub proof: CircomProof,
+    pub root: String,
+    pub nullifier_hash: String,
+    pub recipient: String,
+    pub relayer: String,
+    pub fee: U128,
+}
+
+/// The proof builder error types.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProofBuilderError {
+    /// Returned if the tree doesn't keep its leaves or the note isn't at the leaf index
+    UnknownLeaf,
+    /// Returned if the prover failed
+    Prover(String),
+}
+
+impl std::fmt::Display for ProofBuilderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProofBuilderError::UnknownLeaf => write!(f, "Note isn't in the tree at this index"),
+            ProofBuilderError::Prover(err) => write!(f, "Prover failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ProofBuilderError {}
+
+// Assembles a withdrawal of `deposit` against the current root of a tree built
+// `with_leaves`. No relayer and no fee unless set.
+pub struct ProofBuilder<P: Prover> {
+    prover: P,
+    deposit: Deposit,
+    leaf_index: u32,
+    recipient: String,
+    relayer: String,
+    fee: U128,
+}
+
+impl<P: Prover> ProofBuilder<P> {
+    pub fn new(prover: P, deposit: Deposit, leaf_index: u32, recipient: &str) -> Self {
+        Self {
+            prover,
+            deposit,
+            leaf_index,
+            recipient: recipient.to_string(),
+            relayer: "0".to_string(),
+            fee: U128::zero(),
+        }
+    }
+
+    pub fn relayer(mut self, relayer: &str, fee: U128) -> Self {
+        self.relayer = relayer.to_string();
+        self.fee = fee;
+        self
+    }
+
+    pub fn witness(
+        &self,
+        tree: &MerkleTreeWithHistory,
+    ) -> Result<WithdrawWitness, ProofBuilderError> {
+        let path = tree
+            .get_path(self.leaf_index)
+            .ok_or(ProofBuilderError::UnknownLeaf)?;
+        let commitment = U256::from_str(&self.deposit.clone().get_commitment()).unwrap();
+        if !tree.verify_merkle_proof(&commitment, &path) {
+            return Err(ProofBuilderError::UnknownLeaf);
+        }
+
+        Ok(WithdrawWitness {
+            root: tree.get_last_root(),
+            nullifier_hash: self
+                .deposit
+                .clone()
+                .get_nullifier_hash(self.leaf_index as u128),
+            recipient: self.recipient.clone(),
+            relayer: self.relayer.clone(),
+            fee: self.fee,
+            nullifier: self.deposit.nullifier.clone(),
+            path,
+        })
+    }
+
+    pub fn build(&self, tree: &MerkleTreeWithHistory) -> Result<WithdrawProof, ProofBuilderError> {
+        let witness = self.witness(tree)?;
+        let proof = self
+            .prover
+            .prove(&witness)
+            .map_err(ProofBuilderError::Prover)?;
+
+        Ok(WithdrawProof {
+            proof,
+            root: witness.root.to_string(),
+            nullifier_hash: witness.nullifier_hash,
+            recipient: witness.recipient,
+            relayer: witness.relayer,
+            fee: witness.fee,
+        })
+    }
+}
+
+// `scalar` times the G1 generator in circom's affine form, to build degenerate
+// verification keys and proofs that bind a chosen public input in tests
+pub fn g1_multiple(scalar: u128) -> Vec<String> {
+    let point = G1Affine::prime_subgroup_generator()
+        .mul(Fr::from(scalar))
+        .into_affine();
+    if point.infinity {
+        return vec!["0".to_string(), "1".to_string(), "0".to_string()];
+    }
+    let to_string = |repr: <ark_bn254::Fq as PrimeField>::BigInt| {
+        U256::from_le_bytes(repr.to_bytes_le().try_into().unwrap()).to_string()
+    };
+
+    vec![
+        to_string(point.x.into_repr()),
+        to_string(point.y.into_repr()),
+        "1".to_string(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    // Records the witness it was asked to prove
+    struct RecordingProver(RefCell<Option<WithdrawWitness>>);
+
+    impl Prover for RecordingProver {
+        fn prove(&self, witness: &WithdrawWitness) -> Result<CircomProof, String> {
+            self.0.replace(Some(witness.clone()));
+            Err("no circuit".to_string())
+        }
+    }
+
+    #[test]
+    fn test_proof_builder_witness() {
+        let deposit = Deposit::new("42".to_string());
+        let mut tree = MerkleTreeWithHistory::new(4).with_leaves();
+        tree.insert(&U256::from(1_u8)).unwrap();
+        tree.insert(&U256::from_str(&deposit.clone().get_commitment()).unwrap())
+            .unwrap();
+
+        let prover = RecordingProver(RefCell::new(None));
+        let builder = ProofBuilder::new(prover, deposit.clone(), 1, "recipient")
+            .relayer("relayer", U128::from(2_u128));
+        assert_eq!(
+            builder.build(&tree),
+            Err(ProofBuilderError::Prover("no circuit".to_string()))
+        );
+
+        let witness = builder.prover.0.borrow().clone().unwrap();
+        assert_eq!(witness, builder.witness(&tree).unwrap());
+        assert_eq!(witness.root, tree.get_last_root());
+        assert_eq!(witness.nullifier_hash, deposit.get_nullifier_hash(1));
+        assert_eq!(witness.path.leaf_index(), 1);
+        assert_eq!(witness.relayer, "relayer");
+        assert_eq!(witness.fee, U128::from(2_u128));
+
+        // wrong index, or a tree without its leaves
+        let builder = ProofBuilder::new(
+            CannedProver(dummy_proof()),
+            Deposit::new("42".to_string()),
+            0,
+            "recipient",
+        );
+        assert_eq!(builder.build(&tree), Err(ProofBuilderError::UnknownLeaf));
+        let mut tree = MerkleTreeWithHistory::new(4);
+        tree.insert(&U256::from(1_u8)).unwrap();
+        assert_eq!(builder.build(&tree), Err(ProofBuilderError::UnknownLeaf));
+    }
+
+    #[test]
+    fn test_g1_multiple() {
+        assert_eq!(g1_multiple(0), vec!["0", "1", "0"]);
+        assert_eq!(g1_multiple(1), vec!["1", "2", "1"]);
+
+        assert_eq!(
+            g1_multiple(2),
+            vec![
+                "1368015179489954701390400359078579693043519447331113978918064868415326638035",
+                "9918110051302171585080402603319702774565515993150576347155970296011118125764",
+                "1"
+            ]
+        );
+    }
+
+    fn dummy_proof() -> CircomProof {
+        CircomProof::from_flat(&vec!["1".to_string(); 8]).unwrap()
+    }
+}
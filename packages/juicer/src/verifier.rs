@@ -2,33 +2,156 @@ use ark_groth16::{prepare_verifying_key, verify_proof, Proof, VerifyingKey};
 use serde::{Deserialize, Serialize};
 
 use ark_bn254::{Bn254, Fq, Fq2, Fr, G1Affine, G1Projective, G2Affine, G2Projective};
+use ark_ff::{BigInteger, PrimeField};
+use cosmwasm_std::Uint256 as U256;
 use schemars::JsonSchema;
+use std::convert::{TryFrom, TryInto};
 use std::str::FromStr;
 
+use crate::bignum::is_in_field;
+
+// Stored as the key's JSON, which is parsed and checked once when it's loaded
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "StoredVerifier", into = "StoredVerifier")]
 pub struct Verifier {
     vk_json: String,
+    vk: VerifyingKey<Bn254>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredVerifier {
+    vk_json: String,
+}
+
+impl TryFrom<StoredVerifier> for Verifier {
+    type Error = VerifierError;
+
+    fn try_from(stored: StoredVerifier) -> Result<Self, VerifierError> {
+        let vk: VerifyingKeyJson =
+            serde_json::from_str(&stored.vk_json).map_err(|_| VerifierError::InvalidKey)?;
+
+        Ok(Self {
+            vk: vk.to_verifying_key()?,
+            vk_json: stored.vk_json,
+        })
+    }
+}
+
+impl From<Verifier> for StoredVerifier {
+    fn from(verifier: Verifier) -> Self {
+        Self {
+            vk_json: verifier.vk_json,
+        }
+    }
 }
 
 impl Verifier {
     pub fn new() -> Self {
         let vk_json = include_str!("../../../circuits/build/verification_key.json");
 
-        Self {
+        Self::try_from(StoredVerifier {
             vk_json: vk_json.to_string(),
-        }
+        })
+        .expect("the bundled verification key is valid")
+    }
+
+    // Verifier for a snarkjs `verification_key.json`, rejecting a malformed key
+    // up front rather than on the first withdrawal
+    pub fn from_vk(vk: VerifyingKeyJson) -> Result<Self, VerifierError> {
+        Ok(Self {
+            vk: vk.to_verifying_key()?,
+            vk_json: serde_json::to_string(&vk).map_err(|_| VerifierError::InvalidKey)?,
+        })
+    }
+
+    // The key in snarkjs `verification_key.json` shape
+    pub fn vk_json(&self) -> &str {
+        &self.vk_json
     }
 
-    pub fn verify_proof(self, proof: Proof<Bn254>, inputs: &[Fr]) -> bool {
-        let vk_json: VerifyingKeyJson = serde_json::from_str(&self.vk_json).unwrap();
+    // Number of public inputs the key expects, one IC point per input plus a constant
+    pub fn public_inputs_count(&self) -> usize {
+        self.vk.gamma_abc_g1.len() - 1
+    }
+
+    // Ok once the pairing check passes, every failure is reported as an error
+    pub fn verify_proof(&self, proof: Proof<Bn254>, inputs: &[Fr]) -> Result<(), VerifierError> {
+        let inputs: Vec<U256> = inputs
+            .iter()
+            .map(|input| U256::from_le_bytes(input.into_repr().to_bytes_le().try_into().unwrap()))
+            .collect();
+
+        self.verify_with_inputs(proof, &inputs)
+    }
+
+    // Any circuit's public inputs, in the order of the key's IC points. Inputs
+    // aren't reduced, one outside the scalar field is an error.
+    pub fn verify_with_inputs(
+        &self,
+        proof: Proof<Bn254>,
+        inputs: &[U256],
+    ) -> Result<(), VerifierError> {
+        let expected = self.public_inputs_count();
+        if inputs.len() != expected {
+            return Err(VerifierError::InputCountMismatch {
+                expected,
+                got: inputs.len(),
+            });
+        }
+        if !(proof.a.is_on_curve() && proof.b.is_on_curve() && proof.c.is_on_curve()) {
+            return Err(VerifierError::MalformedProof);
+        }
+        if let Some(index) = inputs.iter().position(|input| !is_in_field(input)) {
+            return Err(VerifierError::InputOutOfField { index });
+        }
+        let inputs: Vec<Fr> = inputs
+            .iter()
+            .map(|input| Fr::from_le_bytes_mod_order(&input.to_le_bytes()))
+            .collect();
+
+        let pvk = prepare_verifying_key(&self.vk);
+
+        match verify_proof(&pvk, &proof, &inputs) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(VerifierError::PairingCheckFailed),
+            Err(_) => Err(VerifierError::MalformedProof),
+        }
+    }
+}
 
-        let vk = vk_json.to_verifying_key();
-        let pvk = prepare_verifying_key(&vk);
+/// The proof verification error types.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifierError {
+    /// Returned if a proof point can't be used by the pairing
+    MalformedProof,
+    /// Returned if the number of public inputs doesn't match the verifying key
+    InputCountMismatch { expected: usize, got: usize },
+    /// Returned if a public input isn't a BN254 scalar field element
+    InputOutOfField { index: usize },
+    /// Returned if the proof doesn't verify against the public inputs
+    PairingCheckFailed,
+    /// Returned if the verifying key is malformed or has points off the curve
+    InvalidKey,
+}
 
-        verify_proof(&pvk, &proof, &inputs).unwrap()
+impl std::fmt::Display for VerifierError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifierError::MalformedProof => write!(f, "Malformed proof"),
+            VerifierError::InputCountMismatch { expected, got } => {
+                write!(f, "Expected {} public inputs, got {}", expected, got)
+            }
+            VerifierError::InputOutOfField { index } => {
+                write!(f, "Public input {} is outside the scalar field", index)
+            }
+            VerifierError::PairingCheckFailed => write!(f, "Pairing check failed"),
+            VerifierError::InvalidKey => write!(f, "Invalid verification key"),
+        }
     }
 }
 
+impl std::error::Error for VerifierError {}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 pub struct VerifyingKeyJson {
     #[serde(rename = "IC")]
@@ -46,108 +169,209 @@ pub struct VerifyingKeyJson {
 }
 
 impl VerifyingKeyJson {
-    pub fn to_verifying_key(self) -> VerifyingKey<Bn254> {
-        let alpha_g1 = G1Affine::from(G1Projective::new(
-            str_to_fq(&self.vk_alpha_1[0]),
-            str_to_fq(&self.vk_alpha_1[1]),
-            str_to_fq(&self.vk_alpha_1[2]),
-        ));
-        let beta_g2 = G2Affine::from(G2Projective::new(
-            // x
-            Fq2::new(
-                str_to_fq(&self.vk_beta_2[0][0]),
-                str_to_fq(&self.vk_beta_2[0][1]),
-            ),
-            // y
-            Fq2::new(
-                str_to_fq(&self.vk_beta_2[1][0]),
-                str_to_fq(&self.vk_beta_2[1][1]),
-            ),
-            // z,
-            Fq2::new(
-                str_to_fq(&self.vk_beta_2[2][0]),
-                str_to_fq(&self.vk_beta_2[2][1]),
-            ),
-        ));
-
-        let gamma_g2 = G2Affine::from(G2Projective::new(
-            // x
-            Fq2::new(
-                str_to_fq(&self.vk_gamma_2[0][0]),
-                str_to_fq(&self.vk_gamma_2[0][1]),
-            ),
-            // y
-            Fq2::new(
-                str_to_fq(&self.vk_gamma_2[1][0]),
-                str_to_fq(&self.vk_gamma_2[1][1]),
-            ),
-            // z,
-            Fq2::new(
-                str_to_fq(&self.vk_gamma_2[2][0]),
-                str_to_fq(&self.vk_gamma_2[2][1]),
-            ),
-        ));
-
-        let delta_g2 = G2Affine::from(G2Projective::new(
-            // x
-            Fq2::new(
-                str_to_fq(&self.vk_delta_2[0][0]),
-                str_to_fq(&self.vk_delta_2[0][1]),
-            ),
-            // y
-            Fq2::new(
-                str_to_fq(&self.vk_delta_2[1][0]),
-                str_to_fq(&self.vk_delta_2[1][1]),
-            ),
-            // z,
-            Fq2::new(
-                str_to_fq(&self.vk_delta_2[2][0]),
-                str_to_fq(&self.vk_delta_2[2][1]),
-            ),
-        ));
-
-        let gamma_abc_g1: Vec<G1Affine> = self
+    // Points are projective (x, y, z) coordinates, G2 ones over Fq2 pairs.
+    // Missing coordinates, non-numeric ones and points off the curve are rejected.
+    pub fn to_verifying_key(&self) -> Result<VerifyingKey<Bn254>, VerifierError> {
+        let gamma_abc_g1 = self
             .ic
             .iter()
-            .map(|coords| {
-                G1Affine::from(G1Projective::new(
-                    str_to_fq(&coords[0]),
-                    str_to_fq(&coords[1]),
-                    str_to_fq(&coords[2]),
-                ))
-            })
-            .collect();
+            .map(|point| g1_point(point))
+            .collect::<Result<Vec<_>, _>>()?;
+        if gamma_abc_g1.is_empty() {
+            return Err(VerifierError::InvalidKey);
+        }
+
+        Ok(VerifyingKey::<Bn254> {
+            alpha_g1: g1_point(&self.vk_alpha_1)?,
+            beta_g2: g2_point(&self.vk_beta_2)?,
+            gamma_g2: g2_point(&self.vk_gamma_2)?,
+            delta_g2: g2_point(&self.vk_delta_2)?,
+            gamma_abc_g1,
+        })
+    }
+}
+
+fn g1_point(coords: &[String]) -> Result<G1Affine, VerifierError> {
+    match coords {
+        [x, y, z] => {
+            let point = G1Affine::from(G1Projective::new(
+                str_to_fq(x)?,
+                str_to_fq(y)?,
+                str_to_fq(z)?,
+            ));
+            match point.is_on_curve() {
+                true => Ok(point),
+                false => Err(VerifierError::InvalidKey),
+            }
+        }
+        _ => Err(VerifierError::InvalidKey),
+    }
+}
 
-        VerifyingKey::<Bn254> {
-            alpha_g1: alpha_g1,
-            beta_g2: beta_g2,
-            gamma_g2: gamma_g2,
-            delta_g2: delta_g2,
-            gamma_abc_g1: gamma_abc_g1,
+fn g2_point(coords: &[Vec<String>]) -> Result<G2Affine, VerifierError> {
+    let fq2 = |coord: &Vec<String>| match coord.as_slice() {
+        [c0, c1] => Ok(Fq2::new(str_to_fq(c0)?, str_to_fq(c1)?)),
+        _ => Err(VerifierError::InvalidKey),
+    };
+    match coords {
+        [x, y, z] => {
+            let point = G2Affine::from(G2Projective::new(fq2(x)?, fq2(y)?, fq2(z)?));
+            match point.is_on_curve() {
+                true => Ok(point),
+                false => Err(VerifierError::InvalidKey),
+            }
         }
+        _ => Err(VerifierError::InvalidKey),
     }
 }
 
-pub fn str_to_fq(s: &str) -> Fq {
-    Fq::from_str(s).unwrap()
+pub fn str_to_fq(s: &str) -> Result<Fq, VerifierError> {
+    Fq::from_str(s).map_err(|_| VerifierError::InvalidKey)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::msg::{CircomProof, PublicSignals};
+    use crate::test_fixtures::{PROOF_JSON, PUBLIC_SIGNALS_JSON};
 
     #[test]
     fn test_verifier() {
         let v = Verifier::new();
 
-        let proof = CircomProof::from(r#"{"pi_a":["6552461980235014125512485858574907252011786385287906273628562834862223432928","11860269414224780188951814911792416959087517485707388287639623625338071932162","1"],"pi_b":[["9681761101763472006554831866824970971123704940950423917193355053768804987946","5927707442728914367014416326038522894294769459332802500499248118399686183810"],["8311882933934792033033929758431827548593018661457980967793576235624041094075","9580190490647948836337327257463300132379241952918693313899161298407425234595"],["1","0"]],"pi_c":["14983953388384713833269817801465516661871057866090801236649846900102814228715","17793356028202487683602645872301205836685643881182882218786806260625920223574","1"],"protocol":"groth16","curve":"bn128"}"#.to_string())
-            .to_proof();
-        let public_signals = PublicSignals::from_json(r#"["12102025269368723514786154929741041693298912567371778248333614260822400991070","11026638163601698230824004152583421065610480743788604283266155961805787602581","0","0","0"]"#.to_string());
+        let proof = CircomProof::from(PROOF_JSON.to_string())
+            .to_proof()
+            .unwrap();
+        let public_signals = PublicSignals::from_json(PUBLIC_SIGNALS_JSON.to_string());
 
         let res = v.verify_proof(proof, &public_signals.get());
 
-        println!("res: {}", res);
-        assert!(res);
+        println!("res: {:?}", res);
+        assert_eq!(res, Ok(()));
+    }
+
+    #[test]
+    fn test_verifier_from_vk() {
+        let vk: VerifyingKeyJson = serde_json::from_str(include_str!(
+            "../../../circuits/build/verification_key.json"
+        ))
+        .unwrap();
+        let v = Verifier::from_vk(vk).unwrap();
+
+        let proof = CircomProof::from(PROOF_JSON.to_string())
+            .to_proof()
+            .unwrap();
+        let public_signals = PublicSignals::from_json(PUBLIC_SIGNALS_JSON.to_string());
+
+        assert_eq!(v.public_inputs_count(), 5);
+        assert_eq!(
+            v.verify_proof(proof.clone(), &public_signals.clone().get()),
+            Ok(())
+        );
+
+        // stored as the key JSON, parsed again when loaded
+        let stored = serde_json::to_string(&v).unwrap();
+        let loaded: Verifier = serde_json::from_str(&stored).unwrap();
+        assert_eq!(loaded.vk_json(), v.vk_json());
+        assert_eq!(loaded.verify_proof(proof, &public_signals.get()), Ok(()));
+    }
+
+    #[test]
+    fn test_verifier_invalid_key() {
+        let vk: VerifyingKeyJson = serde_json::from_str(include_str!(
+            "../../../circuits/build/verification_key.json"
+        ))
+        .unwrap();
+
+        let mut short = vk.clone();
+        short.vk_alpha_1.pop();
+        let mut not_a_number = vk.clone();
+        not_a_number.vk_beta_2[0][1] = "beta".to_string();
+        let mut off_curve = vk.clone();
+        off_curve.ic[1][0] = "1".to_string();
+        let mut no_ic = vk;
+        no_ic.ic.clear();
+
+        for vk in [short, not_a_number, off_curve, no_ic] {
+            assert_eq!(
+                Verifier::from_vk(vk).unwrap_err(),
+                VerifierError::InvalidKey
+            );
+        }
+        let stored = r#"{"vk_json":"{}"}"#;
+        assert!(serde_json::from_str::<Verifier>(stored).is_err());
+    }
+
+    #[test]
+    fn test_verifier_errors() {
+        let proof = CircomProof::from(PROOF_JSON.to_string());
+        let mut inputs = PublicSignals::from_json(PUBLIC_SIGNALS_JSON.to_string()).get();
+
+        // a valid proof for other inputs
+        let mut other_inputs = inputs.clone();
+        other_inputs[4] = Fr::from(1_u64);
+        assert_eq!(
+            Verifier::new().verify_proof(proof.clone().to_proof().unwrap(), &other_inputs),
+            Err(VerifierError::PairingCheckFailed)
+        );
+
+        // corrupted `a.x`, no longer on the curve, built around `to_proof` checks
+        let mut corrupted = proof.clone().to_proof().unwrap();
+        corrupted.a = G1Affine::new(Fq::from(1_u64), corrupted.a.y, false);
+        assert_eq!(
+            Verifier::new().verify_proof(corrupted, &inputs),
+            Err(VerifierError::MalformedProof)
+        );
+
+        inputs.pop();
+        assert_eq!(
+            Verifier::new().verify_proof(proof.clone().to_proof().unwrap(), &inputs),
+            Err(VerifierError::InputCountMismatch {
+                expected: 5,
+                got: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_verify_with_inputs() {
+        let proof = CircomProof::from(PROOF_JSON.to_string())
+            .to_proof()
+            .unwrap();
+        let mut inputs = vec![
+            U256::from_str(
+                "12102025269368723514786154929741041693298912567371778248333614260822400991070",
+            )
+            .unwrap(),
+            U256::from_str(
+                "11026638163601698230824004152583421065610480743788604283266155961805787602581",
+            )
+            .unwrap(),
+            U256::zero(),
+            U256::zero(),
+            U256::zero(),
+        ];
+
+        let v = Verifier::new();
+        assert_eq!(v.verify_with_inputs(proof.clone(), &inputs), Ok(()));
+
+        inputs[4] = U256::from(1_u8);
+        assert_eq!(
+            v.verify_with_inputs(proof.clone(), &inputs),
+            Err(VerifierError::PairingCheckFailed)
+        );
+
+        // not reduced into the zero the proof was made for
+        inputs[4] = crate::bignum::FIELD_MODULUS;
+        assert_eq!(
+            v.verify_with_inputs(proof, &inputs),
+            Err(VerifierError::InputOutOfField { index: 4 })
+        );
+    }
+
+    #[test]
+    fn test_public_inputs_count() {
+        let v = Verifier::new();
+        assert_eq!(v.public_inputs_count(), 5);
     }
 }